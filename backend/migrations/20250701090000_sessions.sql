@@ -0,0 +1,19 @@
+-- Server-side sessions, referenced by the `jti` claim of every issued JWT
+
+CREATE TABLE IF NOT EXISTS sessions (
+    id VARCHAR(36) PRIMARY KEY DEFAULT (
+        lower(
+               hex( randomblob(4)) || '-'
+            || hex( randomblob(2)) || '-'
+            || '4' || substr( hex( randomblob(2)), 2) || '-'
+            || substr('AB89', 1 + (abs(random()) % 4) , 1) 
+            || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))
+            )
+        ),
+    user_id VARCHAR(36) NOT NULL,
+    created_at DATETIME DEFAULT (datetime('now')),
+    revoked_at DATETIME,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX sessions_user_id ON sessions(user_id);
@@ -0,0 +1,12 @@
+-- Refresh tokens are rotated on every use; a reused token revokes its whole session
+
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    token_hash CHARACTER(64) PRIMARY KEY,
+    session_id VARCHAR(36) NOT NULL,
+    created_at DATETIME DEFAULT (datetime('now')),
+    expires_at DATETIME NOT NULL,
+    used_at DATETIME,
+    FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE
+);
+
+CREATE INDEX refresh_tokens_session_id ON refresh_tokens(session_id);
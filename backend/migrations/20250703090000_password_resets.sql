@@ -0,0 +1,10 @@
+-- Single-use password reset tokens, only the hash of the mailed token is stored
+
+CREATE TABLE IF NOT EXISTS password_resets (
+    token_hash CHARACTER(64) PRIMARY KEY,
+    user_id VARCHAR(36) NOT NULL,
+    created_at DATETIME DEFAULT (datetime('now')),
+    expires_at DATETIME NOT NULL,
+    used_at DATETIME,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,19 @@
+-- Failed attempt counters (only used with LOGIN_THROTTLE_STORE=sqlite) and lockout audit trail
+
+CREATE TABLE IF NOT EXISTS login_attempts (
+    key TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    last_failure INTEGER NOT NULL,
+    locked_until INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS account_lockouts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email VARCHAR(50) NOT NULL,
+    ip TEXT,
+    failures INTEGER NOT NULL,
+    locked_until DATETIME NOT NULL,
+    created_at DATETIME DEFAULT (datetime('now'))
+);
+
+CREATE INDEX account_lockouts_email ON account_lockouts(email);
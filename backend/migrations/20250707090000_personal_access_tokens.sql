@@ -0,0 +1,24 @@
+-- Named, revocable tokens for scripts, sent as `Authorization: Bearer pat_...`
+
+CREATE TABLE IF NOT EXISTS personal_access_tokens (
+    id VARCHAR(36) PRIMARY KEY DEFAULT (
+        lower(
+               hex( randomblob(4)) || '-'
+            || hex( randomblob(2)) || '-'
+            || '4' || substr( hex( randomblob(2)), 2) || '-'
+            || substr('AB89', 1 + (abs(random()) % 4) , 1) 
+            || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))
+            )
+        ),
+    user_id VARCHAR(36) NOT NULL,
+    name VARCHAR(50) NOT NULL,
+    token_hash CHARACTER(64) NOT NULL UNIQUE,
+    scope VARCHAR(10) NOT NULL CHECK (scope IN ('read', 'write')),
+    created_at DATETIME DEFAULT (datetime('now')),
+    expires_at DATETIME,
+    last_used_at DATETIME,
+    revoked_at DATETIME,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX personal_access_tokens_user_id ON personal_access_tokens(user_id);
@@ -0,0 +1,4 @@
+-- Instance administrators and disabled accounts
+
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN disabled_at DATETIME;
@@ -0,0 +1,7 @@
+-- Device details per session, shown in the session list
+
+ALTER TABLE sessions ADD COLUMN user_agent TEXT;
+ALTER TABLE sessions ADD COLUMN ip VARCHAR(45);
+ALTER TABLE sessions ADD COLUMN last_seen_at DATETIME;
+
+UPDATE sessions SET last_seen_at = created_at;
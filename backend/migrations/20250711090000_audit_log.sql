@@ -0,0 +1,20 @@
+-- Append-only trail of security relevant actions. Ids are not foreign keys, so entries
+-- outlive deleted users and canvases.
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    action VARCHAR(32) NOT NULL,
+    actor_id TEXT,
+    -- User id, or the submitted email for failed logins
+    target TEXT,
+    canvas_id TEXT,
+    ip VARCHAR(45),
+    -- JSON values
+    before TEXT,
+    after TEXT,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX audit_log_canvas ON audit_log(canvas_id, id);
+CREATE INDEX audit_log_actor ON audit_log(actor_id, id);
+CREATE INDEX audit_log_target ON audit_log(target, id);
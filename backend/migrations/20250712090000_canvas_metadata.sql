@@ -0,0 +1,13 @@
+-- Title, description and timestamps shown in the canvas list
+
+ALTER TABLE canvas ADD COLUMN title VARCHAR(100) NOT NULL DEFAULT '';
+ALTER TABLE canvas ADD COLUMN description TEXT NOT NULL DEFAULT '';
+ALTER TABLE canvas ADD COLUMN created_at DATETIME;
+-- Bumped on every persisted drawing event and metadata change
+ALTER TABLE canvas ADD COLUMN updated_at DATETIME;
+ALTER TABLE canvas ADD COLUMN created_by VARCHAR(36) REFERENCES users(id) ON DELETE SET NULL;
+
+-- The creator of existing canvases is unknown, their first owner is the best guess
+UPDATE canvas SET created_by = (
+    SELECT user_id FROM user_canvas WHERE canvas_id = canvas.id AND right = 'O' ORDER BY rowid LIMIT 1
+);
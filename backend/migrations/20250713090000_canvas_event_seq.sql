@@ -0,0 +1,22 @@
+-- Stable sequence numbers to page through the history, plus type and author of each event.
+-- SQLite can't add a primary key to an existing table, so the table is rebuilt.
+
+CREATE TABLE canvas_events_new (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    canvas_id VARCHAR(36),
+    events TEXT,
+    event_type VARCHAR(32),
+    author_id VARCHAR(36) REFERENCES users(id) ON DELETE SET NULL,
+    created_at DATETIME DEFAULT (datetime('now')),
+    FOREIGN KEY (canvas_id) REFERENCES canvas(id) ON DELETE CASCADE
+);
+
+-- Author and time of existing events are unknown
+INSERT INTO canvas_events_new (canvas_id, events, event_type, created_at)
+SELECT canvas_id, events, CASE WHEN json_valid(events) THEN json_extract(events, '$.type') END, NULL
+FROM canvas_events ORDER BY rowid;
+
+DROP TABLE canvas_events;
+ALTER TABLE canvas_events_new RENAME TO canvas_events;
+
+CREATE INDEX canvas_events_id ON canvas_events(canvas_id, seq);
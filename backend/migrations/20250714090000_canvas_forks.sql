@@ -0,0 +1,4 @@
+-- Provenance of forked canvases: the source canvas and the last event copied from it
+
+ALTER TABLE canvas ADD COLUMN forked_from VARCHAR(36) REFERENCES canvas(id) ON DELETE SET NULL;
+ALTER TABLE canvas ADD COLUMN forked_at_seq INTEGER;
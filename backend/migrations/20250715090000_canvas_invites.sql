@@ -0,0 +1,27 @@
+-- Links granting a right on a canvas to whoever accepts them, until they expire or run out
+
+CREATE TABLE IF NOT EXISTS canvas_invites (
+    id VARCHAR(36) PRIMARY KEY DEFAULT (
+        lower(
+               hex( randomblob(4)) || '-'
+            || hex( randomblob(2)) || '-'
+            || '4' || substr( hex( randomblob(2)), 2) || '-'
+            || substr('AB89', 1 + (abs(random()) % 4) , 1) 
+            || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))
+            )
+        ),
+    canvas_id VARCHAR(36) NOT NULL,
+    token_hash CHARACTER(64) NOT NULL UNIQUE,
+    right VARCHAR(1) NOT NULL CHECK (right IN ('R', 'W', 'V', 'M', 'O')),
+    created_by VARCHAR(36),
+    created_at DATETIME DEFAULT (datetime('now')),
+    expires_at DATETIME NOT NULL,
+    -- Unlimited when NULL
+    max_uses INTEGER,
+    uses INTEGER NOT NULL DEFAULT 0,
+    revoked_at DATETIME,
+    FOREIGN KEY (canvas_id) REFERENCES canvas(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX canvas_invites_canvas_id ON canvas_invites(canvas_id);
@@ -0,0 +1,3 @@
+-- Public canvases can be watched by anyone, including visitors without an account
+
+ALTER TABLE canvas ADD COLUMN public BOOLEAN NOT NULL DEFAULT FALSE;
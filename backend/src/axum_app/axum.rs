@@ -1,18 +1,34 @@
-use axum::{Extension, http::Method};
+use axum::{
+    Extension,
+    http::{HeaderName, HeaderValue, Method},
+};
 use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
-use std::{env, sync::Arc};
+use std::{env, net::SocketAddr, sync::Arc};
 use tower_http::cors::CorsLayer;
 
+use crate::axum_app::csrf::{ALLOWED_ORIGINS, CSRF_HEADER};
+use crate::axum_app::oidc::OidcClient;
 use crate::axum_app::routes::create_router;
+use crate::axum_app::throttle::Throttle;
+use crate::shared::admin::bootstrap_admins;
+use crate::shared::http_client::create_http_client;
+use crate::shared::mail::{MailSender, create_mail_sender};
+use crate::shared::open_sockets::OpenSockets;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Arc<SqlitePool>,
     pub ws_sender: tokio::sync::broadcast::Sender<crate::shared::CanvasDataEvent>,
+    pub mailer: Arc<dyn MailSender>,
+    pub throttle: Throttle,
+    /// Only set when an OpenID provider is configured
+    pub oidc: Option<Arc<OidcClient>>,
+    pub open_sockets: OpenSockets,
 }
 
 pub async fn create_axum(
     ws_sender: tokio::sync::broadcast::Sender<crate::shared::CanvasDataEvent>,
+    open_sockets: OpenSockets,
 ) -> tokio::task::JoinHandle<()> {
     let pool = SqlitePoolOptions::new()
         .connect(
@@ -22,13 +38,37 @@ pub async fn create_axum(
         )
         .await
         .unwrap();
+    if let Err(e) = bootstrap_admins(&pool).await {
+        tracing::error!("Failed to bootstrap admins: {:?}", e);
+    }
+    let db = Arc::new(pool);
+    let oidc = OidcClient::from_env(create_http_client()).map(Arc::new);
+    // Fail on startup instead of the first login if provider requests can't be made
+    if let Some(oidc) = &oidc {
+        if let Err(e) = oidc.check_http_client().await {
+            tracing::error!(
+                "OpenID is configured but its HTTP client can't run: {:?}",
+                e
+            );
+            std::process::exit(1);
+        }
+    }
     let shared_state = Arc::new(AppState {
-        db: Arc::new(pool),
+        throttle: Throttle::from_env(db.clone()),
+        db,
         ws_sender,
+        mailer: create_mail_sender(),
+        oidc,
+        open_sockets,
     });
 
     let cors = CorsLayer::new()
-        .allow_origin(["http://localhost:3000"].map(|s| s.parse().unwrap())) // local development
+        .allow_origin(
+            ALLOWED_ORIGINS
+                .iter()
+                .map(|s| s.parse().unwrap())
+                .collect::<Vec<HeaderValue>>(),
+        )
         .allow_methods([
             Method::GET,
             Method::POST,
@@ -42,7 +82,10 @@ pub async fn create_axum(
             axum::http::header::AUTHORIZATION,
             axum::http::header::CONTENT_TYPE,
             axum::http::header::ACCEPT,
-        ]);
+            axum::http::header::IF_NONE_MATCH,
+            HeaderName::from_static(CSRF_HEADER),
+        ])
+        .expose_headers([axum::http::header::ETAG]);
 
     let app = create_router().layer(Extension(shared_state)).layer(cors);
 
@@ -52,9 +95,12 @@ pub async fn create_axum(
     tracing::debug!("Axum listening on {}", listener.local_addr().unwrap());
 
     // Spawn Axum server
-    let axum_handle = tokio::spawn(async move {
-        axum::serve(listener, app).await.unwrap();
-    });
-
-    return axum_handle;
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    })
 }
@@ -0,0 +1,214 @@
+use axum::extract::Request;
+use axum::http::{HeaderMap, Method, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::LazyLock;
+
+use crate::axum_app::error::AuthError;
+use crate::shared::cookies::Cookie;
+use crate::shared::token::generate_token;
+
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Origins the SPA is served from, configurable as a comma separated list via `ALLOWED_ORIGINS`
+pub static ALLOWED_ORIGINS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("ALLOWED_ORIGINS")
+        .unwrap_or("http://localhost:3000,http://localhost:8000".to_string())
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect()
+});
+
+/// The double-submit token of the current request, exposed via `GET /api/auth/csrf`
+#[derive(Clone)]
+pub struct CsrfToken(pub String);
+
+fn origin_of(url: &str) -> &str {
+    // scheme://host[:port] without path
+    match url.find("://") {
+        Some(scheme_end) => {
+            let rest = &url[scheme_end + 3..];
+            let end = rest.find('/').map_or(url.len(), |i| scheme_end + 3 + i);
+            &url[..end]
+        }
+        None => url,
+    }
+}
+
+/// Requests from browsers carry `Origin` (or at least `Referer`); it has to be one of ours.
+fn check_origin(headers: &HeaderMap) -> Result<(), AuthError> {
+    let source = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+        .map(|v| v.to_str().map(origin_of));
+    match source {
+        // Non-browser clients send neither header
+        None => Ok(()),
+        Some(Ok(origin)) if ALLOWED_ORIGINS.iter().any(|allowed| allowed == origin) => Ok(()),
+        Some(_) => Err(AuthError::OriginNotAllowed),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Double-submit cookie protection for all mutating requests.
+/// Every response hands out a `csrf_token` cookie readable by the SPA, which has to echo it
+/// in the `X-CSRF-Token` header for anything but GET, HEAD and OPTIONS.
+pub async fn csrf_protection(mut req: Request, next: Next) -> Response {
+    let cookies = req
+        .headers()
+        .get(header::COOKIE)
+        .and_then(|c| c.to_str().ok());
+    let cookie_token = cookies
+        .and_then(|c| Cookie::Csrf.get(c))
+        .map(str::to_string);
+
+    let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if !safe {
+        if let Err(e) = check_origin(req.headers()) {
+            return e.into_response();
+        }
+        // Without cookies there is no ambient authority a forged request could abuse
+        if cookies.is_some() {
+            let header_token = req.headers().get(CSRF_HEADER).and_then(|v| v.to_str().ok());
+            let valid = match (&cookie_token, header_token) {
+                (Some(cookie), Some(header)) => {
+                    constant_time_eq(cookie.as_bytes(), header.as_bytes())
+                }
+                _ => false,
+            };
+            if !valid {
+                return AuthError::CsrfFailed.into_response();
+            }
+        }
+    }
+
+    let issue_cookie = cookie_token.is_none();
+    let token = cookie_token.unwrap_or_else(generate_token);
+    req.extensions_mut().insert(CsrfToken(token.clone()));
+    let mut response = next.run(req).await;
+    if issue_cookie {
+        response
+            .headers_mut()
+            .append(header::SET_COOKIE, Cookie::Csrf.set(&token));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axum_app::test_support::{TestApp, TestResponse};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+
+    /// A mutating request carrying the session cookie, with the given CSRF cookie and header.
+    async fn logout(app: &TestApp, cookie: Option<&str>, header: Option<&str>) -> TestResponse {
+        let user_id = app.create_user("alice@example.com").await;
+        let mut cookies = app.login(&user_id).await;
+        if let Some(cookie) = cookie {
+            cookies = format!("{}; csrf_token={}", cookies, cookie);
+        }
+        let mut builder = Request::post("/api/auth/logout").header(header::COOKIE, cookies);
+        if let Some(header) = header {
+            builder = builder.header(CSRF_HEADER, header);
+        }
+        app.send(builder.body(Body::empty()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn safe_requests_get_a_token() {
+        let app = TestApp::new().await;
+        let res = app
+            .send(Request::get("/api/auth/csrf").body(Body::empty()).unwrap())
+            .await;
+        let token = res.cookie("csrf_token").unwrap();
+        assert_eq!(res.json()["csrf_token"], token);
+    }
+
+    #[tokio::test]
+    async fn matching_header_passes() {
+        let app = TestApp::new().await;
+        let status = logout(&app, Some("token"), Some("token")).await.status;
+        assert!(status.is_success(), "{}", status);
+    }
+
+    #[tokio::test]
+    async fn missing_or_wrong_header_is_rejected() {
+        for (cookie, header) in [
+            (None, None),
+            (Some("token"), None),
+            (None, Some("token")),
+            (Some("token"), Some("other")),
+        ] {
+            let app = TestApp::new().await;
+            let res = logout(&app, cookie, header).await;
+            assert_eq!(res.status, StatusCode::FORBIDDEN);
+            assert_eq!(
+                res.json()["code"],
+                "csrf_failed",
+                "{:?} {:?}",
+                cookie,
+                header
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_without_cookies_need_no_token() {
+        let app = TestApp::new().await;
+        let res = app
+            .send(
+                Request::post("/api/auth/password/forgot")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"email":"alice@example.com"}"#))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn foreign_origins_are_rejected() {
+        let app = TestApp::new().await;
+        for (name, value) in [
+            (header::ORIGIN, "https://evil.example"),
+            (header::REFERER, "https://evil.example/page"),
+        ] {
+            let res = app
+                .send(
+                    Request::post("/api/auth/password/forgot")
+                        .header(name, value)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(r#"{"email":"alice@example.com"}"#))
+                        .unwrap(),
+                )
+                .await;
+            assert_eq!(res.status, StatusCode::FORBIDDEN);
+            assert_eq!(res.json()["code"], "origin_not_allowed");
+        }
+        let res = app
+            .send(
+                Request::post("/api/auth/password/forgot")
+                    .header(header::ORIGIN, "http://localhost:3000")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"email":"alice@example.com"}"#))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+
+    #[test]
+    fn origin_drops_the_path() {
+        assert_eq!(
+            origin_of("https://a.example:8443/x/y"),
+            "https://a.example:8443"
+        );
+        assert_eq!(origin_of("http://localhost:3000"), "http://localhost:3000");
+        assert_eq!(origin_of("null"), "null");
+    }
+}
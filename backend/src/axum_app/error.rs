@@ -1,29 +1,204 @@
 use axum::{
     Json,
-    http::StatusCode,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde_json::json;
 
+use crate::axum_app::validation::ValidationErrors;
+
+/// Error of any handler. Every variant is rendered as
+/// `{"error": <message>, "code": <code>, ...details}` with a matching status.
+/// Internal errors are logged and answered without their cause.
+#[derive(Debug)]
+pub enum AppError {
+    Auth(AuthError),
+    Validation(ValidationErrors),
+    NotFound,
+    /// Authenticated, but lacking the right for the resource
+    Forbidden,
+    Conflict(Conflict),
+    Internal(anyhow::Error),
+}
+
+/// Failed authentication or authorization of the caller itself
 #[derive(Debug)]
 pub enum AuthError {
     WrongCredentials,
     MissingCredentials,
-    TokenCreation,
     InvalidToken,
+    RevokedToken,
+    ExpiredToken,
+    EmailNotVerified,
+    /// Locked out for the given number of seconds
+    TooManyRequests(u64),
+    CsrfFailed,
+    /// A personal access token was used beyond its scope
+    InsufficientScope,
+    OriginNotAllowed,
+    AccountDisabled,
+    AdminRequired,
 }
 
-impl IntoResponse for AuthError {
+#[derive(Debug)]
+pub enum Conflict {
+    EmailTaken,
+    TotpAlreadyEnabled,
+    /// Canvases only the user owns, which still have other members
+    OwnershipTransferRequired(Vec<String>),
+}
+
+impl From<AuthError> for AppError {
+    fn from(error: AuthError) -> Self {
+        AppError::Auth(error)
+    }
+}
+
+impl From<Conflict> for AppError {
+    fn from(conflict: Conflict) -> Self {
+        AppError::Conflict(conflict)
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError::Validation(errors)
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(error: anyhow::Error) -> Self {
+        AppError::Internal(error)
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        AppError::Internal(error.into())
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(error: jsonwebtoken::errors::Error) -> Self {
+        AppError::Internal(error.into())
+    }
+}
+
+impl AuthError {
+    fn parts(&self) -> (StatusCode, &'static str, &'static str) {
+        match self {
+            AuthError::WrongCredentials => (
+                StatusCode::UNAUTHORIZED,
+                "wrong_credentials",
+                "Wrong credentials",
+            ),
+            AuthError::MissingCredentials => (
+                StatusCode::UNAUTHORIZED,
+                "missing_credentials",
+                "Missing credentials",
+            ),
+            AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "invalid_token", "Invalid token"),
+            AuthError::RevokedToken => (StatusCode::UNAUTHORIZED, "token_revoked", "Token revoked"),
+            AuthError::ExpiredToken => (StatusCode::UNAUTHORIZED, "token_expired", "Token expired"),
+            AuthError::EmailNotVerified => (
+                StatusCode::FORBIDDEN,
+                "email_not_verified",
+                "Email not verified",
+            ),
+            AuthError::TooManyRequests(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "too_many_requests",
+                "Too many requests",
+            ),
+            AuthError::CsrfFailed => (
+                StatusCode::FORBIDDEN,
+                "csrf_failed",
+                "CSRF token missing or invalid",
+            ),
+            AuthError::InsufficientScope => (
+                StatusCode::FORBIDDEN,
+                "insufficient_scope",
+                "Insufficient token scope",
+            ),
+            AuthError::OriginNotAllowed => (
+                StatusCode::FORBIDDEN,
+                "origin_not_allowed",
+                "Origin not allowed",
+            ),
+            AuthError::AccountDisabled => (
+                StatusCode::FORBIDDEN,
+                "account_disabled",
+                "Account disabled",
+            ),
+            AuthError::AdminRequired => (
+                StatusCode::FORBIDDEN,
+                "admin_required",
+                "Admin rights required",
+            ),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AuthError::WrongCredentials => (StatusCode::UNAUTHORIZED, "Wrong credentials"),
-            AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
-            AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
-            AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
+        let (status, code, message) = match &self {
+            AppError::Auth(error) => error.parts(),
+            AppError::Validation(_) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation_failed",
+                "Validation failed",
+            ),
+            AppError::NotFound => (StatusCode::NOT_FOUND, "not_found", "Not found"),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "forbidden", "Forbidden"),
+            AppError::Conflict(Conflict::EmailTaken) => {
+                (StatusCode::CONFLICT, "email_taken", "Email already in use")
+            }
+            AppError::Conflict(Conflict::TotpAlreadyEnabled) => (
+                StatusCode::CONFLICT,
+                "totp_already_enabled",
+                "TOTP already enabled",
+            ),
+            AppError::Conflict(Conflict::OwnershipTransferRequired(_)) => (
+                StatusCode::CONFLICT,
+                "ownership_transfer_required",
+                "Owned canvases have to be transferred or deleted",
+            ),
+            AppError::Internal(error) => {
+                tracing::error!("Internal error: {:?}", error);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal",
+                    "Internal error",
+                )
+            }
         };
-        let body = Json(json!({
-            "error": error_message,
-        }));
-        (status, body).into_response()
+        let mut body = json!({
+            "error": message,
+            "code": code,
+        });
+        // Details clients need to react, next to error and code
+        match &self {
+            AppError::Validation(fields) => body["fields"] = json!(fields),
+            AppError::Conflict(Conflict::OwnershipTransferRequired(canvases)) => {
+                body["canvases"] = json!(canvases)
+            }
+            AppError::Auth(AuthError::TooManyRequests(retry_after)) => {
+                body["retry_after"] = json!(retry_after);
+                return (
+                    status,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(body),
+                )
+                    .into_response();
+            }
+            _ => {}
+        }
+        (status, Json(body)).into_response()
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        AppError::from(self).into_response()
     }
 }
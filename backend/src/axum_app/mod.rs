@@ -1,6 +1,12 @@
 mod axum;
+mod csrf;
 mod error;
+mod oidc;
 mod routes;
+#[cfg(test)]
+pub(crate) mod test_support;
+mod throttle;
 mod transformers;
+mod validation;
 
 pub use axum::create_axum;
@@ -0,0 +1,556 @@
+use anyhow::{Context, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use url::Url;
+
+use crate::shared::http_client::HttpClient;
+
+/// OpenID provider settings, enabled by setting `OIDC_ISSUER` and `OIDC_CLIENT_ID`
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+    /// Where the browser is sent after a successful login
+    pub post_login_redirect: String,
+    /// Where accounts with TOTP are sent for their second factor, the pending token follows
+    /// in the fragment
+    pub mfa_redirect: String,
+}
+
+impl OidcConfig {
+    pub fn from_env() -> Option<Self> {
+        let issuer = std::env::var("OIDC_ISSUER").ok()?;
+        let client_id = std::env::var("OIDC_CLIENT_ID").ok()?;
+        let public_url = std::env::var("PUBLIC_URL").unwrap_or("http://localhost:8000".to_string());
+        Some(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: std::env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_uri: std::env::var("OIDC_REDIRECT_URI")
+                .unwrap_or(format!("{}/api/auth/oidc/callback", public_url)),
+            scopes: std::env::var("OIDC_SCOPES").unwrap_or("openid email profile".to_string()),
+            post_login_redirect: std::env::var("OIDC_POST_LOGIN_REDIRECT")
+                .unwrap_or(format!("{}/", public_url)),
+            mfa_redirect: std::env::var("OIDC_MFA_REDIRECT")
+                .unwrap_or(format!("{}/login", public_url)),
+        })
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The claims of a validated ID token we care about
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+    nonce: Option<String>,
+}
+
+/// Authorization code flow with PKCE against a single provider.
+/// Discovery and the provider's signing keys are fetched lazily and cached.
+pub struct OidcClient {
+    pub config: OidcConfig,
+    http: Arc<dyn HttpClient>,
+    metadata: RwLock<Option<ProviderMetadata>>,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+/// S256 code challenge for a PKCE code verifier.
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+impl OidcClient {
+    pub fn from_env(http: Arc<dyn HttpClient>) -> Option<Self> {
+        Some(Self {
+            config: OidcConfig::from_env()?,
+            http,
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(None),
+        })
+    }
+
+    /// Fails if the client for provider requests can't run, so startup can stop early.
+    pub async fn check_http_client(&self) -> anyhow::Result<()> {
+        self.http.check().await
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> anyhow::Result<T> {
+        let response = self.http.get(url).await?;
+        if response.status != 200 {
+            bail!("{} returned status {}", url, response.status);
+        }
+        serde_json::from_slice(&response.body).with_context(|| format!("Invalid JSON from {}", url))
+    }
+
+    async fn metadata(&self) -> anyhow::Result<ProviderMetadata> {
+        if let Some(metadata) = self.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+        let url = format!("{}/.well-known/openid-configuration", self.config.issuer);
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != self.config.issuer {
+            bail!(
+                "Discovered issuer {} does not match {}",
+                metadata.issuer,
+                self.config.issuer
+            );
+        }
+        *self.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// URL of the provider's login page for a new login attempt.
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> anyhow::Result<String> {
+        let metadata = self.metadata().await?;
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", &self.config.scopes)
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", &pkce_challenge(code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.into())
+    }
+
+    /// Redeems the authorization code and returns the validated ID token claims.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> anyhow::Result<IdTokenClaims> {
+        let metadata = self.metadata().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let response = self.http.post_form(&metadata.token_endpoint, &form).await?;
+        if response.status != 200 {
+            bail!(
+                "Token endpoint returned status {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            );
+        }
+        let tokens: TokenResponse = serde_json::from_slice(&response.body)?;
+        self.validate_id_token(&metadata, &tokens.id_token, nonce)
+            .await
+    }
+
+    async fn validate_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: &str,
+    ) -> anyhow::Result<IdTokenClaims> {
+        let header = jsonwebtoken::decode_header(id_token)?;
+        // Only keys published by the provider are trusted, never a shared secret
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            bail!("Unsupported ID token algorithm {:?}", header.alg);
+        }
+        let key = self.decoding_key(metadata, header.kid.as_deref()).await?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)?.claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            bail!("ID token nonce does not match");
+        }
+        Ok(claims)
+    }
+
+    /// Finds the provider key for `kid`, refetching the key set once in case it was rotated.
+    async fn decoding_key(
+        &self,
+        metadata: &ProviderMetadata,
+        kid: Option<&str>,
+    ) -> anyhow::Result<DecodingKey> {
+        let find = |jwks: &JwkSet| match kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            // Without a kid only an unambiguous key set is usable
+            None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+            None => None,
+        };
+        if let Some(jwk) = self.jwks.read().await.as_ref().and_then(find) {
+            return Ok(DecodingKey::from_jwk(&jwk)?);
+        }
+        let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+        let jwk = find(&jwks).ok_or_else(|| anyhow!("Unknown ID token key {:?}", kid))?;
+        *self.jwks.write().await = Some(jwks);
+        Ok(DecodingKey::from_jwk(&jwk)?)
+    }
+}
+
+/// An in-process OpenID provider for tests of the client and the login routes
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use crate::shared::http_client::HttpResponse;
+    use futures::future::BoxFuture;
+    use jsonwebtoken::{EncodingKey, Header};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    pub const ISSUER: &str = "https://idp.example";
+    pub const CLIENT_ID: &str = "drawer";
+
+    pub struct SigningKey {
+        kid: String,
+        pkcs8: Vec<u8>,
+        public: Vec<u8>,
+    }
+
+    impl SigningKey {
+        pub fn generate(kid: &str) -> Self {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+                .unwrap()
+                .as_ref()
+                .to_vec();
+            let public = Ed25519KeyPair::from_pkcs8(&pkcs8)
+                .unwrap()
+                .public_key()
+                .as_ref()
+                .to_vec();
+            Self {
+                kid: kid.to_string(),
+                pkcs8,
+                public,
+            }
+        }
+
+        pub fn sign(&self, claims: &serde_json::Value) -> String {
+            let mut header = Header::new(Algorithm::EdDSA);
+            header.kid = Some(self.kid.clone());
+            jsonwebtoken::encode(&header, claims, &EncodingKey::from_ed_der(&self.pkcs8)).unwrap()
+        }
+
+        fn jwk(&self) -> serde_json::Value {
+            json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "alg": "EdDSA",
+                "kid": self.kid,
+                "x": URL_SAFE_NO_PAD.encode(&self.public),
+            })
+        }
+    }
+
+    /// An OpenID provider answering in process, its token endpoint hands out `id_token`.
+    pub struct MockProvider {
+        key: Mutex<SigningKey>,
+        id_token: Mutex<Option<String>>,
+        /// URLs of all requests, forms of token requests
+        pub requests: Mutex<Vec<String>>,
+        pub token_requests: Mutex<Vec<Vec<(String, String)>>>,
+    }
+
+    impl MockProvider {
+        pub fn new() -> Arc<Self> {
+            Arc::new(Self {
+                key: Mutex::new(SigningKey::generate("key-1")),
+                id_token: Mutex::new(None),
+                requests: Mutex::default(),
+                token_requests: Mutex::default(),
+            })
+        }
+
+        /// Valid claims of an ID token for `nonce`
+        pub fn claims(sub: &str, email: &str, nonce: &str) -> serde_json::Value {
+            json!({
+                "iss": ISSUER,
+                "aud": CLIENT_ID,
+                "sub": sub,
+                "exp": jsonwebtoken::get_current_timestamp() + 60,
+                "nonce": nonce,
+                "email": email,
+                "email_verified": true,
+                "name": "Alice",
+            })
+        }
+
+        /// Signs the claims with the current key and hands them out on the next code exchange.
+        pub fn issue(&self, claims: &serde_json::Value) {
+            *self.id_token.lock().unwrap() = Some(self.key.lock().unwrap().sign(claims));
+        }
+
+        pub fn issue_raw(&self, id_token: String) {
+            *self.id_token.lock().unwrap() = Some(id_token);
+        }
+
+        pub fn rotate_key(&self, kid: &str) {
+            *self.key.lock().unwrap() = SigningKey::generate(kid);
+        }
+
+        pub fn client(self: &Arc<Self>) -> OidcClient {
+            OidcClient {
+                config: OidcConfig {
+                    issuer: ISSUER.to_string(),
+                    client_id: CLIENT_ID.to_string(),
+                    client_secret: None,
+                    redirect_uri: "http://localhost:8000/api/auth/oidc/callback".to_string(),
+                    scopes: "openid email".to_string(),
+                    post_login_redirect: "http://localhost:8000/".to_string(),
+                    mfa_redirect: "http://localhost:8000/login".to_string(),
+                },
+                http: self.clone(),
+                metadata: RwLock::new(None),
+                jwks: RwLock::new(None),
+            }
+        }
+
+        fn respond(&self, url: &str) -> HttpResponse {
+            self.requests.lock().unwrap().push(url.to_string());
+            let body = match url.strip_prefix(ISSUER) {
+                Some("/.well-known/openid-configuration") => json!({
+                    "issuer": ISSUER,
+                    "authorization_endpoint": format!("{}/authorize", ISSUER),
+                    "token_endpoint": format!("{}/token", ISSUER),
+                    "jwks_uri": format!("{}/jwks", ISSUER),
+                }),
+                Some("/jwks") => json!({ "keys": [self.key.lock().unwrap().jwk()] }),
+                Some("/token") => match self.id_token.lock().unwrap().take() {
+                    Some(id_token) => json!({ "id_token": id_token, "token_type": "Bearer" }),
+                    None => {
+                        return HttpResponse {
+                            status: 400,
+                            body: br#"{"error":"invalid_grant"}"#.to_vec(),
+                        };
+                    }
+                },
+                _ => {
+                    return HttpResponse {
+                        status: 404,
+                        body: Vec::new(),
+                    };
+                }
+            };
+            HttpResponse {
+                status: 200,
+                body: body.to_string().into_bytes(),
+            }
+        }
+    }
+
+    impl HttpClient for MockProvider {
+        fn get(&self, url: &str) -> BoxFuture<'_, anyhow::Result<HttpResponse>> {
+            let response = self.respond(url);
+            Box::pin(async move { Ok(response) })
+        }
+
+        fn post_form(
+            &self,
+            url: &str,
+            form: &[(&str, &str)],
+        ) -> BoxFuture<'_, anyhow::Result<HttpResponse>> {
+            self.token_requests.lock().unwrap().push(
+                form.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            );
+            let response = self.respond(url);
+            Box::pin(async move { Ok(response) })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{CLIENT_ID, MockProvider, SigningKey};
+    use super::*;
+    use crate::shared::http_client::HttpResponse;
+    use futures::future::BoxFuture;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn discovers_the_authorization_endpoint_once() {
+        let provider = MockProvider::new();
+        let client = provider.client();
+        let url = client
+            .authorization_url("the-state", "the-nonce", "the-verifier")
+            .await
+            .unwrap();
+        let url = Url::parse(&url).unwrap();
+        assert_eq!(
+            url.as_str().split('?').next(),
+            Some("https://idp.example/authorize")
+        );
+        let query: std::collections::HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["client_id"], CLIENT_ID);
+        assert_eq!(query["state"], "the-state");
+        assert_eq!(query["nonce"], "the-nonce");
+        assert_eq!(query["code_challenge"], pkce_challenge("the-verifier"));
+        assert_eq!(query["code_challenge_method"], "S256");
+
+        client.authorization_url("a", "b", "c").await.unwrap();
+        assert_eq!(provider.requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_provider_claiming_another_issuer() {
+        struct Impostor(Arc<MockProvider>);
+        impl HttpClient for Impostor {
+            fn get(&self, _url: &str) -> BoxFuture<'_, anyhow::Result<HttpResponse>> {
+                let body = json!({
+                    "issuer": "https://evil.example",
+                    "authorization_endpoint": "https://evil.example/authorize",
+                    "token_endpoint": "https://evil.example/token",
+                    "jwks_uri": "https://evil.example/jwks",
+                });
+                Box::pin(async move {
+                    Ok(HttpResponse {
+                        status: 200,
+                        body: body.to_string().into_bytes(),
+                    })
+                })
+            }
+
+            fn post_form(
+                &self,
+                url: &str,
+                form: &[(&str, &str)],
+            ) -> BoxFuture<'_, anyhow::Result<HttpResponse>> {
+                self.0.post_form(url, form)
+            }
+        }
+        let mut client = MockProvider::new().client();
+        client.http = Arc::new(Impostor(MockProvider::new()));
+        assert!(client.authorization_url("a", "b", "c").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn exchanges_the_code_for_validated_claims() {
+        let provider = MockProvider::new();
+        let client = provider.client();
+        provider.issue(&MockProvider::claims("subject-1", "alice@example.com", "n"));
+
+        let claims = client
+            .exchange_code("the-code", "the-verifier", "n")
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "subject-1");
+        assert_eq!(claims.email.as_deref(), Some("alice@example.com"));
+        assert!(claims.email_verified);
+
+        let form = provider.token_requests.lock().unwrap().pop().unwrap();
+        for expected in [
+            ("grant_type", "authorization_code"),
+            ("code", "the-code"),
+            ("code_verifier", "the-verifier"),
+            ("client_id", CLIENT_ID),
+        ] {
+            assert!(
+                form.iter()
+                    .any(|(k, v)| (k.as_str(), v.as_str()) == expected),
+                "{:?} missing in {:?}",
+                expected,
+                form
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_that_fail_validation() {
+        let provider = MockProvider::new();
+        let client = provider.client();
+        let valid = MockProvider::claims("subject-1", "alice@example.com", "n");
+        let with = |key: &str, value: serde_json::Value| {
+            let mut claims = valid.clone();
+            claims[key] = value;
+            claims
+        };
+        let cases = [
+            ("nonce", with("nonce", json!("other"))),
+            ("audience", with("aud", json!("another-client"))),
+            ("issuer", with("iss", json!("https://evil.example"))),
+            ("expiry", with("exp", json!(1_000_000))),
+        ];
+        for (name, claims) in cases {
+            provider.issue(&claims);
+            assert!(
+                client.exchange_code("code", "verifier", "n").await.is_err(),
+                "accepted a token with a wrong {}",
+                name
+            );
+        }
+
+        // Signed by a key the provider never published, under its kid
+        provider.issue_raw(SigningKey::generate("key-1").sign(&valid));
+        assert!(client.exchange_code("code", "verifier", "n").await.is_err());
+
+        // A shared secret is never accepted, even with a known kid
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("key-1".to_string());
+        let secret = EncodingKey::from_secret(CLIENT_ID.as_bytes());
+        provider.issue_raw(jsonwebtoken::encode(&header, &valid, &secret).unwrap());
+        assert!(client.exchange_code("code", "verifier", "n").await.is_err());
+
+        provider.issue(&valid);
+        assert!(client.exchange_code("code", "verifier", "n").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn refetches_the_keys_after_rotation() {
+        let provider = MockProvider::new();
+        let client = provider.client();
+        let claims = MockProvider::claims("subject-1", "alice@example.com", "n");
+        provider.issue(&claims);
+        client.exchange_code("code", "verifier", "n").await.unwrap();
+
+        provider.rotate_key("key-2");
+        provider.issue(&claims);
+        client.exchange_code("code", "verifier", "n").await.unwrap();
+        let jwks_fetches = provider
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|url| url.ends_with("/jwks"))
+            .count();
+        assert_eq!(jwks_fetches, 2);
+    }
+}
@@ -0,0 +1,745 @@
+use crate::axum_app::axum::AppState;
+use crate::axum_app::error::{AppError, AuthError, Conflict};
+use crate::axum_app::routes::auth::verify_password;
+use crate::axum_app::transformers::ClientIp;
+use crate::shared::CanvasDataEvent;
+use crate::shared::audit::{AuditAction, AuditEvent, record};
+use crate::shared::cookies::Cookie;
+use crate::shared::jwt::{Claims, TOKEN_TTL};
+use crate::shared::mail::Mail;
+use crate::shared::token::{generate_token, hash_token};
+use axum::body::{Body, Bytes};
+use axum::extract::Path;
+use axum::http::{StatusCode, header};
+use axum::{
+    Extension, Json,
+    response::{IntoResponse, Response},
+};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqlitePool};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// What happens to canvases the deleted user is the only owner of
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum OwnedCanvases {
+    /// Promote the highest ranked remaining member to owner
+    Transfer,
+    Delete,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteUserPayload {
+    pub password: Option<String>,
+    /// Token of the mailed confirmation, for accounts without a password
+    pub confirmation: Option<String>,
+    /// Required as soon as a solely owned canvas has other members
+    pub owned_canvases: Option<OwnedCanvases>,
+}
+
+#[derive(Serialize)]
+pub struct Transfer {
+    pub canvas_id: String,
+    pub new_owner: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct DeleteUserResponse {
+    pub deleted_canvases: Vec<String>,
+    pub transferred_canvases: Vec<Transfer>,
+}
+
+/// Mails a link confirming the deletion of the account. Users who signed up with an OpenID
+/// provider have no password to re-enter.
+pub async fn request_deletion_confirmation(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    if claims.id != user_id {
+        return Err(AuthError::WrongCredentials.into());
+    }
+    let token = generate_token();
+    sqlx::query(
+        "INSERT INTO account_deletions (token_hash, user_id, expires_at) VALUES ($1, $2, datetime('now', $3))",
+    )
+    .bind(hash_token(&token))
+    .bind(&user_id)
+    .bind(format!("+{} seconds", TOKEN_TTL.account_deletion))
+    .execute(&*state.db)
+    .await?;
+    let public_url = std::env::var("PUBLIC_URL").unwrap_or("http://localhost:8000".to_string());
+    state
+        .mailer
+        .send(Mail {
+            to: claims.email.clone(),
+            subject: "Confirm account deletion".to_string(),
+            body: format!(
+                "Use the following link to delete your account. It is valid for {} minutes. Ignore this mail if you did not ask for it.\r\n\r\n{}/user?confirm_delete={}",
+                TOKEN_TTL.account_deletion / 60,
+                public_url,
+                token
+            ),
+        })
+        .await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Deletes the account after re-entering the password or with a mailed confirmation.
+/// Canvases without another owner are handed over or deleted first, so no canvas is left
+/// without an `O`.
+pub async fn delete_user(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Path(user_id): Path<String>,
+    Json(payload): Json<DeleteUserPayload>,
+) -> Result<Response, AppError> {
+    if claims.id != user_id {
+        return Err(AuthError::WrongCredentials.into());
+    }
+    if let Some(password) = &payload.password {
+        let row = sqlx::query("SELECT password_hash FROM users WHERE id = $1")
+            .bind(&user_id)
+            .fetch_one(&*state.db)
+            .await?;
+        let hash: String = row.try_get("password_hash")?;
+        verify_password(password, &hash)?;
+    }
+
+    let mut tx = state.db.begin().await?;
+    if payload.password.is_none() {
+        let confirmation = payload
+            .confirmation
+            .as_deref()
+            .ok_or(AuthError::MissingCredentials)?;
+        // Used up together with the deletion, a rejected attempt leaves it valid
+        let res = sqlx::query(
+            "UPDATE account_deletions SET used_at = datetime('now') WHERE token_hash = $1 AND user_id = $2 AND used_at IS NULL AND expires_at > datetime('now')",
+        )
+        .bind(hash_token(confirmation))
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(AuthError::InvalidToken.into());
+        }
+    }
+    let memberships = sqlx::query("SELECT canvas_id FROM user_canvas WHERE user_id = $1")
+        .bind(&user_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    let solely_owned = sqlx::query(
+        "SELECT canvas_id FROM user_canvas uc WHERE user_id = $1 AND right = 'O' AND NOT EXISTS (SELECT 1 FROM user_canvas o WHERE o.canvas_id = uc.canvas_id AND o.right = 'O' AND o.user_id != $1)",
+    )
+    .bind(&user_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut result = DeleteUserResponse::default();
+    // Sockets to close or notify once the transaction is committed
+    let mut events = Vec::new();
+    let mut undecided = Vec::new();
+    for row in solely_owned {
+        let canvas_id: String = row.try_get("canvas_id")?;
+        // Highest right first, earliest member among equals
+        let successor = sqlx::query(
+            "SELECT uc.user_id, u.email FROM user_canvas uc JOIN users u ON u.id = uc.user_id WHERE uc.canvas_id = $1 AND uc.user_id != $2 ORDER BY CASE uc.right WHEN 'CO' THEN 0 WHEN 'M' THEN 1 WHEN 'V' THEN 2 WHEN 'W' THEN 3 ELSE 4 END, uc.rowid LIMIT 1",
+        )
+        .bind(&canvas_id)
+        .bind(&user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        match (successor, payload.owned_canvases) {
+            (Some(successor), Some(OwnedCanvases::Transfer)) => {
+                let successor_id: String = successor.try_get("user_id")?;
+                sqlx::query(
+                    "UPDATE user_canvas SET right = 'O' WHERE canvas_id = $1 AND user_id = $2",
+                )
+                .bind(&canvas_id)
+                .bind(&successor_id)
+                .execute(&mut *tx)
+                .await?;
+                events.push(CanvasDataEvent::RightChanged(
+                    canvas_id.clone(),
+                    (successor_id, Some("O".to_string())),
+                ));
+                result.transferred_canvases.push(Transfer {
+                    canvas_id,
+                    new_owner: successor.try_get("email")?,
+                });
+            }
+            (Some(_), None) => undecided.push(canvas_id),
+            // Canvases nobody else can see are deleted without asking
+            (_, _) => {
+                events.push(CanvasDataEvent::CanvasDeleted(canvas_id.clone()));
+                sqlx::query("DELETE FROM canvas WHERE id = $1")
+                    .bind(&canvas_id)
+                    .execute(&mut *tx)
+                    .await?;
+                result.deleted_canvases.push(canvas_id);
+            }
+        }
+    }
+    if !undecided.is_empty() {
+        return Err(Conflict::OwnershipTransferRequired(undecided).into());
+    }
+
+    for row in memberships {
+        let canvas_id: String = row.try_get("canvas_id")?;
+        events.push(CanvasDataEvent::RightChanged(
+            canvas_id,
+            (user_id.clone(), None),
+        ));
+    }
+    // Invites would otherwise outlive their creator with `created_by` unset
+    sqlx::query("DELETE FROM canvas_invites WHERE created_by = $1")
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await?;
+    // Sessions, tokens and memberships go with the user
+    sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::AccountDeleted)
+            .actor(&user_id)
+            .target(&user_id)
+            .ip(client_ip.0)
+            .before(json!({ "email": claims.email })),
+    )
+    .await;
+
+    for event in events {
+        let _ = state.ws_sender.send(event);
+    }
+    tracing::info!("Deleted user {}", user_id);
+
+    let mut response = Json(result).into_response();
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, Cookie::Access.clear());
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, Cookie::Refresh.clear());
+    Ok(response)
+}
+
+type ExportChunk = Result<Bytes, std::io::Error>;
+
+async fn send_chunk(tx: &mpsc::Sender<ExportChunk>, chunk: String) -> anyhow::Result<()> {
+    tx.send(Ok(Bytes::from(chunk)))
+        .await
+        .map_err(|_| anyhow::anyhow!("Export download was cancelled"))
+}
+
+/// Stored JSON text as a value, anything else as a string
+fn stored_json(text: Option<String>) -> serde_json::Value {
+    text.map_or(serde_json::Value::Null, |text| {
+        serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text))
+    })
+}
+
+/// Writes the rows of the query as the JSON array `name`, one row at a time.
+async fn write_section(
+    db: &SqlitePool,
+    tx: &mpsc::Sender<ExportChunk>,
+    name: &str,
+    sql: &str,
+    binds: &[&str],
+    to_json: fn(&SqliteRow) -> Result<serde_json::Value, sqlx::Error>,
+) -> anyhow::Result<()> {
+    send_chunk(tx, format!(",{}:[", json!(name))).await?;
+    let mut query = sqlx::query(sql);
+    for bind in binds {
+        query = query.bind(*bind);
+    }
+    let mut rows = query.fetch(db);
+    let mut first = true;
+    while let Some(row) = rows.try_next().await? {
+        let separator = if first { "" } else { "," };
+        send_chunk(tx, format!("{}{}", separator, to_json(&row)?)).await?;
+        first = false;
+    }
+    send_chunk(tx, "]".to_string()).await
+}
+
+/// Writes the export document piece by piece, holding at most one row in memory.
+async fn write_export(
+    db: &SqlitePool,
+    user_id: &str,
+    email: &str,
+    profile: serde_json::Value,
+    tx: &mpsc::Sender<ExportChunk>,
+) -> anyhow::Result<()> {
+    send_chunk(tx, format!("{{\"user\":{}", profile)).await?;
+    write_section(
+        db,
+        tx,
+        "memberships",
+        "SELECT canvas_id, right FROM user_canvas WHERE user_id = $1 ORDER BY rowid",
+        &[user_id],
+        |row| {
+            Ok(json!({
+                "canvas_id": row.try_get::<String, _>("canvas_id")?,
+                "right": row.try_get::<String, _>("right")?,
+            }))
+        },
+    )
+    .await?;
+    // Token hashes are left out of sessions, tokens and invites
+    write_section(
+        db,
+        tx,
+        "sessions",
+        "SELECT id, user_agent, ip, created_at, last_seen_at, revoked_at FROM sessions WHERE user_id = $1 ORDER BY created_at",
+        &[user_id],
+        |row| {
+            Ok(json!({
+                "id": row.try_get::<String, _>("id")?,
+                "user_agent": row.try_get::<Option<String>, _>("user_agent")?,
+                "ip": row.try_get::<Option<String>, _>("ip")?,
+                "created_at": row.try_get::<Option<String>, _>("created_at")?,
+                "last_seen_at": row.try_get::<Option<String>, _>("last_seen_at")?,
+                "revoked_at": row.try_get::<Option<String>, _>("revoked_at")?,
+            }))
+        },
+    )
+    .await?;
+    write_section(
+        db,
+        tx,
+        "personal_access_tokens",
+        "SELECT id, name, scope, created_at, expires_at, last_used_at, revoked_at FROM personal_access_tokens WHERE user_id = $1 ORDER BY created_at",
+        &[user_id],
+        |row| {
+            Ok(json!({
+                "id": row.try_get::<String, _>("id")?,
+                "name": row.try_get::<String, _>("name")?,
+                "scope": row.try_get::<String, _>("scope")?,
+                "created_at": row.try_get::<Option<String>, _>("created_at")?,
+                "expires_at": row.try_get::<Option<String>, _>("expires_at")?,
+                "last_used_at": row.try_get::<Option<String>, _>("last_used_at")?,
+                "revoked_at": row.try_get::<Option<String>, _>("revoked_at")?,
+            }))
+        },
+    )
+    .await?;
+    write_section(
+        db,
+        tx,
+        "identities",
+        "SELECT issuer, subject, created_at FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+        &[user_id],
+        |row| {
+            Ok(json!({
+                "issuer": row.try_get::<String, _>("issuer")?,
+                "subject": row.try_get::<String, _>("subject")?,
+                "created_at": row.try_get::<Option<String>, _>("created_at")?,
+            }))
+        },
+    )
+    .await?;
+    // Lockouts are recorded by the submitted email, not the user id
+    write_section(
+        db,
+        tx,
+        "lockouts",
+        "SELECT ip, failures, locked_until, created_at FROM account_lockouts WHERE email = $1 ORDER BY id",
+        &[email],
+        |row| {
+            Ok(json!({
+                "ip": row.try_get::<Option<String>, _>("ip")?,
+                "failures": row.try_get::<i64, _>("failures")?,
+                "locked_until": row.try_get::<String, _>("locked_until")?,
+                "created_at": row.try_get::<Option<String>, _>("created_at")?,
+            }))
+        },
+    )
+    .await?;
+    write_section(
+        db,
+        tx,
+        "audit_log",
+        "SELECT id, action, actor_id, target, canvas_id, ip, before, after, created_at FROM audit_log WHERE actor_id = $1 OR target IN ($1, $2) ORDER BY id",
+        &[user_id, email],
+        |row| {
+            Ok(json!({
+                "id": row.try_get::<i64, _>("id")?,
+                "action": row.try_get::<String, _>("action")?,
+                "actor_id": row.try_get::<Option<String>, _>("actor_id")?,
+                "target": row.try_get::<Option<String>, _>("target")?,
+                "canvas_id": row.try_get::<Option<String>, _>("canvas_id")?,
+                "ip": row.try_get::<Option<String>, _>("ip")?,
+                "before": stored_json(row.try_get("before")?),
+                "after": stored_json(row.try_get("after")?),
+                "created_at": row.try_get::<String, _>("created_at")?,
+            }))
+        },
+    )
+    .await?;
+    write_section(
+        db,
+        tx,
+        "invites",
+        "SELECT id, canvas_id, right, created_at, expires_at, max_uses, uses, revoked_at FROM canvas_invites WHERE created_by = $1 ORDER BY created_at",
+        &[user_id],
+        |row| {
+            Ok(json!({
+                "id": row.try_get::<String, _>("id")?,
+                "canvas_id": row.try_get::<String, _>("canvas_id")?,
+                "right": row.try_get::<String, _>("right")?,
+                "created_at": row.try_get::<Option<String>, _>("created_at")?,
+                "expires_at": row.try_get::<String, _>("expires_at")?,
+                "max_uses": row.try_get::<Option<i64>, _>("max_uses")?,
+                "uses": row.try_get::<i64, _>("uses")?,
+                "revoked_at": row.try_get::<Option<String>, _>("revoked_at")?,
+            }))
+        },
+    )
+    .await?;
+
+    send_chunk(tx, ",\"owned_canvases\":[".to_string()).await?;
+    let owned = sqlx::query(
+        "SELECT c.id, c.title, c.description, c.created_at, c.moderated FROM canvas c JOIN user_canvas uc ON uc.canvas_id = c.id WHERE uc.user_id = $1 AND uc.right = 'O' ORDER BY uc.rowid",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+    for (i, canvas) in owned.iter().enumerate() {
+        let canvas_id: String = canvas.try_get("id")?;
+        let title: String = canvas.try_get("title")?;
+        let description: String = canvas.try_get("description")?;
+        let created_at: Option<String> = canvas.try_get("created_at")?;
+        let moderated: bool = canvas.try_get("moderated")?;
+        send_chunk(
+            tx,
+            format!(
+                "{}{{\"id\":{},\"title\":{},\"description\":{},\"created_at\":{},\"moderated\":{},\"events\":[",
+                if i == 0 { "" } else { "," },
+                json!(canvas_id),
+                json!(title),
+                json!(description),
+                json!(created_at),
+                moderated
+            ),
+        )
+        .await?;
+        let mut events =
+            sqlx::query("SELECT events FROM canvas_events WHERE canvas_id = $1 ORDER BY seq")
+                .bind(&canvas_id)
+                .fetch(db);
+        let mut first = true;
+        while let Some(row) = events.try_next().await? {
+            // Events are stored as serialized JSON, anything else is exported as a string
+            let event = stored_json(row.try_get("events")?);
+            let separator = if first { "" } else { "," };
+            send_chunk(tx, format!("{}{}", separator, event)).await?;
+            first = false;
+        }
+        send_chunk(tx, "]}".to_string()).await?;
+    }
+    send_chunk(tx, "]}".to_string()).await
+}
+
+/// Exports everything stored about the user as one JSON document. The body is streamed
+/// so accounts with large canvases are never held in memory as a whole.
+pub async fn export_user(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    Path(user_id): Path<String>,
+) -> Result<Response, AppError> {
+    if claims.id != user_id {
+        return Err(AuthError::WrongCredentials.into());
+    }
+    // Secrets like the password hash and TOTP secret are left out
+    let row = sqlx::query(
+        "SELECT id, email, display_name, email_verified, totp_enabled, created_at, updated_at FROM users WHERE id = $1",
+    )
+    .bind(&user_id)
+    .fetch_one(&*state.db)
+    .await?;
+    let email: String = row.try_get("email")?;
+    let profile = json!({
+        "id": row.try_get::<String, _>("id")?,
+        "email": row.try_get::<String, _>("email")?,
+        "display_name": row.try_get::<String, _>("display_name")?,
+        "email_verified": row.try_get::<bool, _>("email_verified")?,
+        "totp_enabled": row.try_get::<bool, _>("totp_enabled")?,
+        "created_at": row.try_get::<Option<String>, _>("created_at")?,
+        "updated_at": row.try_get::<Option<String>, _>("updated_at")?,
+    });
+
+    let (tx, rx) = mpsc::channel::<ExportChunk>(16);
+    let db = state.db.clone();
+    tokio::spawn(async move {
+        if let Err(e) = write_export(&db, &user_id, &email, profile, &tx).await {
+            tracing::error!("Export of user {} failed: {:?}", user_id, e);
+            // Aborts the body so the client does not take a truncated export for a complete one
+            let _ = tx.send(Err(std::io::Error::other("export failed"))).await;
+        }
+    });
+
+    let mut response = Body::from_stream(ReceiverStream::new(rx)).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"export-{}.json\"", claims.id)
+            .parse()
+            .unwrap(),
+    );
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axum_app::test_support::{PASSWORD, TestApp};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    async fn user_exists(app: &TestApp, user_id: &str) -> bool {
+        sqlx::query("SELECT 1 FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(app.db())
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    /// A user that signed up with an OpenID provider and has no password
+    async fn oidc_user(app: &TestApp, email: &str) -> String {
+        let user_id = app.create_user(email).await;
+        sqlx::query("UPDATE users SET password_hash = '' WHERE id = $1")
+            .bind(&user_id)
+            .execute(app.db())
+            .await
+            .unwrap();
+        user_id
+    }
+
+    async fn request_confirmation(app: &TestApp, user_id: &str, cookie: &str) -> String {
+        let res = app
+            .request(
+                Method::POST,
+                &format!("/api/user/{}/delete-confirmation", user_id),
+                Some(cookie),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+        let mail = app.outbox().pop().unwrap();
+        let (_, token) = mail.split_once("/user?confirm_delete=").unwrap();
+        token.trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn deletion_requires_the_password() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let cookie = app.login(&user_id).await;
+        let uri = format!("/api/user/{}", user_id);
+
+        for body in [json!({ "password": "wrong" }), json!({})] {
+            let res = app
+                .request(Method::DELETE, &uri, Some(&cookie), Some(body))
+                .await;
+            assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        }
+        let res = app
+            .request(
+                Method::DELETE,
+                &uri,
+                Some(&cookie),
+                Some(json!({ "password": PASSWORD })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(!user_exists(&app, &user_id).await);
+    }
+
+    #[tokio::test]
+    async fn others_can_not_delete_the_account() {
+        let app = TestApp::new().await;
+        let alice = app.create_user("alice@example.com").await;
+        let bob = app.create_user("bob@example.com").await;
+        let cookie = app.login(&bob).await;
+        let res = app
+            .request(
+                Method::DELETE,
+                &format!("/api/user/{}", alice),
+                Some(&cookie),
+                Some(json!({ "password": PASSWORD })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        assert!(user_exists(&app, &alice).await);
+    }
+
+    #[tokio::test]
+    async fn account_without_password_is_deleted_with_the_mailed_confirmation() {
+        let app = TestApp::new().await;
+        let user_id = oidc_user(&app, "alice@example.com").await;
+        let cookie = app.login(&user_id).await;
+        let uri = format!("/api/user/{}", user_id);
+
+        let res = app
+            .request(
+                Method::DELETE,
+                &uri,
+                Some(&cookie),
+                Some(json!({ "password": "" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+        let token = request_confirmation(&app, &user_id, &cookie).await;
+        let res = app
+            .request(
+                Method::DELETE,
+                &uri,
+                Some(&cookie),
+                Some(json!({ "confirmation": "made-up" })),
+            )
+            .await;
+        assert_eq!(res.json()["code"], "invalid_token");
+        let res = app
+            .request(
+                Method::DELETE,
+                &uri,
+                Some(&cookie),
+                Some(json!({ "confirmation": token })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(!user_exists(&app, &user_id).await);
+    }
+
+    #[tokio::test]
+    async fn confirmation_survives_a_required_ownership_decision() {
+        let app = TestApp::new().await;
+        let alice = oidc_user(&app, "alice@example.com").await;
+        let bob = app.create_user("bob@example.com").await;
+        let canvas_id = app.create_canvas(&[(&alice, "O"), (&bob, "W")]).await;
+        let cookie = app.login(&alice).await;
+        let token = request_confirmation(&app, &alice, &cookie).await;
+        let uri = format!("/api/user/{}", alice);
+
+        let res = app
+            .request(
+                Method::DELETE,
+                &uri,
+                Some(&cookie),
+                Some(json!({ "confirmation": token })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        assert_eq!(res.json()["canvases"], json!([canvas_id]));
+
+        let res = app
+            .request(
+                Method::DELETE,
+                &uri,
+                Some(&cookie),
+                Some(json!({ "confirmation": token, "owned_canvases": "transfer" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(
+            res.json()["transferred_canvases"][0]["new_owner"],
+            "bob@example.com"
+        );
+        let right: String = sqlx::query_scalar(
+            "SELECT right FROM user_canvas WHERE canvas_id = $1 AND user_id = $2",
+        )
+        .bind(&canvas_id)
+        .bind(&bob)
+        .fetch_one(app.db())
+        .await
+        .unwrap();
+        assert_eq!(right, "O");
+    }
+
+    #[tokio::test]
+    async fn export_contains_all_stored_data_without_secrets() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let canvas_id = app.create_canvas(&[(&user_id, "O")]).await;
+        let cookie = app.login(&user_id).await;
+        for sql in [
+            "INSERT INTO personal_access_tokens (user_id, name, token_hash, scope) VALUES ($1, 'script', 'pat-hash', 'read')",
+            "INSERT INTO user_identities (issuer, subject, user_id) VALUES ('https://idp.example.com', 'sub-1', $1)",
+            "INSERT INTO audit_log (action, actor_id, target) VALUES ('login', $1, $1)",
+            "INSERT INTO audit_log (action, target) VALUES ('login_failed', 'alice@example.com')",
+            "INSERT INTO audit_log (action, target) VALUES ('login_failed', 'bob@example.com')",
+            "INSERT INTO account_lockouts (email, ip, failures, locked_until) VALUES ('alice@example.com', '10.0.0.1', 5, datetime('now'))",
+            "INSERT INTO account_lockouts (email, ip, failures, locked_until) VALUES ('bob@example.com', '10.0.0.1', 5, datetime('now'))",
+        ] {
+            sqlx::query(sql)
+                .bind(&user_id)
+                .execute(app.db())
+                .await
+                .unwrap();
+        }
+        crate::shared::invite::create_invite(app.db(), &canvas_id, &user_id, "W", 7, None)
+            .await
+            .unwrap();
+
+        let res = app
+            .request(
+                Method::GET,
+                &format!("/api/user/{}/export", user_id),
+                Some(&cookie),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        let body = String::from_utf8_lossy(&res.body);
+        assert!(!body.contains("hash"));
+        let export = res.json();
+        assert_eq!(export["user"]["email"], "alice@example.com");
+        assert_eq!(export["memberships"][0]["right"], "O");
+        assert_eq!(export["sessions"].as_array().unwrap().len(), 1);
+        assert_eq!(export["personal_access_tokens"][0]["name"], "script");
+        assert_eq!(export["identities"][0]["subject"], "sub-1");
+        assert_eq!(export["lockouts"].as_array().unwrap().len(), 1);
+        let targets: Vec<&str> = export["audit_log"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["target"].as_str().unwrap())
+            .collect();
+        assert_eq!(targets, [user_id.as_str(), "alice@example.com"]);
+        assert_eq!(export["invites"][0]["right"], "W");
+        assert_eq!(export["owned_canvases"][0]["id"], json!(canvas_id));
+    }
+
+    #[tokio::test]
+    async fn confirmation_of_another_account_is_rejected() {
+        let app = TestApp::new().await;
+        let alice = oidc_user(&app, "alice@example.com").await;
+        let bob = oidc_user(&app, "bob@example.com").await;
+        let bob_cookie = app.login(&bob).await;
+        let token = request_confirmation(&app, &bob, &bob_cookie).await;
+        let alice_cookie = app.login(&alice).await;
+
+        let res = app
+            .request(
+                Method::DELETE,
+                &format!("/api/user/{}", alice),
+                Some(&alice_cookie),
+                Some(json!({ "confirmation": token })),
+            )
+            .await;
+        assert_eq!(res.json()["code"], "invalid_token");
+        assert!(user_exists(&app, &alice).await);
+    }
+}
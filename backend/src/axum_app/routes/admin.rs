@@ -0,0 +1,378 @@
+use crate::axum_app::axum::AppState;
+use crate::axum_app::error::AppError;
+use crate::axum_app::routes::auth::{broadcast_revoked, send_password_reset};
+use crate::axum_app::transformers::{Admin, ClientIp};
+use crate::axum_app::validation::ValidationErrors;
+use crate::shared::audit::{AuditAction, AuditEvent, AuditFilter, list_entries, record};
+use crate::shared::session::revoke_user_sessions;
+use anyhow::Context;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::{
+    Extension, Json,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::sync::Arc;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    /// Matches email and display name
+    pub q: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+impl ListQuery {
+    fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE)
+    }
+}
+
+/// Filters and pagination of audit log queries
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub action: Option<AuditAction>,
+    /// User id of the actor
+    pub actor: Option<String>,
+    /// User id of the affected user, or the email of a failed login to an unknown account
+    pub target: Option<String>,
+    pub canvas_id: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+impl AuditQuery {
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE)
+    }
+
+    pub fn filter(&self) -> AuditFilter {
+        AuditFilter {
+            action: self.action,
+            actor_id: self.actor.clone(),
+            target: self.target.clone(),
+            canvas_id: self.canvas_id.clone(),
+            since: self.since.clone(),
+            until: self.until.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AdminUser {
+    pub id: String,
+    pub email: String,
+    pub display_name: String,
+    pub email_verified: bool,
+    pub is_admin: bool,
+    pub disabled_at: Option<String>,
+    pub created_at: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AdminCanvas {
+    pub id: String,
+    pub title: String,
+    pub moderated: bool,
+    pub owners: Vec<String>,
+    pub members: i64,
+    pub events: i64,
+    /// Size of the stored events in bytes
+    pub size: i64,
+}
+
+pub async fn list_users(
+    state: Extension<Arc<AppState>>,
+    _admin: Admin,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let pattern = query.q.as_deref().map(|q| {
+        format!(
+            "%{}%",
+            q.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        )
+    });
+    let rows = sqlx::query(
+        "SELECT id, email, display_name, email_verified, is_admin, disabled_at, created_at FROM users WHERE $1 IS NULL OR email LIKE $1 ESCAPE '\\' OR display_name LIKE $1 ESCAPE '\\' ORDER BY email LIMIT $2 OFFSET $3",
+    )
+    .bind(pattern)
+    .bind(query.limit())
+    .bind(query.offset.unwrap_or(0))
+    .fetch_all(&*state.db)
+    .await?;
+    let users = rows
+        .iter()
+        .map(|row| {
+            Ok(AdminUser {
+                id: row.try_get("id")?,
+                email: row.try_get("email")?,
+                display_name: row.try_get("display_name")?,
+                email_verified: row.try_get("email_verified")?,
+                is_admin: row.try_get("is_admin")?,
+                disabled_at: row.try_get("disabled_at")?,
+                created_at: row.try_get("created_at")?,
+            })
+        })
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    Ok(Json(users))
+}
+
+/// Disables the account, ends its sessions and closes its sockets. Personal access tokens
+/// are kept but rejected until the account is enabled again.
+pub async fn disable_user(
+    state: Extension<Arc<AppState>>,
+    Admin(admin): Admin,
+    client_ip: ClientIp,
+    Path(user_id): Path<String>,
+) -> Result<Response, AppError> {
+    if admin.id == user_id {
+        // Would lock out the last admin in the worst case
+        let mut errors = ValidationErrors::default();
+        errors.add("id", "can't disable your own account");
+        return Err(errors.into());
+    }
+    let res = sqlx::query(
+        "UPDATE users SET disabled_at = coalesce(disabled_at, datetime('now')) WHERE id = $1",
+    )
+    .bind(&user_id)
+    .execute(&*state.db)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    let mut revoked = revoke_user_sessions(&state.db, &user_id, None).await?;
+    // Sockets opened with a personal access token carry the token id as session
+    let tokens = sqlx::query(
+        "SELECT id FROM personal_access_tokens WHERE user_id = $1 AND revoked_at IS NULL",
+    )
+    .bind(&user_id)
+    .fetch_all(&*state.db)
+    .await?;
+    for token in tokens {
+        revoked.push(token.try_get("id")?);
+    }
+    broadcast_revoked(&state, revoked);
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::UserDisabled)
+            .actor(&admin.id)
+            .target(&user_id)
+            .ip(client_ip.0),
+    )
+    .await;
+    tracing::info!("Admin {} disabled user {}", admin.email, user_id);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn enable_user(
+    state: Extension<Arc<AppState>>,
+    Admin(admin): Admin,
+    client_ip: ClientIp,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let res = sqlx::query("UPDATE users SET disabled_at = NULL WHERE id = $1")
+        .bind(&user_id)
+        .execute(&*state.db)
+        .await?;
+    if res.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::UserEnabled)
+            .actor(&admin.id)
+            .target(&user_id)
+            .ip(client_ip.0),
+    )
+    .await;
+    tracing::info!("Admin {} enabled user {}", admin.email, user_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Invalidates the password, ends all sessions and mails the user a reset link.
+pub async fn reset_user_password(
+    state: Extension<Arc<AppState>>,
+    Admin(admin): Admin,
+    client_ip: ClientIp,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    // An empty hash never verifies, so only the reset link can set a new password
+    let Some(row) = sqlx::query(
+        "UPDATE users SET password_hash = '', updated_at = datetime('now') WHERE id = $1 RETURNING email",
+    )
+    .bind(&user_id)
+    .fetch_optional(&*state.db)
+    .await?
+    else {
+        return Err(AppError::NotFound);
+    };
+    let email: String = row.try_get("email")?;
+    let revoked = revoke_user_sessions(&state.db, &user_id, None).await?;
+    broadcast_revoked(&state, revoked);
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::PasswordReset)
+            .actor(&admin.id)
+            .target(&user_id)
+            .ip(client_ip.0),
+    )
+    .await;
+    send_password_reset(&state, &email)
+        .await
+        .with_context(|| format!("Failed to send password reset for {}", email))?;
+    tracing::info!("Admin {} reset the password of {}", admin.email, user_id);
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn list_canvases(
+    state: Extension<Arc<AppState>>,
+    _admin: Admin,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let rows = sqlx::query(
+        "SELECT c.id, c.title, c.moderated, \
+            (SELECT json_group_array(u.email) FROM user_canvas uc JOIN users u ON u.id = uc.user_id WHERE uc.canvas_id = c.id AND uc.right = 'O') AS owners, \
+            (SELECT COUNT(*) FROM user_canvas uc WHERE uc.canvas_id = c.id) AS members, \
+            (SELECT COUNT(*) FROM canvas_events ce WHERE ce.canvas_id = c.id) AS events, \
+            (SELECT coalesce(SUM(length(ce.events)), 0) FROM canvas_events ce WHERE ce.canvas_id = c.id) AS size \
+        FROM canvas c ORDER BY c.rowid LIMIT $1 OFFSET $2",
+    )
+    .bind(query.limit())
+    .bind(query.offset.unwrap_or(0))
+    .fetch_all(&*state.db)
+    .await?;
+    let canvases = rows
+        .iter()
+        .map(|row| {
+            let owners: String = row.try_get("owners")?;
+            Ok(AdminCanvas {
+                id: row.try_get("id")?,
+                title: row.try_get("title")?,
+                moderated: row.try_get("moderated")?,
+                owners: serde_json::from_str(&owners).unwrap_or_default(),
+                members: row.try_get("members")?,
+                events: row.try_get("events")?,
+                size: row.try_get("size")?,
+            })
+        })
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    Ok(Json(canvases))
+}
+
+pub async fn list_audit(
+    state: Extension<Arc<AppState>>,
+    _admin: Admin,
+    Query(query): Query<AuditQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let entries = list_entries(
+        &state.db,
+        &query.filter(),
+        query.limit(),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+    Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axum_app::test_support::TestApp;
+    use axum::http::{Method, StatusCode};
+    use serde_json::Value;
+
+    async fn create_admin(app: &TestApp, email: &str) -> String {
+        let user_id = app.create_user(email).await;
+        sqlx::query("UPDATE users SET is_admin = TRUE WHERE id = $1")
+            .bind(&user_id)
+            .execute(app.db())
+            .await
+            .unwrap();
+        user_id
+    }
+
+    #[tokio::test]
+    async fn audit_log_is_only_visible_to_admins() {
+        let app = TestApp::new().await;
+        let admin = create_admin(&app, "admin@example.com").await;
+        let alice = app.create_user("alice@example.com").await;
+
+        let cookie = app.login(&alice).await;
+        let res = app
+            .request(Method::GET, "/api/admin/audit", Some(&cookie), None)
+            .await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+        let res = app
+            .request(Method::GET, "/api/admin/audit", None, None)
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+        let cookie = app.login(&admin).await;
+        let res = app
+            .request(
+                Method::POST,
+                &format!("/api/admin/users/{}/disable", alice),
+                Some(&cookie),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        let res = app
+            .request(Method::GET, "/api/admin/audit", Some(&cookie), None)
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        let entries = res.json();
+        assert_eq!(entries[0]["action"], "user_disabled");
+        // Unlike the canvas audit log, admins see the client IP
+        assert_eq!(entries[0]["ip"], "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn admin_actions_are_audited_with_the_user_as_target() {
+        let app = TestApp::new().await;
+        let admin = create_admin(&app, "admin@example.com").await;
+        let alice = app.create_user("alice@example.com").await;
+        let cookie = app.login(&admin).await;
+        for (action, status) in [
+            ("disable", StatusCode::NO_CONTENT),
+            ("enable", StatusCode::NO_CONTENT),
+            ("reset-password", StatusCode::ACCEPTED),
+        ] {
+            let res = app
+                .request(
+                    Method::POST,
+                    &format!("/api/admin/users/{}/{}", alice, action),
+                    Some(&cookie),
+                    None,
+                )
+                .await;
+            assert_eq!(res.status, status);
+        }
+
+        let res = app
+            .request(
+                Method::GET,
+                &format!("/api/admin/audit?target={}", alice),
+                Some(&cookie),
+                None,
+            )
+            .await;
+        let entries = res.json();
+        let entries = entries.as_array().unwrap();
+        let actions: Vec<&Value> = entries.iter().map(|entry| &entry["action"]).collect();
+        assert_eq!(actions, ["password_reset", "user_enabled", "user_disabled"]);
+        assert!(
+            entries
+                .iter()
+                .all(|entry| entry["actor_id"] == admin.as_str())
+        );
+    }
+}
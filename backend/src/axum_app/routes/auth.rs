@@ -1,22 +1,64 @@
 use crate::axum_app::axum::AppState;
-use crate::axum_app::error::AuthError;
-use crate::shared::jwt::{Claims, KEYS};
+use crate::axum_app::csrf::CsrfToken;
+use crate::axum_app::error::{AppError, AuthError, Conflict};
+use crate::axum_app::throttle::AttemptState;
+use crate::axum_app::transformers::{ClientIp, UserAgent};
+use crate::axum_app::validation::{
+    Validate, ValidationErrors, check_display_name, check_email, check_not_empty, check_password,
+};
+use crate::shared::admin::get_account_status;
+use crate::shared::audit::{AuditAction, AuditEvent, record};
+use crate::shared::cookies::Cookie;
+use crate::shared::jwt::{Claims, KEYS, MfaPendingClaims, TOKEN_TTL, access_token_exp};
+use crate::shared::mail::Mail;
+use crate::shared::session::{
+    RefreshOutcome, create_session, issue_refresh_token, revoke_session, revoke_user_sessions,
+    rotate_refresh_token, session_of_refresh_token,
+};
+use crate::shared::token::{generate_token, hash_token};
+use crate::shared::verification::{
+    VERIFICATION_POLICY, VerifyOutcome, send_verification_mail, verify_email,
+};
+use anyhow::{Context, anyhow};
 use argon2::password_hash::SaltString;
 use argon2::password_hash::rand_core::OsRng;
 use argon2::{Argon2, PasswordHasher, PasswordVerifier};
 use axum::body::Body;
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
 use axum::{
     Extension, Json,
     http::header,
     response::{IntoResponse, Response},
 };
-use jsonwebtoken::encode;
 use serde::Deserialize;
 use serde::Serialize;
 use sqlx::Row;
 use std::sync::Arc;
 
+pub(super) fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::Internal(anyhow!("Failed to hash password: {}", e)))
+}
+
+pub(super) fn verify_password(password: &str, hash: &str) -> Result<(), AppError> {
+    let parsed_hash = argon2::PasswordHash::new(hash).map_err(|_| AuthError::WrongCredentials)?;
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .map_err(|_| AuthError::WrongCredentials.into())
+}
+
+/// Closes the sockets of revoked sessions.
+pub(super) fn broadcast_revoked(state: &AppState, session_ids: Vec<String>) {
+    for session_id in session_ids {
+        let _ = state
+            .ws_sender
+            .send(crate::shared::CanvasDataEvent::SessionRevoked(session_id));
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RegisterPayload {
     pub email: String,
@@ -24,31 +66,68 @@ pub struct RegisterPayload {
     pub display_name: String,
 }
 
+impl Validate for RegisterPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_email(&mut errors, "email", &self.email);
+        check_password(&mut errors, "password", &self.password);
+        check_display_name(&mut errors, "display_name", &self.display_name);
+        errors.into_result()
+    }
+}
+
 pub async fn register(
     state: Extension<Arc<AppState>>,
+    client_ip: ClientIp,
     Json(payload): Json<RegisterPayload>,
-) -> Result<impl IntoResponse, AuthError> {
-    let mut rng = OsRng;
-    let salt = SaltString::generate(&mut rng);
-
-    let argon2 = Argon2::default();
-    let hash = argon2
-        .hash_password(payload.password.as_bytes(), &salt)
-        .unwrap();
-
-    match sqlx::query("INSERT INTO users (email, display_name, password_hash) VALUES ($1, $2, $3)")
-        .bind(&payload.email)
-        .bind(&payload.display_name)
-        .bind(&hash.to_string())
-        .execute(&*state.db)
-        .await
+) -> Result<impl IntoResponse, AppError> {
+    payload.validate()?;
+    // Every registration counts against the IP to slow down account spam
+    let ip_key = format!("register-ip:{}", client_ip);
+    if let Err(retry_after) = state.throttle.attempt(&[&ip_key]).await {
+        return Err(AuthError::TooManyRequests(retry_after).into());
+    }
+
+    let hash = hash_password(&payload.password)?;
+
+    match sqlx::query(
+        "INSERT INTO users (email, display_name, password_hash) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(&payload.email)
+    .bind(&payload.display_name)
+    .bind(hash)
+    .fetch_one(&*state.db)
+    .await
     {
-        Ok(_) => return Ok(StatusCode::CREATED),
-        Err(sqlx::Error::Database(_db_err)) => return Ok(StatusCode::BAD_REQUEST),
-        Err(e) => {
-            tracing::error!("Database error: {:?}", e);
-            return Ok(StatusCode::INTERNAL_SERVER_ERROR);
+        Ok(row) => {
+            let user_id: String = row.try_get("id")?;
+            record(
+                &state.db,
+                AuditEvent::new(AuditAction::Register)
+                    .actor(&user_id)
+                    .target(&user_id)
+                    .ip(client_ip.0)
+                    .after(serde_json::json!({
+                        "email": payload.email,
+                        "display_name": payload.display_name,
+                    })),
+            )
+            .await;
+            if let Err(e) =
+                send_verification_mail(&state.db, &*state.mailer, &user_id, &payload.email).await
+            {
+                tracing::error!(
+                    "Failed to send verification mail to {}: {:?}",
+                    payload.email,
+                    e
+                );
+            }
+            Ok(StatusCode::CREATED)
+        }
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            Err(Conflict::EmailTaken.into())
         }
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -58,62 +137,296 @@ pub struct LoginPayload {
     pub password: String,
 }
 
+impl Validate for LoginPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        // Only presence is checked, accounts from before the current rules must keep working
+        let mut errors = ValidationErrors::default();
+        check_not_empty(&mut errors, "email", &self.email);
+        check_not_empty(&mut errors, "password", &self.password);
+        errors.into_result()
+    }
+}
+
+/// Audits a failed login and records the account lockout it caused, the attempt has already
+/// been counted against the IP and the account. Logins of unknown accounts are audited with
+/// the submitted email as target.
+async fn failed_login(
+    state: &AppState,
+    client_ip: &ClientIp,
+    email: &str,
+    user_id: Option<&str>,
+    account: AttemptState,
+) -> AppError {
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::LoginFailed)
+            .target(user_id.unwrap_or(email))
+            .ip(client_ip.0),
+    )
+    .await;
+    if let Some(locked_until) = account.locked_until {
+        tracing::warn!(
+            "Locked out account {} after {} failures",
+            email,
+            account.failures
+        );
+        let res = sqlx::query(
+            "INSERT INTO account_lockouts (email, ip, failures, locked_until) VALUES ($1, $2, $3, datetime($4, 'unixepoch'))",
+        )
+        .bind(email)
+        .bind(client_ip.0.map(|ip| ip.to_string()))
+        .bind(account.failures as i64)
+        .bind(locked_until as i64)
+        .execute(&*state.db)
+        .await;
+        if let Err(e) = res {
+            tracing::error!("Failed to record lockout of {}: {:?}", email, e);
+        }
+    }
+    AuthError::WrongCredentials.into()
+}
+
 pub async fn login(
     state: Extension<Arc<AppState>>,
+    client_ip: ClientIp,
+    user_agent: UserAgent,
     Json(payload): Json<LoginPayload>,
-) -> Result<impl IntoResponse, AuthError> {
+) -> Result<impl IntoResponse, AppError> {
+    payload.validate()?;
+    let ip_key = format!("login-ip:{}", client_ip);
+    let account_key = format!("login-account:{}", payload.email.to_lowercase());
+    // Counted before the password is checked, so parallel guesses can't exceed the limit
+    let account = match state.throttle.attempt(&[&ip_key, &account_key]).await {
+        Ok(states) => states[1],
+        Err(retry_after) => return Err(AuthError::TooManyRequests(retry_after).into()),
+    };
+
     // Query user by email
-    let row = sqlx::query("SELECT password_hash, display_name,id FROM users WHERE email = $1")
-        .bind(&payload.email)
-        .fetch_optional(&*state.db)
-        .await
-        .unwrap(); // 500 if fail
+    let row = sqlx::query(
+        "SELECT password_hash, display_name, id, email_verified, totp_enabled FROM users WHERE email = $1",
+    )
+    .bind(&payload.email)
+    .fetch_optional(&*state.db)
+    .await?;
 
     let row = match row {
         Some(row) => row,
-        None => return Err(AuthError::WrongCredentials),
+        None => {
+            return Err(failed_login(&state, &client_ip, &payload.email, None, account).await);
+        }
     };
 
-    let user_id: String = row.try_get("id").unwrap();
-    let hash: String = row
-        .try_get("password_hash")
-        .map_err(|_| AuthError::WrongCredentials)?;
-    let display_name: String = row.try_get("display_name").unwrap();
+    let user_id: String = row.try_get("id")?;
+    let hash: String = row.try_get("password_hash")?;
+    let display_name: String = row.try_get("display_name")?;
 
     // Verify password
-    let parsed_hash = argon2::PasswordHash::new(&hash).map_err(|_| AuthError::WrongCredentials)?;
-    Argon2::default()
-        .verify_password(payload.password.as_bytes(), &parsed_hash)
-        .map_err(|_| AuthError::WrongCredentials)?;
+    if verify_password(&payload.password, &hash).is_err() {
+        return Err(
+            failed_login(&state, &client_ip, &payload.email, Some(&user_id), account).await,
+        );
+    }
+    state.throttle.reset(&account_key).await;
+    state.throttle.release(&ip_key).await;
+    let email_verified: bool = row.try_get("email_verified")?;
+    if VERIFICATION_POLICY.login && !email_verified {
+        return Err(AuthError::EmailNotVerified.into());
+    }
+
+    // With a second factor the password only earns a short-lived pending token
+    let totp_enabled: bool = row.try_get("totp_enabled")?;
+    if totp_enabled {
+        let pending = MfaPendingClaims {
+            mfa_user_id: user_id,
+            exp: (jsonwebtoken::get_current_timestamp() + TOKEN_TTL.mfa_pending) as usize,
+        };
+        let mfa_token = KEYS.encode(&pending)?;
+        return Ok(
+            Json(serde_json::json!({ "mfa_required": true, "mfa_token": mfa_token }))
+                .into_response(),
+        );
+    }
 
+    start_session(
+        &state,
+        &user_id,
+        &payload.email,
+        &display_name,
+        &client_ip,
+        &user_agent,
+    )
+    .await
+}
+
+/// Creates a new session for an authenticated user and sets its cookies.
+pub(super) async fn start_session(
+    state: &AppState,
+    user_id: &str,
+    email: &str,
+    display_name: &str,
+    client_ip: &ClientIp,
+    user_agent: &UserAgent,
+) -> Result<Response, AppError> {
+    let status = get_account_status(&state.db, user_id).await?;
+    if status.is_some_and(|status| status.disabled) {
+        return Err(AuthError::AccountDisabled.into());
+    }
+    let jti = create_session(
+        &state.db,
+        user_id,
+        user_agent.0.as_deref(),
+        client_ip.0.map(|ip| ip.to_string()).as_deref(),
+    )
+    .await?;
+    let refresh_token = issue_refresh_token(&state.db, &jti).await?;
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::Login)
+            .actor(user_id)
+            .target(user_id)
+            .ip(client_ip.0),
+    )
+    .await;
     let claims = Claims {
-        email: payload.email.clone(),
-        exp: 2000000000,
-        display_name: display_name.clone(),
-        id: user_id.clone(),
+        email: email.to_string(),
+        exp: access_token_exp(),
+        display_name: display_name.to_string(),
+        id: user_id.to_string(),
+        jti,
+        scope: None,
     };
-    let token = encode(&jsonwebtoken::Header::default(), &claims, &KEYS.encoding)
-        .map_err(|_| AuthError::TokenCreation)?;
-    let cookie = format!("access_token={}; HttpOnly; Path=/; SameSite=Lax", token);
+    session_response(&claims, &refresh_token)
+}
+
+/// Builds the login/refresh response carrying a fresh access token and the rotated refresh token.
+fn session_response(claims: &Claims, refresh_token: &str) -> Result<Response, AppError> {
+    let token = KEYS.encode(claims)?;
     let mut response = Response::new(Body::from(
-        serde_json::json!({"email": payload.email, "display_name": display_name}).to_string(),
+        serde_json::json!({"email": claims.email, "display_name": claims.display_name}).to_string(),
     ));
     response
         .headers_mut()
-        .insert(header::SET_COOKIE, cookie.parse().unwrap());
+        .append(header::SET_COOKIE, Cookie::Access.set(&token));
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, Cookie::Refresh.set(refresh_token));
     Ok(response)
 }
 
+pub async fn refresh(
+    state: Extension<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let refresh_token = headers
+        .get(header::COOKIE)
+        .and_then(|c| c.to_str().ok())
+        .and_then(|c| Cookie::Refresh.get(c))
+        .ok_or(AuthError::MissingCredentials)?;
+    let outcome = rotate_refresh_token(&state.db, refresh_token).await?;
+    let (session_id, user_id, token) = match outcome {
+        RefreshOutcome::Rotated {
+            session_id,
+            user_id,
+            token,
+        } => (session_id, user_id, token),
+        RefreshOutcome::Reused { session_id } => {
+            // A stolen refresh token was used, kill the session for both parties
+            tracing::warn!("Refresh token reuse detected for session {}", session_id);
+            let _ = state
+                .ws_sender
+                .send(crate::shared::CanvasDataEvent::SessionRevoked(session_id));
+            return Err(AuthError::RevokedToken.into());
+        }
+        RefreshOutcome::Invalid => return Err(AuthError::InvalidToken.into()),
+    };
+    let row = sqlx::query("SELECT email, display_name FROM users WHERE id = $1")
+        .bind(&user_id)
+        .fetch_optional(&*state.db)
+        .await?
+        .ok_or(AuthError::InvalidToken)?;
+    let claims = Claims {
+        id: user_id,
+        email: row.try_get("email")?,
+        exp: access_token_exp(),
+        display_name: row.try_get("display_name")?,
+        jti: session_id,
+        scope: None,
+    };
+    session_response(&claims, &token)
+}
+
+/// Public keys for services verifying our tokens, see `Keyring::jwks`.
+pub async fn jwks() -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(KEYS.jwks()),
+    )
+}
+
+/// Returns the double-submit token for clients that can't read the `csrf_token` cookie.
+pub async fn csrf(Extension(token): Extension<CsrfToken>) -> impl IntoResponse {
+    Json(serde_json::json!({ "csrf_token": token.0 }))
+}
+
 pub async fn me(claims: Claims) -> impl IntoResponse {
     axum::Json(claims)
 }
 
-pub async fn logout() -> impl IntoResponse {
-    let cookie = "access_token=; HttpOnly; Path=/; SameSite=Lax; Max-Age=0";
+pub async fn logout(
+    state: Extension<Arc<AppState>>,
+    claims: Result<Claims, AppError>,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    // Fall back to the refresh token if the access token already expired
+    let jti = match claims {
+        Ok(claims) => Some(claims.jti),
+        Err(_) => match headers
+            .get(header::COOKIE)
+            .and_then(|c| c.to_str().ok())
+            .and_then(|c| Cookie::Refresh.get(c))
+        {
+            Some(token) => session_of_refresh_token(&state.db, token)
+                .await
+                .unwrap_or_default(),
+            None => None,
+        },
+    };
+    // Revoke the session so copies of the token stop working, then close its sockets
+    if let Some(jti) = jti {
+        match revoke_session(&state.db, &jti).await {
+            Ok(_) => {
+                let user_id = sqlx::query("SELECT user_id FROM sessions WHERE id = $1")
+                    .bind(&jti)
+                    .fetch_optional(&*state.db)
+                    .await
+                    .ok()
+                    .flatten()
+                    .and_then(|row| row.try_get::<String, _>("user_id").ok());
+                if let Some(user_id) = user_id {
+                    record(
+                        &state.db,
+                        AuditEvent::new(AuditAction::Logout)
+                            .actor(&user_id)
+                            .target(&user_id)
+                            .ip(client_ip.0),
+                    )
+                    .await;
+                }
+                let _ = state
+                    .ws_sender
+                    .send(crate::shared::CanvasDataEvent::SessionRevoked(jti));
+            }
+            Err(e) => tracing::error!("Failed to revoke session {}: {:?}", jti, e),
+        }
+    }
     let mut response = Response::new(Body::from("Logged out"));
     response
         .headers_mut()
-        .insert(header::SET_COOKIE, cookie.parse().unwrap());
+        .append(header::SET_COOKIE, Cookie::Access.clear());
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, Cookie::Refresh.clear());
     response
 }
 
@@ -123,66 +436,677 @@ pub struct UpdateUserPayload {
     pub display_name: Option<String>,
 }
 
+impl Validate for UpdateUserPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(email) = &self.email {
+            check_email(&mut errors, "email", email);
+        }
+        if let Some(display_name) = &self.display_name {
+            check_display_name(&mut errors, "display_name", display_name);
+        }
+        errors.into_result()
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct UserResponse {
     pub id: String,
     pub email: String,
     pub display_name: String,
+    /// New address waiting for confirmation, the old one stays active until then
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_email: Option<String>,
 }
 
 pub async fn update_user(
     state: Extension<Arc<AppState>>,
     claims: Claims,
+    client_ip: ClientIp,
     axum::extract::Path(user_id): axum::extract::Path<String>,
     Json(payload): Json<UpdateUserPayload>,
-) -> Result<impl IntoResponse, AuthError> {
+) -> Result<impl IntoResponse, AppError> {
     if claims.id != user_id {
-        return Err(AuthError::WrongCredentials);
+        return Err(AuthError::WrongCredentials.into());
     }
-    let mut tx = state.db.begin().await.unwrap();
-    if let Some(email) = payload.email.as_ref() {
-        sqlx::query("UPDATE users SET email = ? WHERE id = ?")
+    payload.validate()?;
+    // An email change only applies once the new address is confirmed
+    let pending_email = payload.email.filter(|email| *email != claims.email);
+    if let Some(email) = pending_email.as_ref() {
+        let taken = sqlx::query("SELECT 1 FROM users WHERE email = $1")
             .bind(email)
-            .bind(&user_id)
-            .execute(&mut *tx)
+            .fetch_optional(&*state.db)
+            .await?;
+        if taken.is_some() {
+            return Err(Conflict::EmailTaken.into());
+        }
+        send_verification_mail(&state.db, &*state.mailer, &user_id, email)
             .await
-            .unwrap();
+            .with_context(|| format!("Failed to send verification mail to {}", email))?;
     }
+    let previous_display_name: String = sqlx::query("SELECT display_name FROM users WHERE id = ?")
+        .bind(&user_id)
+        .fetch_one(&*state.db)
+        .await?
+        .try_get("display_name")?;
+    let mut tx = state.db.begin().await?;
     if let Some(display_name) = payload.display_name.as_ref() {
         sqlx::query("UPDATE users SET display_name = ? WHERE id = ?")
             .bind(display_name)
             .bind(&user_id)
             .execute(&mut *tx)
-            .await
-            .unwrap();
+            .await?;
     }
-    tx.commit().await.unwrap();
+    tx.commit().await?;
     // Fetch updated user
     let row = sqlx::query("SELECT id, email, display_name FROM users WHERE id = ?")
         .bind(&user_id)
         .fetch_one(&*state.db)
-        .await
-        .unwrap();
+        .await?;
     let user = UserResponse {
-        id: row.try_get("id").unwrap(),
-        email: row.try_get("email").unwrap(),
-        display_name: row.try_get("display_name").unwrap(),
+        id: row.try_get("id")?,
+        email: row.try_get("email")?,
+        display_name: row.try_get("display_name")?,
+        pending_email,
     };
+    if user.display_name != previous_display_name || user.pending_email.is_some() {
+        record(
+            &state.db,
+            AuditEvent::new(AuditAction::ProfileUpdated)
+                .actor(&claims.id)
+                .target(&user_id)
+                .ip(client_ip.0)
+                .change(
+                    serde_json::json!({
+                        "email": claims.email,
+                        "display_name": previous_display_name,
+                    }),
+                    serde_json::json!({
+                        "email": user.email,
+                        "pending_email": user.pending_email,
+                        "display_name": user.display_name,
+                    }),
+                ),
+        )
+        .await;
+    }
     // Create new claims and JWT
     let claims = Claims {
         id: user.id.clone(),
         email: user.email.clone(),
-        exp: 2000000000,
+        exp: access_token_exp(),
         display_name: user.display_name.clone(),
+        jti: claims.jti,
+        scope: None,
     };
-    let token = encode(&jsonwebtoken::Header::default(), &claims, &KEYS.encoding)
-        .map_err(|_| AuthError::TokenCreation)?;
-    let cookie = format!("access_token={}; HttpOnly; Path=/; SameSite=Lax", token);
-    let mut response = axum::response::Response::new(axum::body::Body::from(
-        serde_json::to_string(&user).unwrap(),
-    ));
+    let token = KEYS.encode(&claims)?;
+    let mut response = Json(user).into_response();
     response
         .headers_mut()
-        .insert(header::SET_COOKIE, cookie.parse().unwrap());
+        .insert(header::SET_COOKIE, Cookie::Access.set(&token));
     Ok(response)
 }
+
+pub async fn verify(
+    state: Extension<Arc<AppState>>,
+    axum::extract::Path(token): axum::extract::Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let outcome = verify_email(&state.db, &token).await?;
+    match outcome {
+        VerifyOutcome::Verified { email } => Ok(Json(serde_json::json!({ "email": email }))),
+        VerifyOutcome::EmailTaken => Err(Conflict::EmailTaken.into()),
+        VerifyOutcome::Invalid => Err(AuthError::InvalidToken.into()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordPayload {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+impl Validate for ChangePasswordPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_not_empty(&mut errors, "old_password", &self.old_password);
+        check_password(&mut errors, "new_password", &self.new_password);
+        errors.into_result()
+    }
+}
+
+pub async fn change_password(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Json(payload): Json<ChangePasswordPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    payload.validate()?;
+    let row = sqlx::query("SELECT password_hash FROM users WHERE id = $1")
+        .bind(&claims.id)
+        .fetch_one(&*state.db)
+        .await?;
+    let hash: String = row.try_get("password_hash")?;
+    verify_password(&payload.old_password, &hash)?;
+
+    let new_hash = hash_password(&payload.new_password)?;
+    sqlx::query("UPDATE users SET password_hash = $1, updated_at = datetime('now') WHERE id = $2")
+        .bind(new_hash)
+        .bind(&claims.id)
+        .execute(&*state.db)
+        .await?;
+
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::PasswordChanged)
+            .actor(&claims.id)
+            .target(&claims.id)
+            .ip(client_ip.0),
+    )
+    .await;
+
+    // Keep the current session, every other device has to log in again
+    let revoked = revoke_user_sessions(&state.db, &claims.id, Some(&claims.jti)).await?;
+    broadcast_revoked(&state, revoked);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordPayload {
+    pub email: String,
+}
+
+pub async fn forgot_password(
+    state: Extension<Arc<AppState>>,
+    client_ip: ClientIp,
+    Json(payload): Json<ForgotPasswordPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    // Every request counts, so nobody can flood an inbox with reset mails. Unknown addresses
+    // are counted alike and don't stand out.
+    let ip_key = format!("forgot-ip:{}", client_ip);
+    let email_key = format!("forgot:{}", payload.email.to_lowercase());
+    if let Err(retry_after) = state.throttle.attempt(&[&ip_key, &email_key]).await {
+        return Err(AuthError::TooManyRequests(retry_after).into());
+    }
+    // Always answer the same way so the endpoint can't be used to probe for accounts
+    if let Err(e) = send_password_reset(&state, &payload.email).await {
+        tracing::error!(
+            "Failed to send password reset for {}: {:?}",
+            payload.email,
+            e
+        );
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub(super) async fn send_password_reset(state: &AppState, email: &str) -> anyhow::Result<()> {
+    let Some(row) = sqlx::query("SELECT id FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(&*state.db)
+        .await?
+    else {
+        return Ok(());
+    };
+    let user_id: String = row.try_get("id")?;
+    let token = generate_token();
+    sqlx::query(
+        "INSERT INTO password_resets (token_hash, user_id, expires_at) VALUES ($1, $2, datetime('now', $3))",
+    )
+    .bind(hash_token(&token))
+    .bind(&user_id)
+    .bind(format!("+{} seconds", TOKEN_TTL.password_reset))
+    .execute(&*state.db)
+    .await?;
+    let public_url = std::env::var("PUBLIC_URL").unwrap_or("http://localhost:8000".to_string());
+    state
+        .mailer
+        .send(Mail {
+            to: email.to_string(),
+            subject: "Password reset".to_string(),
+            body: format!(
+                "Use the following link to reset your password. It is valid for {} minutes.\r\n\r\n{}/reset-password?token={}",
+                TOKEN_TTL.password_reset / 60,
+                public_url,
+                token
+            ),
+        })
+        .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordPayload {
+    pub token: String,
+    pub new_password: String,
+}
+
+impl Validate for ResetPasswordPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_not_empty(&mut errors, "token", &self.token);
+        check_password(&mut errors, "new_password", &self.new_password);
+        errors.into_result()
+    }
+}
+
+pub async fn reset_password(
+    state: Extension<Arc<AppState>>,
+    client_ip: ClientIp,
+    Json(payload): Json<ResetPasswordPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    payload.validate()?;
+    let new_hash = hash_password(&payload.new_password)?;
+    let mut tx = state.db.begin().await?;
+    // Consuming the token and setting the password happen atomically
+    let row = sqlx::query(
+        "UPDATE password_resets SET used_at = datetime('now') WHERE token_hash = $1 AND used_at IS NULL AND expires_at > datetime('now') RETURNING user_id",
+    )
+    .bind(hash_token(&payload.token))
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AuthError::InvalidToken)?;
+    let user_id: String = row.try_get("user_id")?;
+    sqlx::query("UPDATE users SET password_hash = $1, updated_at = datetime('now') WHERE id = $2")
+        .bind(new_hash)
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    // The reset link stands in for the user's login
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::PasswordReset)
+            .actor(&user_id)
+            .target(&user_id)
+            .ip(client_ip.0),
+    )
+    .await;
+
+    let revoked = revoke_user_sessions(&state.db, &user_id, None)
+        .await
+        .with_context(|| format!("Failed to revoke sessions of {}", user_id))?;
+    broadcast_revoked(&state, revoked);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axum_app::test_support::{PASSWORD, TestApp};
+    use crate::axum_app::throttle::THROTTLE;
+    use crate::shared::admin::bootstrap_admins;
+    use crate::shared::session::{create_session, is_session_active, issue_refresh_token};
+    use axum::body::Body;
+    use axum::extract::ConnectInfo;
+    use axum::http::{Method, Request, StatusCode, header};
+    use serde_json::json;
+    use std::net::SocketAddr;
+
+    /// Token of the reset link in the last mail
+    fn reset_token(app: &TestApp) -> String {
+        let mail = app.outbox().pop().expect("no mail was sent");
+        let (_, link) = mail
+            .split_once("/reset-password?token=")
+            .expect("mail has no reset link");
+        link.trim().to_string()
+    }
+
+    async fn login_status(app: &TestApp, email: &str, password: &str) -> StatusCode {
+        app.request(
+            Method::POST,
+            "/api/auth/login",
+            None,
+            Some(json!({ "email": email, "password": password })),
+        )
+        .await
+        .status
+    }
+
+    async fn is_admin(app: &TestApp, email: &str) -> bool {
+        sqlx::query_scalar("SELECT is_admin FROM users WHERE email = $1")
+            .bind(email)
+            .fetch_one(app.db())
+            .await
+            .unwrap()
+    }
+
+    /// Action and target of the audit entries, oldest first
+    async fn audit_entries(app: &TestApp) -> Vec<(String, Option<String>)> {
+        sqlx::query_as("SELECT action, target FROM audit_log ORDER BY id")
+            .fetch_all(app.db())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_logins_are_audited_with_the_user_id_if_known() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        login_status(&app, "alice@example.com", "wrong").await;
+        login_status(&app, "nobody@example.com", "wrong").await;
+        assert_eq!(
+            audit_entries(&app).await,
+            [
+                ("login_failed".to_string(), Some(user_id)),
+                (
+                    "login_failed".to_string(),
+                    Some("nobody@example.com".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn password_change_and_session_revocations_are_audited() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let other = create_session(app.db(), &user_id, None, None)
+            .await
+            .unwrap();
+        let cookie = app.login(&user_id).await;
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/password",
+                Some(&cookie),
+                Some(json!({ "old_password": PASSWORD, "new_password": "another horse battery" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        let third = create_session(app.db(), &user_id, None, None)
+            .await
+            .unwrap();
+        let res = app
+            .request(
+                Method::DELETE,
+                &format!("/api/auth/sessions/{}", third),
+                Some(&cookie),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert!(!is_session_active(app.db(), &other).await.unwrap());
+
+        let actions: Vec<String> = audit_entries(&app)
+            .await
+            .into_iter()
+            .map(|(action, target)| {
+                assert_eq!(target.as_deref(), Some(user_id.as_str()));
+                action
+            })
+            .collect();
+        assert_eq!(actions, ["password_changed", "session_revoked"]);
+    }
+
+    #[tokio::test]
+    async fn personal_tokens_are_audited_without_their_value() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let cookie = app.login(&user_id).await;
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/tokens",
+                Some(&cookie),
+                Some(json!({ "name": " CI ", "scope": "read" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let created = res.json();
+        let res = app
+            .request(
+                Method::DELETE,
+                &format!("/api/auth/tokens/{}", created["id"].as_str().unwrap()),
+                Some(&cookie),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        let entries: Vec<(String, Option<String>, String)> =
+            sqlx::query_as("SELECT action, target, after FROM audit_log ORDER BY id")
+                .fetch_all(app.db())
+                .await
+                .unwrap();
+        let actions: Vec<&str> = entries.iter().map(|(action, ..)| action.as_str()).collect();
+        assert_eq!(actions, ["token_created", "token_revoked"]);
+        let (_, target, after) = &entries[0];
+        assert_eq!(target.as_deref(), Some(user_id.as_str()));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(after).unwrap(),
+            json!({
+                "token": created["id"],
+                "name": "CI",
+                "scope": "read",
+                "expires_in_days": null,
+            })
+        );
+        assert!(!after.contains(created["token"].as_str().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn bootstrap_admin_needs_a_verified_address() {
+        let app = TestApp::new().await;
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/register",
+                None,
+                Some(json!({
+                    "email": "admin@example.com",
+                    "display_name": "Admin",
+                    "password": PASSWORD,
+                })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert!(!is_admin(&app, "admin@example.com").await);
+        bootstrap_admins(app.db()).await.unwrap();
+        assert!(!is_admin(&app, "admin@example.com").await);
+
+        let mail = app.outbox().pop().unwrap();
+        let (_, token) = mail.split_once("/api/auth/verify/").unwrap();
+        let res = app
+            .request(
+                Method::GET,
+                &format!("/api/auth/verify/{}", token.trim()),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(is_admin(&app, "admin@example.com").await);
+    }
+
+    #[tokio::test]
+    async fn bootstrap_promotes_verified_accounts() {
+        let app = TestApp::new().await;
+        app.create_user("admin@example.com").await;
+        app.create_user("alice@example.com").await;
+        bootstrap_admins(app.db()).await.unwrap();
+        assert!(is_admin(&app, "admin@example.com").await);
+        assert!(!is_admin(&app, "alice@example.com").await);
+    }
+
+    #[tokio::test]
+    async fn login_accepts_addresses_from_before_validation() {
+        let app = TestApp::new().await;
+        app.create_user("legacy user@localhost").await;
+        assert_eq!(
+            login_status(&app, "legacy user@localhost", PASSWORD).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            login_status(&app, "", PASSWORD).await,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[tokio::test]
+    async fn password_reset_via_mailed_link() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let session_id = create_session(app.db(), &user_id, None, None)
+            .await
+            .unwrap();
+
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/password/forgot",
+                None,
+                Some(json!({ "email": "alice@example.com" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+        let mail = app.outbox().pop().unwrap();
+        assert!(mail.starts_with("To: alice@example.com\r\n"));
+        let token = reset_token(&app);
+
+        let reset = json!({ "token": token, "new_password": "Another-Secret-42" });
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/password/reset",
+                None,
+                Some(reset.clone()),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert!(!is_session_active(app.db(), &session_id).await.unwrap());
+        assert_eq!(
+            login_status(&app, "alice@example.com", PASSWORD).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            login_status(&app, "alice@example.com", "Another-Secret-42").await,
+            StatusCode::OK
+        );
+
+        // The link only works once
+        let res = app
+            .request(Method::POST, "/api/auth/password/reset", None, Some(reset))
+            .await;
+        assert_eq!(res.json()["code"], "invalid_token");
+    }
+
+    #[tokio::test]
+    async fn password_reset_rejects_expired_tokens_and_weak_passwords() {
+        let app = TestApp::new().await;
+        app.create_user("alice@example.com").await;
+        app.request(
+            Method::POST,
+            "/api/auth/password/forgot",
+            None,
+            Some(json!({ "email": "alice@example.com" })),
+        )
+        .await;
+        let token = reset_token(&app);
+
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/password/reset",
+                None,
+                Some(json!({ "token": token, "new_password": "short" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(res.json()["fields"]["new_password"].is_array());
+
+        sqlx::query("UPDATE password_resets SET expires_at = datetime('now', '-1 seconds')")
+            .execute(app.db())
+            .await
+            .unwrap();
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/password/reset",
+                None,
+                Some(json!({ "token": token, "new_password": "Another-Secret-42" })),
+            )
+            .await;
+        assert_eq!(res.json()["code"], "invalid_token");
+    }
+
+    #[tokio::test]
+    async fn forgot_password_does_not_reveal_unknown_accounts() {
+        let app = TestApp::new().await;
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/password/forgot",
+                None,
+                Some(json!({ "email": "nobody@example.com" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+        assert!(app.outbox().is_empty());
+    }
+
+    #[tokio::test]
+    async fn forgot_password_is_throttled_per_address_and_ip() {
+        let app = TestApp::new().await;
+        app.create_user("alice@example.com").await;
+        let forgot = |email: &str, ip: [u8; 4]| {
+            app.send(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/auth/password/forgot")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .extension(ConnectInfo(SocketAddr::from((ip, 50000))))
+                    .body(Body::from(json!({ "email": email }).to_string()))
+                    .unwrap(),
+            )
+        };
+
+        for _ in 0..THROTTLE.max_attempts {
+            let res = forgot("alice@example.com", [10, 0, 0, 1]).await;
+            assert_eq!(res.status, StatusCode::ACCEPTED);
+        }
+        assert_eq!(app.outbox().len(), THROTTLE.max_attempts as usize);
+        // Neither another address from the same IP nor the same address from elsewhere
+        for (email, ip) in [
+            ("bob@example.com", [10, 0, 0, 1]),
+            ("alice@example.com", [10, 0, 0, 2]),
+        ] {
+            let res = forgot(email, ip).await;
+            assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+        }
+        assert_eq!(app.outbox().len(), THROTTLE.max_attempts as usize);
+
+        let res = forgot("bob@example.com", [10, 0, 0, 2]).await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_the_cookie_and_rejects_reuse() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let session_id = create_session(app.db(), &user_id, None, None)
+            .await
+            .unwrap();
+        let token = issue_refresh_token(app.db(), &session_id).await.unwrap();
+        let cookie = format!("refresh_token={}", token);
+
+        let res = app
+            .request(Method::POST, "/api/auth/refresh", Some(&cookie), None)
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        let next = res.cookie("refresh_token").unwrap();
+        assert_ne!(next, token);
+        assert!(res.cookie("access_token").is_some());
+
+        let res = app
+            .request(Method::POST, "/api/auth/refresh", Some(&cookie), None)
+            .await;
+        assert_eq!(res.json()["code"], "token_revoked");
+        // Reuse revoked the session, so the rotated token is dead as well
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/refresh",
+                Some(&format!("refresh_token={}", next)),
+                None,
+            )
+            .await;
+        assert_eq!(res.json()["code"], "invalid_token");
+    }
+}
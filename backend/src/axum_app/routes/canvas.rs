@@ -1,11 +1,26 @@
 use crate::axum_app::axum::AppState;
+use crate::axum_app::error::AppError;
+use crate::axum_app::routes::admin::AuditQuery;
+use crate::axum_app::routes::events::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::axum_app::transformers::ClientIp;
+use crate::axum_app::validation::{
+    MAX_DESCRIPTION_LENGTH, MAX_TITLE_LENGTH, Validate, ValidationErrors, check_max_length,
+};
+use crate::shared::CanvasMetadata;
+use crate::shared::audit::{AuditAction, AuditEvent, list_entries, record};
+use crate::shared::cookies::Cookie;
 use crate::shared::jwt::{Claims, KEYS};
+use crate::shared::verification::VERIFICATION_POLICY;
+use anyhow::Context;
 use axum::body::Body;
-use axum::http::StatusCode;
+use axum::extract::Query;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
 use axum::{Extension, http::header, response::Response};
 use axum::{Json, extract::Path};
-use jsonwebtoken::encode;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use sqlx::Row;
 use std::sync::Arc;
 use tracing::*;
@@ -25,7 +40,18 @@ pub struct UserRight {
 #[derive(Serialize)]
 pub struct CanvasRightsModerated {
     pub canvas_id: String,
+    pub title: String,
+    pub description: String,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    /// User id of the creator, missing for deleted accounts
+    pub created_by: Option<String>,
+    /// Source canvas of a fork, missing once the source is deleted
+    pub forked_from: Option<String>,
+    /// Sequence number of the last event copied from the source
+    pub forked_at_seq: Option<i64>,
     pub moderated: bool,
+    pub public: bool,
     pub right: String,
     pub rights: Option<Vec<UserRight>>,
 }
@@ -35,26 +61,117 @@ pub struct ModeratedPayload {
     pub moderated: bool,
 }
 
+#[derive(Deserialize)]
+pub struct PublicPayload {
+    pub public: bool,
+}
+
+/// What visitors see of a public canvas, without members, rights or authors
+#[derive(Serialize)]
+pub struct PublicCanvas {
+    pub canvas_id: String,
+    pub title: String,
+    pub description: String,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub events: Vec<serde_json::Value>,
+    /// Cursor for the next page of events, missing on the last one
+    pub next_after: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct PublicCanvasQuery {
+    /// Sequence number of the last event already seen
+    pub after: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateCanvasPayload {
+    pub title: Option<String>,
+}
+
+impl Validate for CreateCanvasPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(title) = &self.title {
+            check_max_length(&mut errors, "title", title, MAX_TITLE_LENGTH);
+        }
+        errors.into_result()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ForkCanvasPayload {
+    /// Defaults to the title of the source
+    pub title: Option<String>,
+    /// Sequence number of the last event to copy, all events by default
+    pub until: Option<i64>,
+}
+
+impl Validate for ForkCanvasPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(title) = &self.title {
+            check_max_length(&mut errors, "title", title, MAX_TITLE_LENGTH);
+        }
+        errors.into_result()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateCanvasPayload {
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Validate for UpdateCanvasPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(title) = &self.title {
+            check_max_length(&mut errors, "title", title, MAX_TITLE_LENGTH);
+        }
+        if let Some(description) = &self.description {
+            check_max_length(
+                &mut errors,
+                "description",
+                description,
+                MAX_DESCRIPTION_LENGTH,
+            );
+        }
+        errors.into_result()
+    }
+}
+
 pub async fn create_canvas(
     state: Extension<Arc<AppState>>,
     claims: Claims,
-) -> Result<impl axum::response::IntoResponse, StatusCode> {
+    payload: Option<Json<CreateCanvasPayload>>,
+) -> Result<impl axum::response::IntoResponse, AppError> {
+    // The body is optional, older clients create untitled canvases
+    let title = match payload {
+        Some(Json(payload)) => {
+            payload.validate()?;
+            payload.title.unwrap_or_default()
+        }
+        None => String::new(),
+    };
     // Insert new canvas
-    let row = sqlx::query("INSERT INTO canvas DEFAULT VALUES RETURNING id")
-        .fetch_one(&*state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let canvas_id: String = row
-        .try_get("id")
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let row = sqlx::query(
+        "INSERT INTO canvas (title, created_by, created_at, updated_at) VALUES ($1, $2, datetime('now'), datetime('now')) RETURNING id",
+    )
+    .bind(&title)
+    .bind(&claims.id)
+    .fetch_one(&*state.db)
+    .await?;
+    let canvas_id: String = row.try_get("id")?;
     // Insert into user_canvas as owner
     sqlx::query("INSERT INTO user_canvas (user_id, canvas_id, right) VALUES ($1, $2, $3)")
         .bind(&claims.id)
         .bind(&canvas_id)
         .bind("O")
         .execute(&*state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
     // Add the new canvas to the claims in memory
     let response = Response::new(Body::from(
         serde_json::json!({ "id": canvas_id }).to_string(),
@@ -62,83 +179,167 @@ pub async fn create_canvas(
     Ok(response)
 }
 
+/// Copies the history of a canvas, up to `until` if given, into a new canvas owned by the
+/// caller. Any right on the source is enough.
+pub async fn fork_canvas(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    Path(canvas_id): Path<String>,
+    payload: Option<Json<ForkCanvasPayload>>,
+) -> Result<impl axum::response::IntoResponse, AppError> {
+    member_right(&state, &canvas_id, &claims.id).await?;
+    let (title, until) = match payload {
+        Some(Json(payload)) => {
+            payload.validate()?;
+            (payload.title, payload.until)
+        }
+        None => (None, None),
+    };
+    let until = until.unwrap_or(i64::MAX);
+
+    let mut tx = state.db.begin().await?;
+    let fork_id: String = sqlx::query(
+        "INSERT INTO canvas (title, description, created_by, created_at, updated_at, forked_from) SELECT coalesce($1, title), description, $2, datetime('now'), datetime('now'), id FROM canvas WHERE id = $3 RETURNING id",
+    )
+    .bind(&title)
+    .bind(&claims.id)
+    .bind(&canvas_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound)?
+    .try_get("id")?;
+    // Stored events carry the id of their canvas, it is rewritten to the fork's
+    sqlx::query(
+        "INSERT INTO canvas_events (canvas_id, events, event_type, author_id, created_at) SELECT $1, CASE WHEN json_valid(events) THEN json_set(events, '$.canvas_id', $1) ELSE events END, event_type, author_id, created_at FROM canvas_events WHERE canvas_id = $2 AND seq <= $3 ORDER BY seq",
+    )
+    .bind(&fork_id)
+    .bind(&canvas_id)
+    .bind(until)
+    .execute(&mut *tx)
+    .await?;
+    // 0 if no event was copied, matching the `after` cursor of the events endpoint
+    let forked_at_seq: i64 = sqlx::query(
+        "UPDATE canvas SET forked_at_seq = (SELECT coalesce(max(seq), 0) FROM canvas_events WHERE canvas_id = $1 AND seq <= $2) WHERE id = $3 RETURNING forked_at_seq",
+    )
+    .bind(&canvas_id)
+    .bind(until)
+    .bind(&fork_id)
+    .fetch_one(&mut *tx)
+    .await?
+    .try_get("forked_at_seq")?;
+    sqlx::query("INSERT INTO user_canvas (user_id, canvas_id, right) VALUES ($1, $2, 'O')")
+        .bind(&claims.id)
+        .bind(&fork_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    info!(
+        "Forked canvas {} at event {} into {}",
+        canvas_id, forked_at_seq, fork_id
+    );
+    Ok(Json(json!({
+        "id": fork_id,
+        "forked_from": canvas_id,
+        "forked_at_seq": forked_at_seq,
+    })))
+}
+
 pub async fn change_canvas_right(
     state: Extension<Arc<AppState>>,
     claims: Claims,
+    client_ip: ClientIp,
     Path(canvas_id): Path<String>,
     Json(payload): Json<ChangeRight>,
-) -> Result<impl axum::response::IntoResponse, StatusCode> {
-    // Look up user_id by email
-    let user_row = sqlx::query("SELECT id FROM users WHERE email = $1")
-        .bind(&payload.email)
-        .fetch_one(&*state.db)
-        .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let user_id: String = user_row
-        .try_get("id")
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> Result<impl axum::response::IntoResponse, AppError> {
+    // An empty or "null" right removes the membership
+    let new_right = payload
+        .right
+        .as_deref()
+        .filter(|right| !right.is_empty() && *right != "null");
     // Fetch current user's right for this canvas from DB
     let my_right_row =
         sqlx::query("SELECT right FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
             .bind(&claims.id)
             .bind(&canvas_id)
             .fetch_optional(&*state.db)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            .await?;
     let my_right = my_right_row
-        .and_then(|row| row.try_get::<String, _>("right").ok())
+        .map(|row| row.try_get::<String, _>("right"))
+        .transpose()?
         .unwrap_or_default();
     let allowed = match my_right.as_str() {
-        "O" => true,                                  // Owner can assign any right
-        "M" => payload.right.as_deref() != Some("O"), // Moderator can't assign O
+        "O" => true,                   // Owner can assign any right
+        "M" => new_right != Some("O"), // Moderator can't assign O
         _ => false,
     };
+    // Checked before the lookup, so only members who may change rights learn which
+    // addresses are registered
     if !allowed {
-        return Err(StatusCode::FORBIDDEN);
+        return Err(AppError::Forbidden);
     }
-    // Update or insert/remove right for the user
-    let remove_right = match &payload.right {
-        None => true,
-        Some(r) if r.is_empty() || r == "null" => true,
-        _ => false,
-    };
-    if remove_right {
-        // Remove right
-        let res = sqlx::query("DELETE FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
+    // Look up user_id by email
+    let user_row = sqlx::query("SELECT id, email_verified FROM users WHERE email = $1")
+        .bind(&payload.email)
+        .fetch_optional(&*state.db)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let user_id: String = user_row.try_get("id")?;
+    let email_verified: bool = user_row.try_get("email_verified")?;
+    if new_right.is_some() && VERIFICATION_POLICY.canvas_rights && !email_verified {
+        // Rights can only be granted to confirmed addresses
+        return Err(AppError::Forbidden);
+    }
+    let previous_right: Option<String> =
+        sqlx::query("SELECT right FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
             .bind(&user_id)
             .bind(&canvas_id)
-            .execute(&*state.db)
-            .await;
-        if res.is_err() {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            .fetch_optional(&*state.db)
+            .await?
+            .map(|row| row.try_get("right"))
+            .transpose()?;
+    match new_right {
+        None => {
+            // Remove right
+            sqlx::query("DELETE FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
+                .bind(&user_id)
+                .bind(&canvas_id)
+                .execute(&*state.db)
+                .await?;
+            // Broadcast right removal
+            let _ = state
+                .ws_sender
+                .send(crate::shared::CanvasDataEvent::RightChanged(
+                    canvas_id.clone(),
+                    (user_id.clone(), None),
+                ));
         }
-        // Broadcast right removal
-        let _ = state
-            .ws_sender
-            .send(crate::shared::CanvasDataEvent::RightChanged(
-                canvas_id.clone(),
-                (user_id.clone(), None),
-            ));
-    } else {
-        // Insert or update right
-        let right = payload.right.as_ref().unwrap();
-        let res = sqlx::query("INSERT INTO user_canvas (user_id, canvas_id, right) VALUES ($1, $2, $3) ON CONFLICT (user_id, canvas_id) DO UPDATE SET right = $3")
-            .bind(&user_id)
-            .bind(&canvas_id)
-            .bind(right)
-            .execute(&*state.db)
-            .await;
-        if res.is_err() {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        Some(right) => {
+            // Insert or update right
+            sqlx::query("INSERT INTO user_canvas (user_id, canvas_id, right) VALUES ($1, $2, $3) ON CONFLICT (user_id, canvas_id) DO UPDATE SET right = $3")
+                .bind(&user_id)
+                .bind(&canvas_id)
+                .bind(right)
+                .execute(&*state.db)
+                .await?;
+            // Broadcast right change
+            let _ = state
+                .ws_sender
+                .send(crate::shared::CanvasDataEvent::RightChanged(
+                    canvas_id.clone(),
+                    (user_id.clone(), Some(right.to_string())),
+                ));
         }
-        // Broadcast right change
-        let _ = state
-            .ws_sender
-            .send(crate::shared::CanvasDataEvent::RightChanged(
-                canvas_id.clone(),
-                (user_id.clone(), Some(right.clone())),
-            ));
     }
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::RightChanged)
+            .actor(&claims.id)
+            .target(&user_id)
+            .canvas(&canvas_id)
+            .ip(client_ip.0)
+            .change(json!(previous_right), json!(new_right)),
+    )
+    .await;
     // Remove redundant bool broadcast
     info!(
         "Changed right for user {} on canvas {} to {:?}",
@@ -147,13 +348,11 @@ pub async fn change_canvas_right(
     // If user changes their own right, update JWT and set cookie
     if user_id == claims.id {
         // No longer update canvases in claims, as rights are now always fetched from DB
-        let token = encode(&jsonwebtoken::Header::default(), &claims, &KEYS.encoding)
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        let cookie = format!("access_token={}; HttpOnly; Path=/; SameSite=Lax", token);
+        let token = KEYS.encode(&claims)?;
         let mut response = Response::new(Body::from("OK"));
         response
             .headers_mut()
-            .insert(header::SET_COOKIE, cookie.parse().unwrap());
+            .insert(header::SET_COOKIE, Cookie::Access.set(&token));
         return Ok(response);
     }
     Ok(Response::new(Body::from("OK")))
@@ -162,41 +361,50 @@ pub async fn change_canvas_right(
 pub async fn set_moderated(
     state: Extension<Arc<AppState>>,
     claims: Claims,
+    client_ip: ClientIp,
     Path(canvas_id): Path<String>,
     Json(payload): Json<ModeratedPayload>,
-) -> Result<impl axum::response::IntoResponse, StatusCode> {
+) -> Result<impl axum::response::IntoResponse, AppError> {
     // Fetch current user's right for this canvas from DB
     let my_right_row =
         sqlx::query("SELECT right FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
             .bind(&claims.id)
             .bind(&canvas_id)
             .fetch_optional(&*state.db)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            .await?;
     let my_right = my_right_row
-        .and_then(|row| row.try_get::<String, _>("right").ok())
+        .map(|row| row.try_get::<String, _>("right"))
+        .transpose()?
         .unwrap_or_default();
     let allowed = matches!(my_right.as_str(), "M" | "O");
     if !allowed {
-        return Err(StatusCode::FORBIDDEN);
+        return Err(AppError::Forbidden);
     }
     info!(
         "Setting moderated status for canvas {}: {}",
         canvas_id, payload.moderated
     );
-    let res = sqlx::query("UPDATE canvas SET moderated = $1 WHERE id = $2")
+    let previous: bool = sqlx::query("SELECT moderated FROM canvas WHERE id = $1")
+        .bind(&canvas_id)
+        .fetch_optional(&*state.db)
+        .await?
+        .ok_or(AppError::NotFound)?
+        .try_get("moderated")?;
+    sqlx::query("UPDATE canvas SET moderated = $1 WHERE id = $2")
         .bind(payload.moderated)
         .bind(&canvas_id)
         .execute(&*state.db)
-        .await;
-    if res.is_err() {
-        error!(
-            "Failed to update moderated status for canvas {}: {:?}",
-            canvas_id,
-            res.err()
-        );
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
+        .await
+        .with_context(|| format!("Failed to update moderated status for canvas {}", canvas_id))?;
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::ModeratedChanged)
+            .actor(&claims.id)
+            .canvas(&canvas_id)
+            .ip(client_ip.0)
+            .change(json!(previous), json!(payload.moderated)),
+    )
+    .await;
     // After moderation change, broadcast ModeratedChanged event
     let _ = state
         .ws_sender
@@ -207,54 +415,545 @@ pub async fn set_moderated(
     Ok(Response::new(Body::from("OK")))
 }
 
+/// Publishes the canvas to visitors without a right, or withdraws it. Only owners may do so,
+/// open sockets of visitors are closed once it is withdrawn.
+pub async fn set_public(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Path(canvas_id): Path<String>,
+    Json(payload): Json<PublicPayload>,
+) -> Result<impl axum::response::IntoResponse, AppError> {
+    if member_right(&state, &canvas_id, &claims.id).await? != "O" {
+        return Err(AppError::Forbidden);
+    }
+    let previous: bool = sqlx::query("SELECT public FROM canvas WHERE id = $1")
+        .bind(&canvas_id)
+        .fetch_one(&*state.db)
+        .await?
+        .try_get("public")?;
+    sqlx::query("UPDATE canvas SET public = $1 WHERE id = $2")
+        .bind(payload.public)
+        .bind(&canvas_id)
+        .execute(&*state.db)
+        .await
+        .with_context(|| format!("Failed to update visibility of canvas {}", canvas_id))?;
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::PublicChanged)
+            .actor(&claims.id)
+            .canvas(&canvas_id)
+            .ip(client_ip.0)
+            .change(json!(previous), json!(payload.public)),
+    )
+    .await;
+    info!(
+        "Setting public status for canvas {}: {}",
+        canvas_id, payload.public
+    );
+    let _ = state
+        .ws_sender
+        .send(crate::shared::CanvasDataEvent::PublicChanged(
+            canvas_id,
+            payload.public,
+        ));
+    Ok(Response::new(Body::from("OK")))
+}
+
+/// A public canvas with a page of its history, for anyone. Pages follow the `after` cursor
+/// like the events of members. Canvases that aren't public are 404, so their existence isn't
+/// revealed.
+pub async fn get_public_canvas(
+    state: Extension<Arc<AppState>>,
+    Path(canvas_id): Path<String>,
+    Query(query): Query<PublicCanvasQuery>,
+) -> Result<impl axum::response::IntoResponse, AppError> {
+    let canvas = sqlx::query(
+        "SELECT title, description, created_at, updated_at FROM canvas WHERE id = $1 AND public",
+    )
+    .bind(&canvas_id)
+    .fetch_optional(&*state.db)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    // One more than asked for tells whether there is another page
+    let mut rows = sqlx::query(
+        "SELECT seq, events FROM canvas_events WHERE canvas_id = $1 AND seq > $2 ORDER BY seq LIMIT $3",
+    )
+    .bind(&canvas_id)
+    .bind(query.after.unwrap_or(0))
+    .bind(limit + 1)
+    .fetch_all(&*state.db)
+    .await?;
+    let next_after = if rows.len() > limit as usize {
+        rows.truncate(limit as usize);
+        rows.last().map(|row| row.try_get("seq")).transpose()?
+    } else {
+        None
+    };
+    let events = rows
+        .iter()
+        .map(|row| {
+            let event: String = row.try_get("events")?;
+            Ok(serde_json::from_str(&event).unwrap_or(serde_json::Value::String(event)))
+        })
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    Ok(Json(PublicCanvas {
+        canvas_id,
+        title: canvas.try_get("title")?,
+        description: canvas.try_get("description")?,
+        created_at: canvas.try_get("created_at")?,
+        updated_at: canvas.try_get("updated_at")?,
+        events,
+        next_after,
+    }))
+}
+
+/// Renames or describes a canvas, only moderators and owners may do so.
+pub async fn update_canvas(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    Path(canvas_id): Path<String>,
+    Json(payload): Json<UpdateCanvasPayload>,
+) -> Result<impl axum::response::IntoResponse, AppError> {
+    let my_right: Option<String> =
+        sqlx::query("SELECT right FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
+            .bind(&claims.id)
+            .bind(&canvas_id)
+            .fetch_optional(&*state.db)
+            .await?
+            .map(|row| row.try_get("right"))
+            .transpose()?;
+    if !matches!(my_right.as_deref(), Some("M" | "O")) {
+        return Err(AppError::Forbidden);
+    }
+    payload.validate()?;
+    let row = sqlx::query(
+        "UPDATE canvas SET title = coalesce($1, title), description = coalesce($2, description), updated_at = datetime('now') WHERE id = $3 RETURNING title, description, updated_at",
+    )
+    .bind(&payload.title)
+    .bind(&payload.description)
+    .bind(&canvas_id)
+    .fetch_optional(&*state.db)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    let metadata = CanvasMetadata {
+        title: row.try_get("title")?,
+        description: row.try_get("description")?,
+        updated_at: row.try_get("updated_at")?,
+    };
+    info!("Updated metadata of canvas {}", canvas_id);
+    let _ = state
+        .ws_sender
+        .send(crate::shared::CanvasDataEvent::MetadataChanged(
+            canvas_id,
+            metadata.clone(),
+        ));
+    Ok(Json(metadata))
+}
+
+/// Deletes the canvas with its events and memberships, only owners may do so.
+/// Open sockets of the canvas are notified and closed.
+pub async fn delete_canvas(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Path(canvas_id): Path<String>,
+) -> Result<impl axum::response::IntoResponse, AppError> {
+    let is_owner = sqlx::query(
+        "SELECT 1 FROM user_canvas WHERE user_id = $1 AND canvas_id = $2 AND right = 'O'",
+    )
+    .bind(&claims.id)
+    .bind(&canvas_id)
+    .fetch_optional(&*state.db)
+    .await?
+    .is_some();
+    if !is_owner {
+        return Err(AppError::Forbidden);
+    }
+    // Events and memberships are removed by ON DELETE CASCADE
+    let row = sqlx::query("DELETE FROM canvas WHERE id = $1 RETURNING title")
+        .bind(&canvas_id)
+        .fetch_optional(&*state.db)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let title: String = row.try_get("title")?;
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::CanvasDeleted)
+            .actor(&claims.id)
+            .canvas(&canvas_id)
+            .ip(client_ip.0)
+            .change(json!({ "title": title }), json!(null)),
+    )
+    .await;
+    info!("Deleted canvas {}", canvas_id);
+    let _ = state
+        .ws_sender
+        .send(crate::shared::CanvasDataEvent::CanvasDeleted(canvas_id));
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub async fn get_canvases_data(
     state: Extension<Arc<AppState>>,
     claims: Claims,
-) -> Result<impl axum::response::IntoResponse, StatusCode> {
+) -> Result<impl axum::response::IntoResponse, AppError> {
     // Find all canvases where user has any right
     let rows = sqlx::query("SELECT canvas_id, right FROM user_canvas WHERE user_id = $1")
         .bind(&claims.id)
         .fetch_all(&*state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
     let mut result = Vec::new();
     for row in rows {
-        let canvas_id: String = row.try_get("canvas_id").unwrap_or_default();
-        let my_right: String = row.try_get("right").unwrap_or_default();
-        // Get moderated status
-        let row2 = sqlx::query("SELECT moderated FROM canvas WHERE id = $1")
+        let canvas_id: String = row.try_get("canvas_id")?;
+        let my_right: String = row.try_get("right")?;
+        result.push(load_canvas(&state, canvas_id, my_right).await?);
+    }
+    Ok(axum::Json(result))
+}
+
+/// Details of a canvas as seen by a member with the given right.
+async fn load_canvas(
+    state: &AppState,
+    canvas_id: String,
+    my_right: String,
+) -> Result<CanvasRightsModerated, sqlx::Error> {
+    let canvas = sqlx::query(
+        "SELECT moderated, public, title, description, created_at, updated_at, created_by, forked_from, forked_at_seq FROM canvas WHERE id = $1",
+    )
+    .bind(&canvas_id)
+    .fetch_one(&*state.db)
+    .await?;
+    // Only show rights if user is M or O
+    let rights = if my_right == "M" || my_right == "O" {
+        let rows = sqlx::query("SELECT users.email, user_canvas.right FROM user_canvas JOIN users ON user_canvas.user_id = users.id WHERE user_canvas.canvas_id = $1")
             .bind(&canvas_id)
-            .fetch_one(&*state.db)
+            .fetch_all(&*state.db)
+            .await?;
+        Some(
+            rows.into_iter()
+                .map(|row| {
+                    Ok(UserRight {
+                        email: row.try_get("email")?,
+                        right: row.try_get("right")?,
+                    })
+                })
+                .collect::<Result<Vec<_>, sqlx::Error>>()?,
+        )
+    } else {
+        None
+    };
+    Ok(CanvasRightsModerated {
+        canvas_id,
+        title: canvas.try_get("title")?,
+        description: canvas.try_get("description")?,
+        created_at: canvas.try_get("created_at")?,
+        updated_at: canvas.try_get("updated_at")?,
+        created_by: canvas.try_get("created_by")?,
+        forked_from: canvas.try_get("forked_from")?,
+        forked_at_seq: canvas.try_get("forked_at_seq")?,
+        moderated: canvas.try_get("moderated")?,
+        public: canvas.try_get("public")?,
+        right: my_right,
+        rights,
+    })
+}
+
+/// Whether an `If-None-Match` header lists the tag (or is `*`).
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        // Weak comparison, as recommended for If-None-Match
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Right of the user on the canvas. Unknown canvases are 404, canvases the user isn't a
+/// member of 403.
+pub(super) async fn member_right(
+    state: &AppState,
+    canvas_id: &str,
+    user_id: &str,
+) -> Result<String, AppError> {
+    let exists = sqlx::query("SELECT 1 FROM canvas WHERE id = $1")
+        .bind(canvas_id)
+        .fetch_optional(&*state.db)
+        .await?
+        .is_some();
+    if !exists {
+        return Err(AppError::NotFound);
+    }
+    let right = sqlx::query("SELECT right FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
+        .bind(user_id)
+        .bind(canvas_id)
+        .fetch_optional(&*state.db)
+        .await?
+        .ok_or(AppError::Forbidden)?
+        .try_get("right")?;
+    Ok(right)
+}
+
+/// A single canvas as in `get_canvases_data`, with an `ETag` for cheap polling.
+pub async fn get_canvas(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    Path(canvas_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let my_right = member_right(&state, &canvas_id, &claims.id).await?;
+    let canvas = load_canvas(&state, canvas_id, my_right).await?;
+    let body = serde_json::to_vec(&canvas).context("Failed to serialize canvas")?;
+    let etag = format!("\"{:x}\"", Sha256::digest(&body));
+    // Clients always revalidate, the members and rights may change any time
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, "private, no-cache".to_string()),
+    ];
+    if etag_matches(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+    Ok((
+        cache_headers,
+        [(header::CONTENT_TYPE, "application/json")],
+        body,
+    )
+        .into_response())
+}
+
+/// Audit log of the canvas, only visible to its owners. IP addresses are left out, those are
+/// for admins only.
+pub async fn list_audit(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    Path(canvas_id): Path<String>,
+    Query(query): Query<AuditQuery>,
+) -> Result<impl axum::response::IntoResponse, AppError> {
+    let is_owner = sqlx::query(
+        "SELECT 1 FROM user_canvas WHERE user_id = $1 AND canvas_id = $2 AND right = 'O'",
+    )
+    .bind(&claims.id)
+    .bind(&canvas_id)
+    .fetch_optional(&*state.db)
+    .await?
+    .is_some();
+    if !is_owner {
+        return Err(AppError::Forbidden);
+    }
+    let mut filter = query.filter();
+    filter.canvas_id = Some(canvas_id);
+    let mut entries =
+        list_entries(&state.db, &filter, query.limit(), query.offset.unwrap_or(0)).await?;
+    for entry in &mut entries {
+        entry.ip = None;
+    }
+    Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axum_app::test_support::TestApp;
+    use crate::shared::CanvasDataEvent;
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn only_owners_delete_canvases() {
+        let app = TestApp::new().await;
+        let owner = app.create_user("owner@example.com").await;
+        let mut members = vec![(owner.clone(), "O")];
+        for right in ["M", "V", "W", "R"] {
+            let user_id = app
+                .create_user(&format!("{}@example.com", right.to_lowercase()))
+                .await;
+            members.push((user_id, right));
+        }
+        let stranger = app.create_user("stranger@example.com").await;
+        let canvas_id = app
+            .create_canvas(
+                &members
+                    .iter()
+                    .map(|(user_id, right)| (user_id.as_str(), *right))
+                    .collect::<Vec<_>>(),
+            )
             .await;
-        let moderated = match row2 {
-            Ok(row) => row.try_get("moderated").unwrap_or(false),
-            Err(_) => false,
-        };
-        // Only show rights if user is M or O
-        let rights = if my_right == "M" || my_right == "O" {
-            let rows2 = sqlx::query("SELECT users.email, user_canvas.right FROM user_canvas JOIN users ON user_canvas.user_id = users.id WHERE user_canvas.canvas_id = $1")
+        let mut sockets = app.state.ws_sender.subscribe();
+        let uri = format!("/api/canvas/{}", canvas_id);
+
+        for user_id in members[1..]
+            .iter()
+            .map(|(user_id, _)| user_id)
+            .chain([&stranger])
+        {
+            let cookie = app.login(user_id).await;
+            let res = app.request(Method::DELETE, &uri, Some(&cookie), None).await;
+            assert_eq!(res.status, StatusCode::FORBIDDEN);
+        }
+        assert!(sockets.try_recv().is_err());
+
+        let cookie = app.login(&owner).await;
+        let res = app.request(Method::DELETE, &uri, Some(&cookie), None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert!(matches!(
+            sockets.try_recv(),
+            Ok(CanvasDataEvent::CanvasDeleted(id)) if id == canvas_id
+        ));
+        let members: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM user_canvas WHERE canvas_id = $1")
                 .bind(&canvas_id)
-                .fetch_all(&*state.db)
+                .fetch_one(app.db())
                 .await
-                .unwrap_or_default();
-            Some(
-                rows2
-                    .into_iter()
-                    .map(|row| UserRight {
-                        email: row.try_get("email").unwrap_or_default(),
-                        right: row.try_get("right").unwrap_or_default(),
-                    })
-                    .collect(),
+                .unwrap();
+        assert_eq!(members, 0);
+        let res = app.request(Method::DELETE, &uri, Some(&cookie), None).await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn canvas_audit_log_is_only_visible_to_owners_without_ips() {
+        let app = TestApp::new().await;
+        let owner = app.create_user("owner@example.com").await;
+        let moderator = app.create_user("moderator@example.com").await;
+        let writer = app.create_user("writer@example.com").await;
+        let stranger = app.create_user("stranger@example.com").await;
+        let canvas_id = app
+            .create_canvas(&[(&owner, "O"), (&moderator, "M"), (&writer, "W")])
+            .await;
+        let other_canvas = app.create_canvas(&[(&owner, "O")]).await;
+        let owner_cookie = app.login(&owner).await;
+        for canvas in [&canvas_id, &other_canvas] {
+            let res = app
+                .request(
+                    Method::POST,
+                    &format!("/api/canvas/{}/moderated", canvas),
+                    Some(&owner_cookie),
+                    Some(json!({ "moderated": true })),
+                )
+                .await;
+            assert_eq!(res.status, StatusCode::OK);
+        }
+        let uri = format!("/api/canvas/{}/audit", canvas_id);
+
+        for user in [&moderator, &writer, &stranger] {
+            let cookie = app.login(user).await;
+            let res = app.request(Method::GET, &uri, Some(&cookie), None).await;
+            assert_eq!(res.status, StatusCode::FORBIDDEN);
+        }
+        let res = app
+            .request(Method::GET, &uri, Some(&owner_cookie), None)
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        let entries = res.json();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["action"], "moderated_changed");
+        assert_eq!(entries[0]["canvas_id"], json!(canvas_id));
+        assert!(entries[0].get("ip").is_none());
+    }
+
+    #[tokio::test]
+    async fn public_canvases_are_paged_for_anyone() {
+        let app = TestApp::new().await;
+        let owner = app.create_user("owner@example.com").await;
+        let canvas_id = app.create_canvas(&[(&owner, "O")]).await;
+        for n in 0..250 {
+            sqlx::query("INSERT INTO canvas_events (canvas_id, events) VALUES ($1, $2)")
+                .bind(&canvas_id)
+                .bind(json!({ "type": "DRAW", "n": n }).to_string())
+                .execute(app.db())
+                .await
+                .unwrap();
+        }
+        let uri = format!("/api/canvas/{}/public", canvas_id);
+
+        // Canvases that aren't public look like missing ones, even to members
+        let owner_cookie = app.login(&owner).await;
+        for cookie in [None, Some(owner_cookie.as_str())] {
+            let res = app.request(Method::GET, &uri, cookie, None).await;
+            assert_eq!(res.status, StatusCode::NOT_FOUND);
+        }
+
+        sqlx::query("UPDATE canvas SET public = TRUE WHERE id = $1")
+            .bind(&canvas_id)
+            .execute(app.db())
+            .await
+            .unwrap();
+        let mut numbers = Vec::new();
+        let mut page_uri = format!("{}?limit=100", uri);
+        loop {
+            let res = app.request(Method::GET, &page_uri, None, None).await;
+            assert_eq!(res.status, StatusCode::OK);
+            let page = res.json();
+            assert!(page["events"].as_array().unwrap().len() <= 100);
+            numbers.extend(
+                page["events"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|event| event["n"].as_u64().unwrap()),
+            );
+            match page["next_after"].as_i64() {
+                Some(next) => page_uri = format!("{}?limit=100&after={}", uri, next),
+                None => break,
+            }
+        }
+        assert_eq!(numbers, (0..250).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn changing_rights_does_not_reveal_addresses() {
+        let app = TestApp::new().await;
+        let owner = app.create_user("owner@example.com").await;
+        let writer = app.create_user("writer@example.com").await;
+        app.create_user("alice@example.com").await;
+        let canvas_id = app.create_canvas(&[(&owner, "O"), (&writer, "W")]).await;
+        let uri = format!("/api/canvas/{}/right", canvas_id);
+
+        let writer_cookie = app.login(&writer).await;
+        for email in ["alice@example.com", "nobody@example.com"] {
+            let res = app
+                .request(
+                    Method::POST,
+                    &uri,
+                    Some(&writer_cookie),
+                    Some(json!({ "email": email, "right": "R" })),
+                )
+                .await;
+            assert_eq!(res.status, StatusCode::FORBIDDEN);
+        }
+
+        let owner_cookie = app.login(&owner).await;
+        let res = app
+            .request(
+                Method::POST,
+                &uri,
+                Some(&owner_cookie),
+                Some(json!({ "email": "nobody@example.com", "right": "R" })),
             )
-        } else {
-            None
-        };
-        result.push(CanvasRightsModerated {
-            canvas_id: canvas_id.clone(),
-            moderated,
-            right: my_right,
-            rights,
-        });
+            .await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        for (right, expected) in [(json!("R"), Some("R")), (json!(null), None)] {
+            let res = app
+                .request(
+                    Method::POST,
+                    &uri,
+                    Some(&owner_cookie),
+                    Some(json!({ "email": "alice@example.com", "right": right })),
+                )
+                .await;
+            assert_eq!(res.status, StatusCode::OK);
+            let stored: Option<String> = sqlx::query_scalar(
+                "SELECT uc.right FROM user_canvas uc JOIN users u ON u.id = uc.user_id WHERE u.email = 'alice@example.com'",
+            )
+            .fetch_optional(app.db())
+            .await
+            .unwrap();
+            assert_eq!(stored.as_deref(), expected);
+        }
     }
-    Ok(axum::Json(result))
 }
@@ -0,0 +1,344 @@
+use crate::axum_app::axum::AppState;
+use crate::axum_app::error::AppError;
+use crate::axum_app::routes::canvas::member_right;
+use crate::shared::jwt::Claims;
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, Query};
+use axum::http::{HeaderMap, header};
+use axum::{
+    Extension, Json,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqlitePool};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+pub const DEFAULT_PAGE_SIZE: u32 = 100;
+pub const MAX_PAGE_SIZE: u32 = 1000;
+/// Events fetched per query while streaming, the connection goes back to the pool in between
+const STREAM_PAGE_SIZE: u32 = 500;
+const NDJSON: &str = "application/x-ndjson";
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    /// Sequence number of the last event already seen
+    pub after: Option<i64>,
+    pub limit: Option<u32>,
+    #[serde(rename = "type")]
+    pub event_type: Option<String>,
+    /// User id of the author
+    pub author: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct StoredEvent {
+    pub seq: i64,
+    #[serde(rename = "type")]
+    pub event_type: Option<String>,
+    /// Missing for events from before authors were recorded and for deleted accounts
+    pub author_id: Option<String>,
+    pub created_at: Option<String>,
+    pub event: Value,
+}
+
+#[derive(Serialize)]
+pub struct EventsPage {
+    pub events: Vec<StoredEvent>,
+    /// Cursor for the next page, missing on the last one
+    pub next_after: Option<i64>,
+}
+
+impl TryFrom<SqliteRow> for StoredEvent {
+    type Error = sqlx::Error;
+
+    fn try_from(row: SqliteRow) -> Result<Self, Self::Error> {
+        let event: String = row.try_get("events")?;
+        Ok(StoredEvent {
+            seq: row.try_get("seq")?,
+            event_type: row.try_get("event_type")?,
+            author_id: row.try_get("author_id")?,
+            created_at: row.try_get("created_at")?,
+            // Events are stored as serialized JSON, anything else is returned as a string
+            event: serde_json::from_str(&event).unwrap_or(Value::String(event)),
+        })
+    }
+}
+
+const EVENTS_QUERY: &str = "SELECT seq, events, event_type, author_id, created_at FROM canvas_events WHERE canvas_id = $1 AND seq > $2 AND ($3 IS NULL OR event_type = $3) AND ($4 IS NULL OR author_id = $4) ORDER BY seq LIMIT $5";
+
+fn wants_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.split(',').any(|t| t.trim().starts_with(NDJSON)))
+}
+
+/// Stored events of the canvas in order, readable with any right. Pages of `limit` events
+/// follow the `after` cursor. With `Accept: application/x-ndjson` the events are streamed one
+/// per line instead, all remaining ones unless `limit` is given.
+pub async fn list(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    Path(canvas_id): Path<String>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    member_right(&state, &canvas_id, &claims.id).await?;
+    let after = query.after.unwrap_or(0);
+
+    if wants_ndjson(&headers) {
+        let limit = query.limit;
+        let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(16);
+        let db = state.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = write_ndjson(&db, &canvas_id, after, &query, limit, &tx).await {
+                tracing::error!("Streaming events of canvas {} failed: {:?}", canvas_id, e);
+                // Aborts the body so the client notices the history is incomplete
+                let _ = tx.send(Err(std::io::Error::other("stream failed"))).await;
+            }
+        });
+        let mut response = Body::from_stream(ReceiverStream::new(rx)).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, NDJSON.parse().unwrap());
+        return Ok(response);
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    // One more than asked for tells whether there is another page
+    let rows = sqlx::query(EVENTS_QUERY)
+        .bind(&canvas_id)
+        .bind(after)
+        .bind(&query.event_type)
+        .bind(&query.author)
+        .bind(limit + 1)
+        .fetch_all(&*state.db)
+        .await?;
+    let mut events = rows
+        .into_iter()
+        .map(StoredEvent::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    let next_after = if events.len() > limit as usize {
+        events.truncate(limit as usize);
+        events.last().map(|event| event.seq)
+    } else {
+        None
+    };
+    Ok(Json(EventsPage { events, next_after }).into_response())
+}
+
+/// Streams the events page by page along `seq`, so a slow client never holds a connection
+/// of the pool. At most `limit` events are written, all remaining ones without it.
+async fn write_ndjson(
+    db: &SqlitePool,
+    canvas_id: &str,
+    after: i64,
+    query: &EventsQuery,
+    limit: Option<u32>,
+    tx: &mpsc::Sender<Result<Bytes, std::io::Error>>,
+) -> anyhow::Result<()> {
+    let mut after = after;
+    let mut remaining = limit;
+    loop {
+        let page_size = remaining.map_or(STREAM_PAGE_SIZE, |r| r.min(STREAM_PAGE_SIZE));
+        if page_size == 0 {
+            return Ok(());
+        }
+        let rows = sqlx::query(EVENTS_QUERY)
+            .bind(canvas_id)
+            .bind(after)
+            .bind(&query.event_type)
+            .bind(&query.author)
+            .bind(page_size)
+            .fetch_all(db)
+            .await?;
+        let count = rows.len() as u32;
+        for row in rows {
+            let event = StoredEvent::try_from(row)?;
+            after = event.seq;
+            let mut line = serde_json::to_vec(&event)?;
+            line.push(b'\n');
+            if tx.send(Ok(Bytes::from(line))).await.is_err() {
+                // Closing the download early is fine, nothing is left to clean up
+                tracing::debug!("Client stopped reading the events of canvas {}", canvas_id);
+                return Ok(());
+            }
+        }
+        if count < page_size {
+            return Ok(());
+        }
+        remaining = remaining.map(|r| r - count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventsQuery, NDJSON, write_ndjson};
+    use crate::axum_app::test_support::{TestApp, TestResponse};
+    use axum::body::Body;
+    use axum::http::{Method, Request, StatusCode, header};
+    use serde_json::{Value, json};
+    use tokio::sync::mpsc;
+
+    /// Stores `count` events, alternating between `DRAW` and `ERASE`, and returns their seqs.
+    async fn store_events(app: &TestApp, canvas_id: &str, author: &str, count: usize) -> Vec<i64> {
+        let mut tx = app.db().begin().await.unwrap();
+        let mut seqs = Vec::new();
+        for i in 0..count {
+            let event_type = if i % 2 == 0 { "DRAW" } else { "ERASE" };
+            let seq = sqlx::query_scalar(
+                "INSERT INTO canvas_events (canvas_id, events, event_type, author_id) VALUES ($1, $2, $3, $4) RETURNING seq",
+            )
+            .bind(canvas_id)
+            .bind(json!({ "type": event_type, "n": i }).to_string())
+            .bind(event_type)
+            .bind(author)
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+            seqs.push(seq);
+        }
+        tx.commit().await.unwrap();
+        seqs
+    }
+
+    async fn ndjson(app: &TestApp, uri: &str, cookie: &str) -> (TestResponse, Vec<Value>) {
+        let res = app
+            .send(
+                Request::builder()
+                    .uri(uri)
+                    .header(header::COOKIE, cookie)
+                    .header(header::ACCEPT, NDJSON)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        let lines = String::from_utf8_lossy(&res.body)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        (res, lines)
+    }
+
+    fn numbers(events: &[Value]) -> Vec<u64> {
+        events
+            .iter()
+            .map(|event| event["event"]["n"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pages_follow_the_cursor() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let canvas_id = app.create_canvas(&[(&user_id, "R")]).await;
+        store_events(&app, &canvas_id, &user_id, 250).await;
+        let cookie = app.login(&user_id).await;
+
+        let mut after = 0;
+        let mut seen = Vec::new();
+        let mut pages = 0;
+        loop {
+            let res = app
+                .request(
+                    Method::GET,
+                    &format!("/api/canvas/{}/events?limit=100&after={}", canvas_id, after),
+                    Some(&cookie),
+                    None,
+                )
+                .await;
+            assert_eq!(res.status, StatusCode::OK);
+            let page = res.json();
+            seen.extend(numbers(page["events"].as_array().unwrap()));
+            pages += 1;
+            match page["next_after"].as_i64() {
+                Some(next) => after = next,
+                None => break,
+            }
+        }
+        assert_eq!(pages, 3);
+        assert_eq!(seen, (0..250).collect::<Vec<_>>());
+
+        let res = app
+            .request(
+                Method::GET,
+                &format!("/api/canvas/{}/events?type=ERASE&limit=1000", canvas_id),
+                Some(&cookie),
+                None,
+            )
+            .await;
+        let page = res.json();
+        assert_eq!(page["events"].as_array().unwrap().len(), 125);
+        assert!(page["next_after"].is_null());
+    }
+
+    #[tokio::test]
+    async fn ndjson_streams_across_pages() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let canvas_id = app.create_canvas(&[(&user_id, "R")]).await;
+        let seqs = store_events(&app, &canvas_id, &user_id, 1200).await;
+        let cookie = app.login(&user_id).await;
+        let uri = format!("/api/canvas/{}/events", canvas_id);
+
+        let (res, events) = ndjson(&app, &uri, &cookie).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.headers[header::CONTENT_TYPE], NDJSON);
+        assert_eq!(numbers(&events), (0..1200).collect::<Vec<_>>());
+
+        let (_, events) = ndjson(&app, &format!("{}?limit=700", uri), &cookie).await;
+        assert_eq!(numbers(&events), (0..700).collect::<Vec<_>>());
+
+        let (_, events) = ndjson(&app, &format!("{}?after={}", uri, seqs[999]), &cookie).await;
+        assert_eq!(numbers(&events), (1000..1200).collect::<Vec<_>>());
+
+        let (_, events) = ndjson(&app, &format!("{}?type=DRAW&limit=550", uri), &cookie).await;
+        assert_eq!(numbers(&events), (0..1100).step_by(2).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn ndjson_stops_quietly_when_the_client_disconnects() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let canvas_id = app.create_canvas(&[(&user_id, "R")]).await;
+        store_events(&app, &canvas_id, &user_id, 10).await;
+        let query = EventsQuery {
+            after: None,
+            limit: None,
+            event_type: None,
+            author: None,
+        };
+        let (tx, rx) = mpsc::channel(1);
+        drop(rx);
+        write_ndjson(app.db(), &canvas_id, 0, &query, None, &tx)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn events_are_only_readable_by_members() {
+        let app = TestApp::new().await;
+        let member = app.create_user("alice@example.com").await;
+        let stranger = app.create_user("bob@example.com").await;
+        let canvas_id = app.create_canvas(&[(&member, "R")]).await;
+        store_events(&app, &canvas_id, &member, 3).await;
+        let uri = format!("/api/canvas/{}/events", canvas_id);
+
+        let cookie = app.login(&stranger).await;
+        let res = app.request(Method::GET, &uri, Some(&cookie), None).await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+        // Rejected before streaming starts, with the usual error body
+        let (res, body) = ndjson(&app, &uri, &cookie).await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+        assert_eq!(body, [json!({ "error": "Forbidden", "code": "forbidden" })]);
+        let res = app.request(Method::GET, &uri, None, None).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    }
+}
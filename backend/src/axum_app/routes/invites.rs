@@ -0,0 +1,402 @@
+use crate::axum_app::axum::AppState;
+use crate::axum_app::error::AppError;
+use crate::axum_app::routes::canvas::member_right;
+use crate::axum_app::transformers::ClientIp;
+use crate::axum_app::validation::{Validate, ValidationErrors};
+use crate::shared::audit::{AuditAction, AuditEvent, record};
+use crate::shared::invite::{
+    AcceptOutcome, INVITE_RIGHTS, accept_invite, can_invite, create_invite, list_invites,
+    revoke_invite,
+};
+use crate::shared::jwt::Claims;
+use crate::shared::verification::VERIFICATION_POLICY;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::{Extension, Json, response::IntoResponse};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Row;
+use std::sync::Arc;
+use tracing::*;
+
+const DEFAULT_EXPIRY_DAYS: u32 = 7;
+const MAX_EXPIRY_DAYS: u32 = 30;
+
+#[derive(Deserialize)]
+pub struct CreateInvitePayload {
+    pub right: String,
+    pub expires_in_days: Option<u32>,
+    /// Unlimited when omitted
+    pub max_uses: Option<u32>,
+}
+
+impl Validate for CreateInvitePayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if !INVITE_RIGHTS.contains(&self.right.as_str()) {
+            errors.add("right", "must be one of R, W, V, M or O");
+        }
+        if self
+            .expires_in_days
+            .is_some_and(|days| !(1..=MAX_EXPIRY_DAYS).contains(&days))
+        {
+            errors.add(
+                "expires_in_days",
+                format!("must be between 1 and {}", MAX_EXPIRY_DAYS),
+            );
+        }
+        if self.max_uses == Some(0) {
+            errors.add("max_uses", "must be at least 1");
+        }
+        errors.into_result()
+    }
+}
+
+async fn require_owner(state: &AppState, canvas_id: &str, user_id: &str) -> Result<(), AppError> {
+    if member_right(state, canvas_id, user_id).await? != "O" {
+        return Err(AppError::Forbidden);
+    }
+    Ok(())
+}
+
+/// Mints an invite link, with the same limits as `change_canvas_right`: moderators may
+/// invite with any right but O, owners with any right.
+pub async fn create(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Path(canvas_id): Path<String>,
+    Json(payload): Json<CreateInvitePayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let right = member_right(&state, &canvas_id, &claims.id).await?;
+    if !can_invite(&right, &payload.right) {
+        return Err(AppError::Forbidden);
+    }
+    payload.validate()?;
+    let expires_in_days = payload.expires_in_days.unwrap_or(DEFAULT_EXPIRY_DAYS);
+    let (id, token) = create_invite(
+        &state.db,
+        &canvas_id,
+        &claims.id,
+        &payload.right,
+        expires_in_days,
+        payload.max_uses,
+    )
+    .await?;
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::InviteCreated)
+            .actor(&claims.id)
+            .target(&id)
+            .canvas(&canvas_id)
+            .ip(client_ip.0)
+            .after(json!({
+                "right": payload.right,
+                "expires_in_days": expires_in_days,
+                "max_uses": payload.max_uses,
+            })),
+    )
+    .await;
+    info!("Created invite {} for canvas {}", id, canvas_id);
+    // The plain token is only shown once
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "id": id, "token": token })),
+    ))
+}
+
+/// Invites of the canvas that can still be accepted, only visible to its owners.
+pub async fn list(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    Path(canvas_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    require_owner(&state, &canvas_id, &claims.id).await?;
+    let invites = list_invites(&state.db, &canvas_id).await?;
+    Ok(Json(invites))
+}
+
+pub async fn revoke(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Path((canvas_id, invite_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    require_owner(&state, &canvas_id, &claims.id).await?;
+    if !revoke_invite(&state.db, &canvas_id, &invite_id).await? {
+        return Err(AppError::NotFound);
+    }
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::InviteRevoked)
+            .actor(&claims.id)
+            .target(&invite_id)
+            .canvas(&canvas_id)
+            .ip(client_ip.0),
+    )
+    .await;
+    info!("Revoked invite {} of canvas {}", invite_id, canvas_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Joins the canvas of the invite, or raises the caller's right to the invited one.
+/// Unknown, revoked, expired and used up invites are all 404, as are invites whose creator
+/// was deleted or may no longer grant the right.
+pub async fn accept(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    if VERIFICATION_POLICY.canvas_rights {
+        let email_verified: bool = sqlx::query("SELECT email_verified FROM users WHERE id = $1")
+            .bind(&claims.id)
+            .fetch_one(&*state.db)
+            .await?
+            .try_get("email_verified")?;
+        // Rights can only be granted to confirmed addresses
+        if !email_verified {
+            return Err(AppError::Forbidden);
+        }
+    }
+    let outcome = accept_invite(&state.db, &token, &claims.id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let (canvas_id, right) = match outcome {
+        AcceptOutcome::Joined {
+            invite_id,
+            canvas_id,
+            previous_right,
+            right,
+        } => {
+            let _ = state
+                .ws_sender
+                .send(crate::shared::CanvasDataEvent::RightChanged(
+                    canvas_id.clone(),
+                    (claims.id.clone(), Some(right.clone())),
+                ));
+            record(
+                &state.db,
+                AuditEvent::new(AuditAction::InviteAccepted)
+                    .actor(&claims.id)
+                    .target(&invite_id)
+                    .canvas(&canvas_id)
+                    .ip(client_ip.0)
+                    .change(json!(previous_right), json!(right)),
+            )
+            .await;
+            info!(
+                "User {} joined canvas {} as {} via invite {}",
+                claims.id, canvas_id, right, invite_id
+            );
+            (canvas_id, right)
+        }
+        AcceptOutcome::AlreadyMember { canvas_id, right } => (canvas_id, right),
+    };
+    Ok(Json(json!({ "canvas_id": canvas_id, "right": right })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axum_app::test_support::TestApp;
+    use axum::http::{Method, StatusCode};
+    use serde_json::{Value, json};
+
+    async fn create(
+        app: &TestApp,
+        user_id: &str,
+        canvas_id: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let cookie = app.login(user_id).await;
+        let res = app
+            .request(
+                Method::POST,
+                &format!("/api/canvas/{}/invites", canvas_id),
+                Some(&cookie),
+                Some(body),
+            )
+            .await;
+        let body = if res.status == StatusCode::CREATED {
+            res.json()
+        } else {
+            Value::Null
+        };
+        (res.status, body)
+    }
+
+    async fn accept(app: &TestApp, user_id: &str, token: &Value) -> (StatusCode, Value) {
+        let cookie = app.login(user_id).await;
+        let res = app
+            .request(
+                Method::POST,
+                &format!("/api/canvas/invite/{}/accept", token.as_str().unwrap()),
+                Some(&cookie),
+                None,
+            )
+            .await;
+        (res.status, res.json())
+    }
+
+    async fn right_of(app: &TestApp, user_id: &str, canvas_id: &str) -> Option<String> {
+        sqlx::query_scalar("SELECT right FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
+            .bind(user_id)
+            .bind(canvas_id)
+            .fetch_optional(app.db())
+            .await
+            .unwrap()
+    }
+
+    async fn set_right(app: &TestApp, user_id: &str, canvas_id: &str, right: &str) {
+        sqlx::query("UPDATE user_canvas SET right = $1 WHERE user_id = $2 AND canvas_id = $3")
+            .bind(right)
+            .bind(user_id)
+            .bind(canvas_id)
+            .execute(app.db())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_owners_and_moderators_create_invites() {
+        let app = TestApp::new().await;
+        let owner = app.create_user("owner@example.com").await;
+        let moderator = app.create_user("moderator@example.com").await;
+        let co_owner = app.create_user("co-owner@example.com").await;
+        let writer = app.create_user("writer@example.com").await;
+        let stranger = app.create_user("stranger@example.com").await;
+        let canvas_id = app
+            .create_canvas(&[
+                (&owner, "O"),
+                (&moderator, "M"),
+                (&co_owner, "CO"),
+                (&writer, "W"),
+            ])
+            .await;
+
+        for (user_id, right, status) in [
+            (&owner, "O", StatusCode::CREATED),
+            (&moderator, "M", StatusCode::CREATED),
+            (&moderator, "O", StatusCode::FORBIDDEN),
+            (&co_owner, "R", StatusCode::FORBIDDEN),
+            (&writer, "R", StatusCode::FORBIDDEN),
+            (&stranger, "R", StatusCode::FORBIDDEN),
+            (&owner, "CO", StatusCode::UNPROCESSABLE_ENTITY),
+        ] {
+            let (res, _) = create(&app, user_id, &canvas_id, json!({ "right": right })).await;
+            assert_eq!(res, status, "{} inviting as {}", user_id, right);
+        }
+
+        let moderator_cookie = app.login(&moderator).await;
+        let res = app
+            .request(
+                Method::GET,
+                &format!("/api/canvas/{}/invites", canvas_id),
+                Some(&moderator_cookie),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn accepting_raises_rights_up_to_the_use_limit() {
+        let app = TestApp::new().await;
+        let owner = app.create_user("owner@example.com").await;
+        let alice = app.create_user("alice@example.com").await;
+        let bob = app.create_user("bob@example.com").await;
+        let canvas_id = app.create_canvas(&[(&owner, "O"), (&alice, "R")]).await;
+        let (_, invite) = create(
+            &app,
+            &owner,
+            &canvas_id,
+            json!({ "right": "W", "max_uses": 1 }),
+        )
+        .await;
+
+        let (status, body) = accept(&app, &alice, &invite["token"]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["right"], "W");
+        assert_eq!(
+            right_of(&app, &alice, &canvas_id).await.as_deref(),
+            Some("W")
+        );
+        let (status, _) = accept(&app, &bob, &invite["token"]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(right_of(&app, &bob, &canvas_id).await, None);
+    }
+
+    #[tokio::test]
+    async fn accepting_never_lowers_a_right() {
+        let app = TestApp::new().await;
+        let owner = app.create_user("owner@example.com").await;
+        let co_owner = app.create_user("co-owner@example.com").await;
+        let moderator = app.create_user("moderator@example.com").await;
+        let canvas_id = app
+            .create_canvas(&[(&owner, "O"), (&co_owner, "CO"), (&moderator, "M")])
+            .await;
+        let (_, invite) = create(&app, &owner, &canvas_id, json!({ "right": "M" })).await;
+
+        for user_id in [&co_owner, &moderator] {
+            let (status, body) = accept(&app, user_id, &invite["token"]).await;
+            assert_eq!(status, StatusCode::OK);
+            let right = right_of(&app, user_id, &canvas_id).await.unwrap();
+            assert_eq!(body["right"], right.as_str());
+        }
+        assert_eq!(
+            right_of(&app, &co_owner, &canvas_id).await.as_deref(),
+            Some("CO")
+        );
+        let uses: i64 = sqlx::query_scalar("SELECT uses FROM canvas_invites")
+            .fetch_one(app.db())
+            .await
+            .unwrap();
+        assert_eq!(uses, 0);
+
+        // Co-owners still become owners through an owner invite
+        let (_, invite) = create(&app, &owner, &canvas_id, json!({ "right": "O" })).await;
+        accept(&app, &co_owner, &invite["token"]).await;
+        assert_eq!(
+            right_of(&app, &co_owner, &canvas_id).await.as_deref(),
+            Some("O")
+        );
+    }
+
+    #[tokio::test]
+    async fn invites_follow_the_rights_of_their_creator() {
+        let app = TestApp::new().await;
+        let owner = app.create_user("owner@example.com").await;
+        let moderator = app.create_user("moderator@example.com").await;
+        let alice = app.create_user("alice@example.com").await;
+        let canvas_id = app.create_canvas(&[(&owner, "O"), (&moderator, "M")]).await;
+        let (_, invite) = create(&app, &moderator, &canvas_id, json!({ "right": "M" })).await;
+
+        set_right(&app, &moderator, &canvas_id, "W").await;
+        let (status, _) = accept(&app, &alice, &invite["token"]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        set_right(&app, &moderator, &canvas_id, "M").await;
+        let (status, _) = accept(&app, &alice, &invite["token"]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Invites of deleted accounts are neither listed nor accepted
+        let (_, invite) = create(&app, &moderator, &canvas_id, json!({ "right": "R" })).await;
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(&moderator)
+            .execute(app.db())
+            .await
+            .unwrap();
+        let bob = app.create_user("bob@example.com").await;
+        let (status, _) = accept(&app, &bob, &invite["token"]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let cookie = app.login(&owner).await;
+        let res = app
+            .request(
+                Method::GET,
+                &format!("/api/canvas/{}/invites", canvas_id),
+                Some(&cookie),
+                None,
+            )
+            .await;
+        assert_eq!(res.json(), json!([]));
+    }
+}
@@ -1,5 +1,13 @@
+mod account;
+mod admin;
 mod auth;
 mod canvas;
+mod events;
+mod invites;
+mod oidc;
 mod router;
+mod sessions;
+mod tokens;
+mod totp;
 
 pub use router::create_router;
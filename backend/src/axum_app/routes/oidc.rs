@@ -0,0 +1,455 @@
+use crate::axum_app::axum::AppState;
+use crate::axum_app::error::{AppError, AuthError, Conflict};
+use crate::axum_app::oidc::IdTokenClaims;
+use crate::axum_app::routes::auth::start_session;
+use crate::axum_app::transformers::{ClientIp, UserAgent};
+use crate::axum_app::validation::{MAX_FIELD_LENGTH, ValidationErrors, check_email};
+use crate::shared::admin::is_bootstrap_admin;
+use crate::shared::audit::{AuditAction, AuditEvent, record};
+use crate::shared::cookies::Cookie;
+use crate::shared::jwt::{KEYS, MfaPendingClaims, TOKEN_TTL};
+use crate::shared::token::{generate_token, hash_token};
+use crate::shared::verification::VERIFICATION_POLICY;
+use anyhow::Context;
+use axum::extract::Query;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::{
+    Extension,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use sqlx::Row;
+use std::sync::Arc;
+
+/// Binds the login attempt to the browser that started it
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+fn redirect(location: &str) -> Response {
+    let mut response = StatusCode::SEE_OTHER.into_response();
+    response
+        .headers_mut()
+        .insert(header::LOCATION, location.parse().unwrap());
+    response
+}
+
+/// Starts a login by redirecting to the provider.
+pub async fn login(state: Extension<Arc<AppState>>) -> Result<Response, AppError> {
+    let Some(oidc) = &state.oidc else {
+        return Err(AppError::NotFound);
+    };
+    let login_state = generate_token();
+    let nonce = generate_token();
+    let code_verifier = generate_token();
+
+    sqlx::query("DELETE FROM oidc_logins WHERE expires_at <= datetime('now')")
+        .execute(&*state.db)
+        .await?;
+    sqlx::query(
+        "INSERT INTO oidc_logins (state_hash, code_verifier, nonce, expires_at) VALUES ($1, $2, $3, datetime('now', $4))",
+    )
+    .bind(hash_token(&login_state))
+    .bind(&code_verifier)
+    .bind(&nonce)
+    .bind(format!("+{} seconds", TOKEN_TTL.oidc_login))
+    .execute(&*state.db)
+    .await?;
+
+    let url = oidc
+        .authorization_url(&login_state, &nonce, &code_verifier)
+        .await
+        .context("OpenID provider discovery failed")?;
+    let mut response = redirect(&url);
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, Cookie::OidcState.set(&login_state));
+    Ok(response)
+}
+
+/// Returns the user linked to the provider account, linking or creating one on first login.
+async fn find_or_create_user(
+    state: &AppState,
+    client_ip: &ClientIp,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> Result<String, AppError> {
+    let linked =
+        sqlx::query("SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2")
+            .bind(issuer)
+            .bind(&claims.sub)
+            .fetch_optional(&*state.db)
+            .await?;
+    if let Some(row) = linked {
+        return Ok(row.try_get("user_id")?);
+    }
+
+    let email = claims
+        .email
+        .as_deref()
+        .ok_or(AuthError::MissingCredentials)?;
+    let mut errors = ValidationErrors::default();
+    check_email(&mut errors, "email", email);
+    errors.into_result()?;
+    let existing = sqlx::query("SELECT id FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(&*state.db)
+        .await?;
+
+    let mut tx = state.db.begin().await?;
+    let created = existing.is_none();
+    let user_id: String = match existing {
+        // Only addresses the provider vouches for may take over an existing account
+        Some(row) if claims.email_verified => row.try_get("id")?,
+        Some(_) => return Err(Conflict::EmailTaken.into()),
+        None => {
+            let fallback = email.split('@').next().unwrap_or(email);
+            let display_name: String = claims
+                .name
+                .as_deref()
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .unwrap_or(fallback)
+                .chars()
+                .take(MAX_FIELD_LENGTH)
+                .collect();
+            // No password: password login stays impossible until one is set via reset
+            sqlx::query(
+                "INSERT INTO users (email, display_name, password_hash, email_verified, is_admin) VALUES ($1, $2, '', $3, $4) RETURNING id",
+            )
+            .bind(email)
+            .bind(&display_name)
+            .bind(claims.email_verified)
+            // Like `verify_email`, only addresses the provider vouches for become admins
+            .bind(claims.email_verified && is_bootstrap_admin(email))
+            .fetch_one(&mut *tx)
+            .await?
+            .try_get("id")?
+        }
+    };
+    sqlx::query("INSERT INTO user_identities (issuer, subject, user_id) VALUES ($1, $2, $3)")
+        .bind(issuer)
+        .bind(&claims.sub)
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    if created {
+        record(
+            &state.db,
+            AuditEvent::new(AuditAction::Register)
+                .actor(&user_id)
+                .target(&user_id)
+                .ip(client_ip.0)
+                .after(serde_json::json!({ "email": email, "issuer": issuer })),
+        )
+        .await;
+    }
+    Ok(user_id)
+}
+
+/// Completes the login: redeems the code, signs the user in and redirects to the frontend.
+pub async fn callback(
+    state: Extension<Arc<AppState>>,
+    client_ip: ClientIp,
+    user_agent: UserAgent,
+    headers: HeaderMap,
+    Query(query): Query<CallbackQuery>,
+) -> Result<Response, AppError> {
+    let Some(oidc) = &state.oidc else {
+        return Err(AppError::NotFound);
+    };
+    if let Some(error) = &query.error {
+        tracing::warn!("OpenID provider returned error {}", error);
+        return Err(AuthError::WrongCredentials.into());
+    }
+    let (Some(code), Some(login_state)) = (&query.code, &query.state) else {
+        return Err(AuthError::MissingCredentials.into());
+    };
+    let cookie_state = headers
+        .get(header::COOKIE)
+        .and_then(|c| c.to_str().ok())
+        .and_then(|c| Cookie::OidcState.get(c));
+    if cookie_state != Some(login_state.as_str()) {
+        return Err(AuthError::InvalidToken.into());
+    }
+
+    // Each authorization request can only be completed once
+    let pending = sqlx::query(
+        "DELETE FROM oidc_logins WHERE state_hash = $1 AND expires_at > datetime('now') RETURNING code_verifier, nonce",
+    )
+    .bind(hash_token(login_state))
+    .fetch_optional(&*state.db)
+    .await?
+    .ok_or(AuthError::InvalidToken)?;
+    let code_verifier: String = pending.try_get("code_verifier")?;
+    let nonce: String = pending.try_get("nonce")?;
+
+    let claims = oidc
+        .exchange_code(code, &code_verifier, &nonce)
+        .await
+        .map_err(|e| {
+            tracing::warn!("OpenID login failed: {:?}", e);
+            AuthError::WrongCredentials
+        })?;
+    let user_id = find_or_create_user(&state, &client_ip, &oidc.config.issuer, &claims).await?;
+
+    let row = sqlx::query(
+        "SELECT email, display_name, email_verified, totp_enabled FROM users WHERE id = $1",
+    )
+    .bind(&user_id)
+    .fetch_one(&*state.db)
+    .await?;
+    let email: String = row.try_get("email")?;
+    let display_name: String = row.try_get("display_name")?;
+    let email_verified: bool = row.try_get("email_verified")?;
+    if VERIFICATION_POLICY.login && !email_verified {
+        return Err(AuthError::EmailNotVerified.into());
+    }
+
+    // The provider login doesn't replace the account's own second factor, e.g. of a password
+    // account linked by its address. Like `login`, it only earns a pending token, which the
+    // frontend exchanges together with a code.
+    if row.try_get::<bool, _>("totp_enabled")? {
+        let pending = MfaPendingClaims {
+            mfa_user_id: user_id,
+            exp: (jsonwebtoken::get_current_timestamp() + TOKEN_TTL.mfa_pending) as usize,
+        };
+        let mut response = redirect(&format!(
+            "{}#mfa_token={}",
+            oidc.config.mfa_redirect,
+            KEYS.encode(&pending)?
+        ));
+        response
+            .headers_mut()
+            .append(header::SET_COOKIE, Cookie::OidcState.clear());
+        return Ok(response);
+    }
+
+    let mut response = start_session(
+        &state,
+        &user_id,
+        &email,
+        &display_name,
+        &client_ip,
+        &user_agent,
+    )
+    .await?;
+    *response.status_mut() = StatusCode::SEE_OTHER;
+    response.headers_mut().insert(
+        header::LOCATION,
+        oidc.config.post_login_redirect.parse().unwrap(),
+    );
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, Cookie::OidcState.clear());
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axum_app::oidc::mock::MockProvider;
+    use crate::axum_app::test_support::{TestApp, TestResponse};
+    use crate::shared::cookies::Cookie;
+    use crate::shared::totp;
+    use axum::http::{Method, StatusCode, header};
+    use serde_json::{Value, json};
+    use std::sync::Arc;
+    use url::Url;
+
+    /// Starts a login and returns the state and the nonce the provider was sent
+    async fn start_login(app: &TestApp) -> (String, String) {
+        let res = app
+            .request(Method::GET, "/api/auth/oidc/login", None, None)
+            .await;
+        assert_eq!(res.status, StatusCode::SEE_OTHER);
+        let location = res.headers[header::LOCATION].to_str().unwrap();
+        let query: std::collections::HashMap<_, _> = Url::parse(location)
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        assert_eq!(
+            res.cookie(&Cookie::OidcState.name()).as_ref(),
+            Some(&query["state"])
+        );
+        (query["state"].clone(), query["nonce"].clone())
+    }
+
+    /// Returns from the provider to the callback with `state`, the browser holding `cookie_state`
+    async fn callback(app: &TestApp, state: &str, cookie_state: Option<&str>) -> TestResponse {
+        let cookie = cookie_state.map(|value| format!("{}={}", Cookie::OidcState.name(), value));
+        app.request(
+            Method::GET,
+            &format!("/api/auth/oidc/callback?code=the-code&state={}", state),
+            cookie.as_deref(),
+            None,
+        )
+        .await
+    }
+
+    /// A full login as the provider account, with the claims changed by `edit`
+    async fn login_as(
+        app: &TestApp,
+        provider: &Arc<MockProvider>,
+        subject: &str,
+        email: &str,
+        edit: impl FnOnce(&mut Value),
+    ) -> TestResponse {
+        let (state, nonce) = start_login(app).await;
+        let mut claims = MockProvider::claims(subject, email, &nonce);
+        edit(&mut claims);
+        provider.issue(&claims);
+        callback(app, &state, Some(&state)).await
+    }
+
+    async fn linked_user(app: &TestApp, subject: &str) -> Option<String> {
+        sqlx::query_scalar("SELECT user_id FROM user_identities WHERE subject = $1")
+            .bind(subject)
+            .fetch_optional(app.db())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn callback_needs_the_state_of_this_browser_once() {
+        let provider = MockProvider::new();
+        let app = TestApp::with_oidc(&provider).await;
+        let (state, nonce) = start_login(&app).await;
+        provider.issue(&MockProvider::claims(
+            "subject-1",
+            "alice@example.com",
+            &nonce,
+        ));
+
+        for cookie_state in [None, Some("another-state")] {
+            let res = callback(&app, &state, cookie_state).await;
+            assert_eq!(res.status, StatusCode::BAD_REQUEST);
+            assert_eq!(res.json()["code"], "invalid_token");
+        }
+        // The pending login is still there after the rejected attempts
+        let res = callback(&app, &state, Some(&state)).await;
+        assert_eq!(res.status, StatusCode::SEE_OTHER);
+        assert!(res.cookie(&Cookie::Access.name()).is_some());
+
+        provider.issue(&MockProvider::claims(
+            "subject-1",
+            "alice@example.com",
+            &nonce,
+        ));
+        let res = callback(&app, &state, Some(&state)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.json()["code"], "invalid_token");
+    }
+
+    #[tokio::test]
+    async fn verified_addresses_link_the_existing_account() {
+        let provider = MockProvider::new();
+        let app = TestApp::with_oidc(&provider).await;
+        let user_id = app.create_user("alice@example.com").await;
+
+        let res = login_as(&app, &provider, "subject-1", "alice@example.com", |_| {}).await;
+        assert_eq!(res.status, StatusCode::SEE_OTHER);
+        assert_eq!(
+            res.headers[header::LOCATION],
+            provider.client().config.post_login_redirect
+        );
+        assert_eq!(linked_user(&app, "subject-1").await, Some(user_id.clone()));
+        let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(app.db())
+            .await
+            .unwrap();
+        assert_eq!(users, 1);
+    }
+
+    #[tokio::test]
+    async fn linked_accounts_with_totp_still_need_a_code() {
+        let provider = MockProvider::new();
+        let app = TestApp::with_oidc(&provider).await;
+        let user_id = app.create_user("alice@example.com").await;
+        let secret = totp::generate_secret();
+        sqlx::query("UPDATE users SET totp_secret = $1, totp_enabled = TRUE WHERE id = $2")
+            .bind(&secret)
+            .bind(&user_id)
+            .execute(app.db())
+            .await
+            .unwrap();
+
+        let res = login_as(&app, &provider, "subject-1", "alice@example.com", |_| {}).await;
+        assert_eq!(res.status, StatusCode::SEE_OTHER);
+        assert!(res.cookie(&Cookie::Access.name()).is_none());
+        let location = res.headers[header::LOCATION].to_str().unwrap();
+        let mfa_token = location
+            .strip_prefix("http://localhost:8000/login#mfa_token=")
+            .unwrap();
+
+        let login_totp = |code: String| {
+            app.request(
+                Method::POST,
+                "/api/auth/login/totp",
+                None,
+                Some(json!({ "mfa_token": mfa_token, "code": code })),
+            )
+        };
+        let res = login_totp("000000".to_string()).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        let res = login_totp(totp::generate(
+            &secret,
+            jsonwebtoken::get_current_timestamp(),
+        ))
+        .await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(res.cookie(&Cookie::Access.name()).is_some());
+    }
+
+    #[tokio::test]
+    async fn unverified_addresses_do_not_link() {
+        let provider = MockProvider::new();
+        let app = TestApp::with_oidc(&provider).await;
+        app.create_user("alice@example.com").await;
+
+        let res = login_as(
+            &app,
+            &provider,
+            "subject-1",
+            "alice@example.com",
+            |claims| claims["email_verified"] = json!(false),
+        )
+        .await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        assert_eq!(res.json()["code"], "email_taken");
+        assert!(res.cookie(&Cookie::Access.name()).is_none());
+        assert_eq!(linked_user(&app, "subject-1").await, None);
+    }
+
+    #[tokio::test]
+    async fn first_login_creates_a_user_without_password() {
+        let provider = MockProvider::new();
+        let app = TestApp::with_oidc(&provider).await;
+
+        let res = login_as(&app, &provider, "subject-1", "bob@example.com", |_| {}).await;
+        assert_eq!(res.status, StatusCode::SEE_OTHER);
+        let user_id = linked_user(&app, "subject-1").await.unwrap();
+        let (email, password_hash, display_name): (String, String, String) =
+            sqlx::query_as("SELECT email, password_hash, display_name FROM users WHERE id = $1")
+                .bind(&user_id)
+                .fetch_one(app.db())
+                .await
+                .unwrap();
+        assert_eq!(email, "bob@example.com");
+        assert_eq!(password_hash, "");
+        assert_eq!(display_name, "Alice");
+
+        // The next login finds the account through the identity, whatever the address says
+        let res = login_as(&app, &provider, "subject-1", "bob@other.example", |_| {}).await;
+        assert_eq!(res.status, StatusCode::SEE_OTHER);
+        let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(app.db())
+            .await
+            .unwrap();
+        assert_eq!(users, 1);
+    }
+}
@@ -1,11 +1,14 @@
 use axum::extract::Request;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::{Router, routing, routing::get_service};
+use axum::{Router, middleware, routing, routing::get_service};
 use std::env;
 use tower_http::services::ServeDir;
 
-use crate::axum_app::routes::{auth, canvas};
+use crate::axum_app::csrf;
+use crate::axum_app::routes::{
+    account, admin, auth, canvas, events, invites, oidc, sessions, tokens, totp,
+};
 
 pub fn create_router() -> Router {
     let frontend_path = env::var("FRONTEND_PATH").unwrap_or_else(|_| "frontend".to_string());
@@ -13,6 +16,7 @@ pub fn create_router() -> Router {
     let static_path = format!("{}/static", frontend_path);
     let index_path = format!("{}/index.html", frontend_path);
     Router::new()
+        .route("/.well-known/jwks.json", routing::get(auth::jwks))
         .nest(
             "/api",
             Router::new()
@@ -20,14 +24,52 @@ pub fn create_router() -> Router {
                     "/auth",
                     Router::new()
                         .route("/login", routing::post(auth::login))
+                        .route("/login/totp", routing::post(totp::login_totp))
+                        .route("/totp/enroll", routing::post(totp::enroll))
+                        .route("/totp/confirm", routing::post(totp::confirm))
+                        .route("/totp/disable", routing::post(totp::disable))
+                        .route("/oidc/login", routing::get(oidc::login))
+                        .route("/oidc/callback", routing::get(oidc::callback))
                         .route("/register", routing::post(auth::register))
+                        .route("/refresh", routing::post(auth::refresh))
+                        .route("/password", routing::post(auth::change_password))
+                        .route("/password/forgot", routing::post(auth::forgot_password))
+                        .route("/password/reset", routing::post(auth::reset_password))
+                        .route("/verify/{token}", routing::get(auth::verify))
+                        .route(
+                            "/sessions",
+                            routing::get(sessions::list).delete(sessions::revoke_others),
+                        )
+                        .route("/sessions/{session_id}", routing::delete(sessions::revoke))
+                        .route("/tokens", routing::get(tokens::list).post(tokens::create))
+                        .route("/tokens/{token_id}", routing::delete(tokens::revoke))
+                        .route("/csrf", routing::get(auth::csrf))
                         .route("/me", routing::get(auth::me))
                         .route("/logout", routing::post(auth::logout)),
                 )
+                .nest(
+                    "/admin",
+                    Router::new()
+                        .route("/users", routing::get(admin::list_users))
+                        .route("/users/{id}/disable", routing::post(admin::disable_user))
+                        .route("/users/{id}/enable", routing::post(admin::enable_user))
+                        .route(
+                            "/users/{id}/reset-password",
+                            routing::post(admin::reset_user_password),
+                        )
+                        .route("/canvases", routing::get(admin::list_canvases))
+                        .route("/audit", routing::get(admin::list_audit)),
+                )
                 .nest(
                     "/canvas",
                     Router::new()
                         .route("/", routing::post(canvas::create_canvas))
+                        .route(
+                            "/{canvas_id}",
+                            routing::get(canvas::get_canvas)
+                                .patch(canvas::update_canvas)
+                                .delete(canvas::delete_canvas),
+                        )
                         .route(
                             "/{canvas_id}/right",
                             routing::post(canvas::change_canvas_right),
@@ -36,9 +78,34 @@ pub fn create_router() -> Router {
                             "/{canvas_id}/moderated",
                             routing::post(canvas::set_moderated),
                         )
+                        .route(
+                            "/{canvas_id}/public",
+                            routing::get(canvas::get_public_canvas).post(canvas::set_public),
+                        )
+                        .route("/{canvas_id}/audit", routing::get(canvas::list_audit))
+                        .route("/{canvas_id}/events", routing::get(events::list))
+                        .route("/{canvas_id}/fork", routing::post(canvas::fork_canvas))
+                        .route(
+                            "/{canvas_id}/invites",
+                            routing::get(invites::list).post(invites::create),
+                        )
+                        .route(
+                            "/{canvas_id}/invites/{invite_id}",
+                            routing::delete(invites::revoke),
+                        )
+                        .route("/invite/{token}/accept", routing::post(invites::accept))
                         .route("/datas", routing::get(canvas::get_canvases_data)),
                 )
-                .route("/user/{id}", routing::patch(auth::update_user)),
+                .route(
+                    "/user/{id}",
+                    routing::patch(auth::update_user).delete(account::delete_user),
+                )
+                .route(
+                    "/user/{id}/delete-confirmation",
+                    routing::post(account::request_deletion_confirmation),
+                )
+                .route("/user/{id}/export", routing::get(account::export_user))
+                .layer(middleware::from_fn(csrf::csrf_protection)),
         )
         .nest_service("/dist", get_service(ServeDir::new(dist_path)))
         .nest_service("/static", get_service(ServeDir::new(static_path)))
@@ -0,0 +1,86 @@
+use crate::axum_app::axum::AppState;
+use crate::axum_app::error::AppError;
+use crate::axum_app::routes::auth::broadcast_revoked;
+use crate::axum_app::transformers::ClientIp;
+use crate::shared::audit::{AuditAction, AuditEvent, record};
+use crate::shared::jwt::Claims;
+use crate::shared::session::{
+    SessionInfo, list_sessions, revoke_user_session, revoke_user_sessions,
+};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::{Extension, Json, response::IntoResponse};
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    #[serde(flatten)]
+    pub session: SessionInfo,
+    /// The session making this request
+    pub current: bool,
+    /// Has an open canvas WebSocket
+    pub websocket: bool,
+}
+
+pub async fn list(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+) -> Result<impl IntoResponse, AppError> {
+    let sessions = list_sessions(&state.db, &claims.id)
+        .await?
+        .into_iter()
+        .map(|session| SessionResponse {
+            current: session.id == claims.jti,
+            websocket: state.open_sockets.is_open(&session.id),
+            session,
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(sessions))
+}
+
+/// Revokes one session of the user and closes its sockets.
+pub async fn revoke(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Path(session_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    if !revoke_user_session(&state.db, &claims.id, &session_id).await? {
+        return Err(AppError::NotFound);
+    }
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::SessionRevoked)
+            .actor(&claims.id)
+            .target(&claims.id)
+            .ip(client_ip.0)
+            .after(json!({ "sessions": [&session_id] })),
+    )
+    .await;
+    broadcast_revoked(&state, vec![session_id]);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Logs out everywhere else: revokes all sessions of the user except the current one.
+pub async fn revoke_others(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+) -> Result<impl IntoResponse, AppError> {
+    let revoked = revoke_user_sessions(&state.db, &claims.id, Some(&claims.jti)).await?;
+    if !revoked.is_empty() {
+        record(
+            &state.db,
+            AuditEvent::new(AuditAction::SessionRevoked)
+                .actor(&claims.id)
+                .target(&claims.id)
+                .ip(client_ip.0)
+                .after(json!({ "sessions": &revoked })),
+        )
+        .await;
+    }
+    broadcast_revoked(&state, revoked);
+    Ok(StatusCode::NO_CONTENT)
+}
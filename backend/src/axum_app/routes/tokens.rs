@@ -0,0 +1,113 @@
+use crate::axum_app::axum::AppState;
+use crate::axum_app::error::AppError;
+use crate::axum_app::transformers::ClientIp;
+use crate::axum_app::validation::{MAX_FIELD_LENGTH, Validate, ValidationErrors};
+use crate::shared::audit::{AuditAction, AuditEvent, record};
+use crate::shared::jwt::Claims;
+use crate::shared::personal_token::{
+    TokenScope, create_personal_token, list_personal_tokens, revoke_personal_token,
+};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::{Extension, Json, response::IntoResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+pub struct CreateTokenPayload {
+    pub name: String,
+    pub scope: TokenScope,
+    /// Never expires when omitted
+    pub expires_in_days: Option<u32>,
+}
+
+impl Validate for CreateTokenPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.name.trim().is_empty() {
+            errors.add("name", "must not be empty");
+        }
+        if self.name.chars().count() > MAX_FIELD_LENGTH {
+            errors.add(
+                "name",
+                format!("must be at most {} characters", MAX_FIELD_LENGTH),
+            );
+        }
+        if self.expires_in_days == Some(0) {
+            errors.add("expires_in_days", "must be at least 1");
+        }
+        errors.into_result()
+    }
+}
+
+pub async fn list(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+) -> Result<impl IntoResponse, AppError> {
+    let tokens = list_personal_tokens(&state.db, &claims.id).await?;
+    Ok(Json(tokens))
+}
+
+pub async fn create(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Json(payload): Json<CreateTokenPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    payload.validate()?;
+    let name = payload.name.trim();
+    let (id, token) = create_personal_token(
+        &state.db,
+        &claims.id,
+        name,
+        payload.scope,
+        payload.expires_in_days,
+    )
+    .await?;
+    // Only the id identifies the token in the log, never its value
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::TokenCreated)
+            .actor(&claims.id)
+            .target(&claims.id)
+            .ip(client_ip.0)
+            .after(serde_json::json!({
+                "token": &id,
+                "name": name,
+                "scope": payload.scope.as_str(),
+                "expires_in_days": payload.expires_in_days,
+            })),
+    )
+    .await;
+    // The plain token is only shown once
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({ "id": id, "token": token })),
+    ))
+}
+
+pub async fn revoke(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Path(token_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let revoked = revoke_personal_token(&state.db, &claims.id, &token_id).await?;
+    if !revoked {
+        return Err(AppError::NotFound);
+    }
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::TokenRevoked)
+            .actor(&claims.id)
+            .target(&claims.id)
+            .ip(client_ip.0)
+            .after(serde_json::json!({ "token": &token_id })),
+    )
+    .await;
+    // Close the sockets opened with the token
+    let _ = state
+        .ws_sender
+        .send(crate::shared::CanvasDataEvent::SessionRevoked(token_id));
+    Ok(StatusCode::NO_CONTENT)
+}
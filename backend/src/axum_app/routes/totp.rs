@@ -0,0 +1,392 @@
+use crate::axum_app::axum::AppState;
+use crate::axum_app::error::{AppError, AuthError, Conflict};
+use crate::axum_app::routes::auth::{start_session, verify_password};
+use crate::axum_app::transformers::{ClientIp, UserAgent};
+use crate::shared::audit::{AuditAction, AuditEvent, record};
+use crate::shared::jwt::{Claims, KEYS, MfaPendingClaims};
+use crate::shared::token::hash_token;
+use crate::shared::totp;
+use axum::http::StatusCode;
+use axum::{Extension, Json, response::IntoResponse};
+use data_encoding::BASE32_NOPAD;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::sync::Arc;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Serialize)]
+pub struct EnrollResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct CodePayload {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DisablePayload {
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpLoginPayload {
+    pub mfa_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    OsRng.fill_bytes(&mut bytes);
+    let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+    format!("{}-{}", &code[..4], &code[4..])
+}
+
+/// Recovery codes are compared case- and separator-insensitively.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+/// Accepts a TOTP code once per time step, so an observed code can't be replayed.
+async fn check_totp_code(state: &AppState, user_id: &str, code: &str) -> Result<(), AppError> {
+    let row = sqlx::query("SELECT totp_secret FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&*state.db)
+        .await?;
+    let secret: Option<String> = row.try_get("totp_secret")?;
+    let secret = secret.ok_or(AuthError::WrongCredentials)?;
+    let step = totp::verify(&secret, code, jsonwebtoken::get_current_timestamp())
+        .ok_or(AuthError::WrongCredentials)?;
+    // Only one of concurrent requests with the same code can advance the step
+    let res = sqlx::query(
+        "UPDATE users SET totp_last_step = $1 WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)",
+    )
+    .bind(step as i64)
+    .bind(user_id)
+    .execute(&*state.db)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(AuthError::WrongCredentials.into());
+    }
+    Ok(())
+}
+
+async fn use_recovery_code(state: &AppState, user_id: &str, code: &str) -> Result<(), AppError> {
+    let res = sqlx::query(
+        "UPDATE recovery_codes SET used_at = datetime('now') WHERE code_hash = $1 AND user_id = $2 AND used_at IS NULL",
+    )
+    .bind(hash_recovery_code(code))
+    .bind(user_id)
+    .execute(&*state.db)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(AuthError::WrongCredentials.into());
+    }
+    Ok(())
+}
+
+pub async fn enroll(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+) -> Result<impl IntoResponse, AppError> {
+    let secret = totp::generate_secret();
+    // The secret stays inactive until a code generated from it is confirmed
+    let res = sqlx::query(
+        "UPDATE users SET totp_secret = $1, totp_last_step = NULL WHERE id = $2 AND totp_enabled = FALSE",
+    )
+    .bind(&secret)
+    .bind(&claims.id)
+    .execute(&*state.db)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(Conflict::TotpAlreadyEnabled.into());
+    }
+    let issuer = std::env::var("TOTP_ISSUER").unwrap_or("Drawer".to_string());
+    Ok(Json(EnrollResponse {
+        otpauth_uri: totp::otpauth_uri(&issuer, &claims.email, &secret),
+        secret,
+    }))
+}
+
+pub async fn confirm(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Json(payload): Json<CodePayload>,
+) -> Result<impl IntoResponse, AppError> {
+    check_totp_code(&state, &claims.id, &payload.code).await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let mut tx = state.db.begin().await?;
+    let res =
+        sqlx::query("UPDATE users SET totp_enabled = TRUE WHERE id = $1 AND totp_enabled = FALSE")
+            .bind(&claims.id)
+            .execute(&mut *tx)
+            .await?;
+    if res.rows_affected() == 0 {
+        return Err(Conflict::TotpAlreadyEnabled.into());
+    }
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(&claims.id)
+        .execute(&mut *tx)
+        .await?;
+    for code in &codes {
+        sqlx::query("INSERT INTO recovery_codes (code_hash, user_id) VALUES ($1, $2)")
+            .bind(hash_recovery_code(code))
+            .bind(&claims.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::TotpEnabled)
+            .actor(&claims.id)
+            .target(&claims.id)
+            .ip(client_ip.0),
+    )
+    .await;
+    // Recovery codes are only shown once
+    Ok(Json(serde_json::json!({ "recovery_codes": codes })))
+}
+
+pub async fn disable(
+    state: Extension<Arc<AppState>>,
+    claims: Claims,
+    client_ip: ClientIp,
+    Json(payload): Json<DisablePayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let row = sqlx::query("SELECT password_hash FROM users WHERE id = $1")
+        .bind(&claims.id)
+        .fetch_one(&*state.db)
+        .await?;
+    let hash: String = row.try_get("password_hash")?;
+    verify_password(&payload.password, &hash)?;
+
+    let mut tx = state.db.begin().await?;
+    sqlx::query(
+        "UPDATE users SET totp_enabled = FALSE, totp_secret = NULL, totp_last_step = NULL WHERE id = $1",
+    )
+    .bind(&claims.id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(&claims.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    record(
+        &state.db,
+        AuditEvent::new(AuditAction::TotpDisabled)
+            .actor(&claims.id)
+            .target(&claims.id)
+            .ip(client_ip.0),
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Second login step: exchanges the pending token and a TOTP or recovery code for a session.
+pub async fn login_totp(
+    state: Extension<Arc<AppState>>,
+    client_ip: ClientIp,
+    user_agent: UserAgent,
+    Json(payload): Json<TotpLoginPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let pending = KEYS
+        .decode::<MfaPendingClaims>(&payload.mfa_token)
+        .map_err(|_| AuthError::InvalidToken)?
+        .claims;
+    let user_id = pending.mfa_user_id;
+
+    // Six digits are guessable, so failures are throttled per account as well
+    let mfa_key = format!("login-mfa:{}", user_id);
+    if let Err(retry_after) = state.throttle.attempt(&[&mfa_key]).await {
+        return Err(AuthError::TooManyRequests(retry_after).into());
+    }
+    let res = match (payload.code.as_deref(), payload.recovery_code.as_deref()) {
+        (Some(code), _) => check_totp_code(&state, &user_id, code).await,
+        (None, Some(recovery_code)) => use_recovery_code(&state, &user_id, recovery_code).await,
+        (None, None) => Err(AuthError::MissingCredentials.into()),
+    };
+    if let Err(e) = res {
+        if matches!(e, AppError::Auth(AuthError::WrongCredentials)) {
+            record(
+                &state.db,
+                AuditEvent::new(AuditAction::LoginFailed)
+                    .target(&user_id)
+                    .ip(client_ip.0)
+                    .after(serde_json::json!({ "factor": "totp" })),
+            )
+            .await;
+        }
+        return Err(e);
+    }
+    state.throttle.reset(&mfa_key).await;
+
+    let row =
+        sqlx::query("SELECT email, display_name FROM users WHERE id = $1 AND totp_enabled = TRUE")
+            .bind(&user_id)
+            .fetch_optional(&*state.db)
+            .await?
+            .ok_or(AuthError::WrongCredentials)?;
+    let email: String = row.try_get("email")?;
+    let display_name: String = row.try_get("display_name")?;
+    start_session(
+        &state,
+        &user_id,
+        &email,
+        &display_name,
+        &client_ip,
+        &user_agent,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axum_app::test_support::{PASSWORD, TestApp};
+    use crate::shared::totp;
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    /// Enrolls and confirms TOTP for a new user, returns the secret.
+    async fn user_with_totp(app: &TestApp, email: &str) -> String {
+        let user_id = app.create_user(email).await;
+        let cookie = app.login(&user_id).await;
+        let res = app
+            .request(Method::POST, "/api/auth/totp/enroll", Some(&cookie), None)
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        let secret = res.json()["secret"].as_str().unwrap().to_string();
+        let code = totp::generate(&secret, jsonwebtoken::get_current_timestamp());
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/totp/confirm",
+                Some(&cookie),
+                Some(json!({ "code": code })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.json()["recovery_codes"].as_array().unwrap().len(), 10);
+        // Confirming used up the current step, later logins start from scratch
+        sqlx::query("UPDATE users SET totp_last_step = NULL WHERE id = $1")
+            .bind(&user_id)
+            .execute(app.db())
+            .await
+            .unwrap();
+        secret
+    }
+
+    async fn mfa_token(app: &TestApp, email: &str) -> String {
+        let res = app
+            .request(
+                Method::POST,
+                "/api/auth/login",
+                None,
+                Some(json!({ "email": email, "password": PASSWORD })),
+            )
+            .await;
+        assert_eq!(res.json()["mfa_required"], true);
+        res.json()["mfa_token"].as_str().unwrap().to_string()
+    }
+
+    async fn login_totp(app: &TestApp, mfa_token: &str, code: &str) -> StatusCode {
+        app.request(
+            Method::POST,
+            "/api/auth/login/totp",
+            None,
+            Some(json!({ "mfa_token": mfa_token, "code": code })),
+        )
+        .await
+        .status
+    }
+
+    #[tokio::test]
+    async fn code_is_accepted_once() {
+        let app = TestApp::new().await;
+        let secret = user_with_totp(&app, "alice@example.com").await;
+        let code = totp::generate(&secret, jsonwebtoken::get_current_timestamp());
+
+        let token = mfa_token(&app, "alice@example.com").await;
+        assert_eq!(login_totp(&app, &token, &code).await, StatusCode::OK);
+        let token = mfa_token(&app, "alice@example.com").await;
+        assert_eq!(
+            login_totp(&app, &token, &code).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_replays_are_rejected() {
+        let app = TestApp::new().await;
+        let secret = user_with_totp(&app, "alice@example.com").await;
+        let code = totp::generate(&secret, jsonwebtoken::get_current_timestamp());
+        let mut tokens = Vec::new();
+        for _ in 0..4 {
+            tokens.push(mfa_token(&app, "alice@example.com").await);
+        }
+
+        let statuses =
+            futures::future::join_all(tokens.iter().map(|token| login_totp(&app, token, &code)))
+                .await;
+        assert_eq!(
+            statuses.iter().filter(|s| **s == StatusCode::OK).count(),
+            1,
+            "{:?}",
+            statuses
+        );
+    }
+
+    #[tokio::test]
+    async fn recovery_code_is_single_use() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let cookie = app.login(&user_id).await;
+        let secret = app
+            .request(Method::POST, "/api/auth/totp/enroll", Some(&cookie), None)
+            .await
+            .json()["secret"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let code = totp::generate(&secret, jsonwebtoken::get_current_timestamp());
+        let recovery_code = app
+            .request(
+                Method::POST,
+                "/api/auth/totp/confirm",
+                Some(&cookie),
+                Some(json!({ "code": code })),
+            )
+            .await
+            .json()["recovery_codes"][0]
+            .as_str()
+            .unwrap()
+            .to_uppercase();
+
+        for expected in [StatusCode::OK, StatusCode::UNAUTHORIZED] {
+            let token = mfa_token(&app, "alice@example.com").await;
+            let res = app
+                .request(
+                    Method::POST,
+                    "/api/auth/login/totp",
+                    None,
+                    Some(json!({ "mfa_token": token, "recovery_code": recovery_code })),
+                )
+                .await;
+            assert_eq!(res.status, expected);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! A migrated scratch database and the full router for tests of the HTTP API.
+use axum::body::{Body, Bytes};
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderMap, Method, Request, StatusCode, header};
+use axum::{Extension, Router};
+use sqlx::SqlitePool;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock};
+use tower::ServiceExt;
+
+use crate::axum_app::axum::AppState;
+use crate::axum_app::csrf::CSRF_HEADER;
+use crate::axum_app::oidc::mock::MockProvider;
+use crate::axum_app::routes::create_router;
+use crate::axum_app::throttle::Throttle;
+use crate::shared::jwt::{Claims, KEYS, access_token_exp};
+use crate::shared::mail::FileOutbox;
+use crate::shared::open_sockets::OpenSockets;
+use crate::shared::session::create_session;
+
+pub const PASSWORD: &str = "correct horse battery";
+const CSRF_TOKEN: &str = "test-csrf-token";
+
+/// Hashing is slow in debug builds, all test users share the hash of `PASSWORD`
+static PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+    use argon2::PasswordHasher;
+    let salt = argon2::password_hash::SaltString::generate(&mut rand_core::OsRng);
+    argon2::Argon2::default()
+        .hash_password(PASSWORD.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+});
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Applies all migrations in order, like the deployment does with the sqlx CLI.
+async fn migrate(pool: &SqlitePool) {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    for file in files {
+        let sql = std::fs::read_to_string(&file).unwrap();
+        sqlx::raw_sql(&sql)
+            .execute(pool)
+            .await
+            .unwrap_or_else(|e| panic!("Migration {} failed: {:?}", file.display(), e));
+    }
+}
+
+pub struct TestApp {
+    pub state: Arc<AppState>,
+    router: Router,
+    dir: PathBuf,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| {
+            panic!(
+                "Invalid JSON ({:?}): {}",
+                e,
+                String::from_utf8_lossy(&self.body)
+            )
+        })
+    }
+
+    /// Value of a cookie set by the response
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .find_map(|v| {
+                let pair = v.split(';').next()?;
+                let (key, value) = pair.split_once('=')?;
+                (key == name).then(|| value.to_string())
+            })
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl TestApp {
+    /// The app on an empty database in its own directory, mails go to `outbox`.
+    pub async fn new() -> Self {
+        Self::build(None).await
+    }
+
+    /// Like `new`, with OpenID logins against the provider.
+    pub async fn with_oidc(provider: &Arc<MockProvider>) -> Self {
+        Self::build(Some(provider)).await
+    }
+
+    async fn build(provider: Option<&Arc<MockProvider>>) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "drawer-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // A file instead of `:memory:` so every connection of the pool sees the same database
+        let pool = SqlitePoolOptions::new()
+            .connect_with(
+                SqliteConnectOptions::new()
+                    .filename(dir.join("test.db"))
+                    .create_if_missing(true),
+            )
+            .await
+            .unwrap();
+        migrate(&pool).await;
+        let db = Arc::new(pool);
+        let (ws_sender, _) = tokio::sync::broadcast::channel(100);
+        let state = Arc::new(AppState {
+            throttle: Throttle::from_env(db.clone()),
+            db,
+            ws_sender,
+            mailer: Arc::new(FileOutbox::new(dir.join("outbox"))),
+            oidc: provider.map(|provider| Arc::new(provider.client())),
+            open_sockets: OpenSockets::default(),
+        });
+        let router = create_router().layer(Extension(state.clone()));
+        Self { state, router, dir }
+    }
+
+    pub fn db(&self) -> &SqlitePool {
+        &self.state.db
+    }
+
+    /// Creates a verified user with `PASSWORD` and returns its id.
+    pub async fn create_user(&self, email: &str) -> String {
+        sqlx::query_scalar(
+            "INSERT INTO users (email, display_name, password_hash, email_verified) VALUES ($1, $2, $3, TRUE) RETURNING id",
+        )
+        .bind(email)
+        .bind(email.split('@').next().unwrap())
+        .bind(&*PASSWORD_HASH)
+        .fetch_one(self.db())
+        .await
+        .unwrap()
+    }
+
+    /// Starts a session for the user and returns its access token cookie.
+    pub async fn login(&self, user_id: &str) -> String {
+        let jti = create_session(self.db(), user_id, None, None)
+            .await
+            .unwrap();
+        let email: String = sqlx::query_scalar("SELECT email FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(self.db())
+            .await
+            .unwrap();
+        let claims = Claims {
+            email,
+            exp: access_token_exp(),
+            display_name: String::new(),
+            id: user_id.to_string(),
+            jti,
+            scope: None,
+        };
+        format!("access_token={}", KEYS.encode(&claims).unwrap())
+    }
+
+    /// Creates a canvas with the given members and returns its id.
+    pub async fn create_canvas(&self, members: &[(&str, &str)]) -> String {
+        let canvas_id: String =
+            sqlx::query_scalar("INSERT INTO canvas (title) VALUES ('Test') RETURNING id")
+                .fetch_one(self.db())
+                .await
+                .unwrap();
+        for (user_id, right) in members {
+            sqlx::query("INSERT INTO user_canvas (user_id, canvas_id, right) VALUES ($1, $2, $3)")
+                .bind(user_id)
+                .bind(&canvas_id)
+                .bind(right)
+                .execute(self.db())
+                .await
+                .unwrap();
+        }
+        canvas_id
+    }
+
+    /// Sends a request as a browser would: with the cookies, the matching CSRF header
+    /// and a JSON body.
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        cookie: Option<&str>,
+        body: Option<serde_json::Value>,
+    ) -> TestResponse {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(cookie) = cookie {
+            builder = builder
+                .header(
+                    header::COOKIE,
+                    format!("{}; csrf_token={}", cookie, CSRF_TOKEN),
+                )
+                .header(CSRF_HEADER, CSRF_TOKEN);
+        }
+        let body = match body {
+            Some(body) => {
+                builder = builder.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        self.send(builder.body(body).unwrap()).await
+    }
+
+    /// Sends the request as is, from 127.0.0.1 unless it carries its own `ConnectInfo`.
+    pub async fn send(&self, mut request: Request<Body>) -> TestResponse {
+        if request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .is_none()
+        {
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 50000))));
+        }
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// Contents of the mails sent so far, oldest first.
+    pub fn outbox(&self) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(self.dir.join("outbox")) else {
+            return Vec::new();
+        };
+        let mut files: Vec<PathBuf> = entries.map(|entry| entry.unwrap().path()).collect();
+        files.sort();
+        files
+            .iter()
+            .map(|file| std::fs::read_to_string(file).unwrap())
+            .collect()
+    }
+}
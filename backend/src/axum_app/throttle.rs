@@ -0,0 +1,392 @@
+use futures::future::BoxFuture;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Failed attempts of a single key (an IP or an account)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AttemptState {
+    pub failures: u32,
+    /// Unix timestamps in seconds
+    pub last_failure: u64,
+    pub locked_until: Option<u64>,
+}
+
+/// Result of counting an attempt
+pub enum Attempt {
+    Counted(AttemptState),
+    /// The key was locked, nothing was counted
+    Locked {
+        until: u64,
+    },
+}
+
+/// Persists attempt counters, either in memory or in SQLite so lockouts survive restarts.
+/// Counting is a single atomic step, so concurrent requests can't both see the last free attempt.
+pub trait AttemptStore: Send + Sync {
+    /// Counts an attempt of an unlocked key and applies `next_state`.
+    fn count(&self, key: &str, now: u64) -> BoxFuture<'_, anyhow::Result<Attempt>>;
+    /// Takes back a counted attempt that turned out to be legitimate.
+    fn uncount(&self, key: &str) -> BoxFuture<'_, anyhow::Result<()>>;
+    fn remove(&self, key: &str) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+/// State after one more attempt, failures older than the window are forgotten.
+fn next_state(previous: Option<AttemptState>, now: u64) -> AttemptState {
+    let failures = match previous {
+        Some(state) if state.last_failure + THROTTLE.window > now => state.failures + 1,
+        _ => 1,
+    };
+    let mut locked_until = None;
+    if failures >= THROTTLE.max_attempts {
+        // Double the lockout for every failure beyond the limit
+        let exponent = (failures - THROTTLE.max_attempts).min(20);
+        let lockout = (THROTTLE.lockout_base << exponent).min(THROTTLE.lockout_max);
+        locked_until = Some(now + lockout);
+    }
+    AttemptState {
+        failures,
+        last_failure: now,
+        locked_until,
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    attempts: Mutex<HashMap<String, AttemptState>>,
+}
+
+impl AttemptStore for MemoryStore {
+    fn count(&self, key: &str, now: u64) -> BoxFuture<'_, anyhow::Result<Attempt>> {
+        let mut attempts = self.attempts.lock().unwrap();
+        let previous = attempts.get(key).copied();
+        let attempt = match previous.and_then(|s| s.locked_until) {
+            Some(until) if until > now => Attempt::Locked { until },
+            _ => {
+                // Drop stale entries so spoofed keys can't grow the map forever
+                if attempts.len() > 10_000 {
+                    attempts.retain(|_, s| {
+                        s.locked_until.is_some_and(|until| until > now)
+                            || s.last_failure + THROTTLE.window > now
+                    });
+                }
+                let state = next_state(previous, now);
+                attempts.insert(key.to_string(), state);
+                Attempt::Counted(state)
+            }
+        };
+        Box::pin(async move { Ok(attempt) })
+    }
+
+    fn uncount(&self, key: &str) -> BoxFuture<'_, anyhow::Result<()>> {
+        if let Some(state) = self.attempts.lock().unwrap().get_mut(key) {
+            state.failures = state.failures.saturating_sub(1);
+            if state.failures < THROTTLE.max_attempts {
+                state.locked_until = None;
+            }
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn remove(&self, key: &str) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.attempts.lock().unwrap().remove(key);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+pub struct SqliteStore {
+    db: Arc<SqlitePool>,
+}
+
+impl AttemptStore for SqliteStore {
+    fn count(&self, key: &str, now: u64) -> BoxFuture<'_, anyhow::Result<Attempt>> {
+        let key = key.to_string();
+        Box::pin(async move {
+            // `next_state` in one statement: reading the previous count and writing the new
+            // one can't interleave with another request. Locked keys are left untouched and
+            // return no row.
+            let row = sqlx::query(
+                "INSERT INTO login_attempts (key, failures, last_failure, locked_until) SELECT $1, failures, $2, CASE WHEN failures >= $3 THEN $2 + min($4 << min(failures - $3, 20), $5) END FROM (SELECT coalesce((SELECT failures FROM login_attempts WHERE key = $1 AND last_failure + $6 > $2), 0) + 1 AS failures) WHERE true ON CONFLICT (key) DO UPDATE SET failures = excluded.failures, last_failure = excluded.last_failure, locked_until = excluded.locked_until WHERE login_attempts.locked_until IS NULL OR login_attempts.locked_until <= $2 RETURNING failures, locked_until",
+            )
+            .bind(&key)
+            .bind(now as i64)
+            .bind(THROTTLE.max_attempts as i64)
+            .bind(THROTTLE.lockout_base as i64)
+            .bind(THROTTLE.lockout_max as i64)
+            .bind(THROTTLE.window as i64)
+            .fetch_optional(&*self.db)
+            .await?;
+            let Some(row) = row else {
+                let until: Option<i64> =
+                    sqlx::query_scalar("SELECT locked_until FROM login_attempts WHERE key = $1")
+                        .bind(&key)
+                        .fetch_optional(&*self.db)
+                        .await?
+                        .flatten();
+                return Ok(Attempt::Locked {
+                    until: until.map_or(now, |until| until as u64),
+                });
+            };
+            Ok(Attempt::Counted(AttemptState {
+                failures: row.try_get::<i64, _>("failures")? as u32,
+                last_failure: now,
+                locked_until: row
+                    .try_get::<Option<i64>, _>("locked_until")?
+                    .map(|until| until as u64),
+            }))
+        })
+    }
+
+    fn uncount(&self, key: &str) -> BoxFuture<'_, anyhow::Result<()>> {
+        let key = key.to_string();
+        Box::pin(async move {
+            sqlx::query(
+                "UPDATE login_attempts SET failures = failures - 1, locked_until = CASE WHEN failures - 1 >= $2 THEN locked_until END WHERE key = $1 AND failures > 0",
+            )
+            .bind(&key)
+            .bind(THROTTLE.max_attempts as i64)
+            .execute(&*self.db)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn remove(&self, key: &str) -> BoxFuture<'_, anyhow::Result<()>> {
+        let key = key.to_string();
+        Box::pin(async move {
+            sqlx::query("DELETE FROM login_attempts WHERE key = $1")
+                .bind(&key)
+                .execute(&*self.db)
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+/// Limits, configurable via `LOGIN_MAX_ATTEMPTS`, `LOGIN_ATTEMPT_WINDOW`,
+/// `LOGIN_LOCKOUT_BASE` and `LOGIN_LOCKOUT_MAX` (seconds)
+pub static THROTTLE: std::sync::LazyLock<ThrottleConfig> =
+    std::sync::LazyLock::new(|| ThrottleConfig {
+        max_attempts: number_from_env("LOGIN_MAX_ATTEMPTS", 5) as u32,
+        window: number_from_env("LOGIN_ATTEMPT_WINDOW", 15 * 60),
+        lockout_base: number_from_env("LOGIN_LOCKOUT_BASE", 30),
+        lockout_max: number_from_env("LOGIN_LOCKOUT_MAX", 60 * 60),
+    });
+
+pub struct ThrottleConfig {
+    /// Failures allowed before the first lockout
+    pub max_attempts: u32,
+    /// Failures older than this are forgotten
+    pub window: u64,
+    pub lockout_base: u64,
+    pub lockout_max: u64,
+}
+
+fn number_from_env(key: &str, default: u64) -> u64 {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Tracks failed attempts per key and locks keys out with exponential backoff.
+#[derive(Clone)]
+pub struct Throttle {
+    store: Arc<dyn AttemptStore>,
+}
+
+impl Throttle {
+    /// Creates the throttle with the store selected by `LOGIN_THROTTLE_STORE` (`memory` or `sqlite`).
+    pub fn from_env(db: Arc<SqlitePool>) -> Self {
+        let store: Arc<dyn AttemptStore> = match std::env::var("LOGIN_THROTTLE_STORE").as_deref() {
+            Ok("sqlite") => Arc::new(SqliteStore { db }),
+            _ => Arc::new(MemoryStore::default()),
+        };
+        Self { store }
+    }
+
+    /// Counts an attempt against every key before the credentials are checked, so parallel
+    /// guesses can't exceed the limit. Returns the new states in the order of the keys, or the
+    /// remaining lockout in seconds if one of the keys is locked; then nothing is counted.
+    pub async fn attempt(&self, keys: &[&str]) -> Result<Vec<AttemptState>, u64> {
+        let now = jsonwebtoken::get_current_timestamp();
+        let mut states = Vec::with_capacity(keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            match self.store.count(key, now).await {
+                Ok(Attempt::Counted(state)) => states.push(state),
+                Ok(Attempt::Locked { until }) => {
+                    for counted in &keys[..i] {
+                        self.release(counted).await;
+                    }
+                    return Err(until.saturating_sub(now).max(1));
+                }
+                Err(e) => {
+                    tracing::error!("Failed to count attempt of {}: {:?}", key, e);
+                    states.push(AttemptState::default());
+                }
+            }
+        }
+        Ok(states)
+    }
+
+    /// Takes back the attempt of a request that succeeded, without forgetting earlier failures.
+    pub async fn release(&self, key: &str) {
+        if let Err(e) = self.store.uncount(key).await {
+            tracing::error!("Failed to release attempt of {}: {:?}", key, e);
+        }
+    }
+
+    /// Forgets all failures of the key.
+    pub async fn reset(&self, key: &str) {
+        if let Err(e) = self.store.remove(key).await {
+            tracing::error!("Failed to reset attempts of {}: {:?}", key, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axum_app::test_support::TestApp;
+
+    async fn stores() -> (TestApp, Vec<Throttle>) {
+        let app = TestApp::new().await;
+        let sqlite = Throttle {
+            store: Arc::new(SqliteStore {
+                db: app.state.db.clone(),
+            }),
+        };
+        let memory = Throttle {
+            store: Arc::new(MemoryStore::default()),
+        };
+        (app, vec![memory, sqlite])
+    }
+
+    #[tokio::test]
+    async fn locks_after_max_attempts() {
+        let (_app, throttles) = stores().await;
+        for throttle in throttles {
+            for failures in 1..THROTTLE.max_attempts {
+                let states = throttle.attempt(&["key"]).await.unwrap();
+                assert_eq!(states[0].failures, failures);
+                assert_eq!(states[0].locked_until, None);
+            }
+            // The last allowed attempt locks the key for the following ones
+            let states = throttle.attempt(&["key"]).await.unwrap();
+            assert!(states[0].locked_until.is_some());
+            let retry_after = throttle.attempt(&["key"]).await.unwrap_err();
+            assert!(retry_after > 0 && retry_after <= THROTTLE.lockout_base);
+
+            throttle.reset("key").await;
+            assert_eq!(throttle.attempt(&["key"]).await.unwrap()[0].failures, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn lockout_doubles_and_window_expires() {
+        let (_app, throttles) = stores().await;
+        let max = THROTTLE.max_attempts;
+        let base = THROTTLE.lockout_base;
+        for throttle in throttles {
+            let store = &throttle.store;
+            let now = 1_000_000;
+            for _ in 0..max {
+                store.count("key", now).await.unwrap();
+            }
+            assert!(matches!(
+                store.count("key", now + base - 1).await.unwrap(),
+                Attempt::Locked { until } if until == now + base
+            ));
+            let Attempt::Counted(state) = store.count("key", now + base).await.unwrap() else {
+                panic!("lockout did not expire");
+            };
+            assert_eq!(state.failures, max + 1);
+            assert_eq!(state.locked_until, Some(now + 3 * base));
+
+            // A failure long after the last one starts over
+            let later = now + 3 * base + THROTTLE.window;
+            let Attempt::Counted(state) = store.count("key", later).await.unwrap() else {
+                panic!("key is still locked");
+            };
+            assert_eq!(state.failures, 1);
+            assert_eq!(state.locked_until, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn locked_key_counts_nothing_on_the_others() {
+        let (_app, throttles) = stores().await;
+        for throttle in throttles {
+            for _ in 0..THROTTLE.max_attempts {
+                throttle.attempt(&["ip"]).await.unwrap();
+            }
+            throttle.attempt(&["account", "ip"]).await.unwrap_err();
+            assert_eq!(throttle.attempt(&["account"]).await.unwrap()[0].failures, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn release_takes_back_the_attempt() {
+        let (_app, throttles) = stores().await;
+        for throttle in throttles {
+            for _ in 0..THROTTLE.max_attempts {
+                throttle.attempt(&["key"]).await.unwrap();
+            }
+            // The last attempt succeeded, so it neither counts nor locks
+            throttle.release("key").await;
+            let states = throttle.attempt(&["key"]).await.unwrap();
+            assert_eq!(states[0].failures, THROTTLE.max_attempts);
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_attempts_stay_within_the_limit() {
+        let (_app, throttles) = stores().await;
+        for throttle in throttles {
+            let results =
+                futures::future::join_all((0..20).map(|_| throttle.attempt(&["key"]))).await;
+            let allowed = results.iter().filter(|result| result.is_ok()).count();
+            assert_eq!(allowed, THROTTLE.max_attempts as usize);
+        }
+    }
+
+    #[tokio::test]
+    async fn login_locks_out_the_account() {
+        use axum::http::{Method, StatusCode, header};
+        use serde_json::json;
+
+        let app = TestApp::new().await;
+        app.create_user("alice@example.com").await;
+        let login = |password: &str| {
+            app.request(
+                Method::POST,
+                "/api/auth/login",
+                None,
+                Some(json!({ "email": "alice@example.com", "password": password })),
+            )
+        };
+        let statuses: Vec<StatusCode> =
+            futures::future::join_all((0..8).map(|_| login("wrong password")))
+                .await
+                .iter()
+                .map(|res| res.status)
+                .collect();
+        let checked = statuses
+            .iter()
+            .filter(|status| **status == StatusCode::UNAUTHORIZED)
+            .count();
+        assert_eq!(checked, THROTTLE.max_attempts as usize, "{:?}", statuses);
+
+        // Even the right password has to wait
+        let res = login(crate::axum_app::test_support::PASSWORD).await;
+        assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers.contains_key(header::RETRY_AFTER));
+        let lockouts: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM account_lockouts WHERE email = 'alice@example.com'",
+        )
+        .fetch_one(app.db())
+        .await
+        .unwrap();
+        assert_eq!(lockouts, 1);
+    }
+}
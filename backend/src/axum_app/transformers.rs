@@ -1,21 +1,172 @@
+use axum::extract::{ConnectInfo, OriginalUri};
+use axum::http::Method;
 use axum::http::request::Parts;
+use jsonwebtoken::errors::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 
 use crate::{
-    axum_app::error::AuthError,
-    shared::jwt::{Claims, parse_jwt_from_cookies},
+    axum_app::{
+        axum::AppState,
+        error::{AppError, AuthError},
+    },
+    shared::{
+        admin::get_account_status,
+        cookies::Cookie,
+        jwt::{Claims, get_bearer_token, parse_jwt},
+        personal_token::{TOKEN_PREFIX, TokenScope, authenticate_personal_token},
+        session::{is_session_active, touch_session},
+    },
 };
 
 impl axum::extract::FromRequestParts<()> for Claims {
-    type Rejection = AuthError;
+    type Rejection = AppError;
     async fn from_request_parts(parts: &mut Parts, _state: &()) -> Result<Self, Self::Rejection> {
-        // Extract JWT from cookie instead of Authorization header
-        let cookies = parts
+        let state = parts
+            .extensions
+            .get::<Arc<AppState>>()
+            .ok_or(AuthError::InvalidToken)?;
+        // Scripts send `Authorization: Bearer`, browsers the access token cookie
+        let bearer = parts
             .headers
-            .get(axum::http::header::COOKIE)
-            .ok_or(AuthError::MissingCredentials)?
-            .to_str()
-            .map_err(|_| AuthError::InvalidToken)?;
-        let token_data = parse_jwt_from_cookies(cookies).map_err(|_| AuthError::InvalidToken)?;
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(get_bearer_token);
+        if let Some(token) = bearer.filter(|t| t.starts_with(TOKEN_PREFIX)) {
+            let claims = authenticate_personal_token(&state.db, token)
+                .await?
+                .ok_or(AuthError::InvalidToken)?;
+            check_token_scope(parts, &claims)?;
+            check_account(state, &claims).await?;
+            return Ok(claims);
+        }
+        let token_data = match bearer {
+            Some(jwt) => parse_jwt(jwt),
+            None => {
+                // The cookie is gone once the token expired, the client has to refresh either way
+                let jwt = parts
+                    .headers
+                    .get(axum::http::header::COOKIE)
+                    .and_then(|c| c.to_str().ok())
+                    .and_then(|cookies| Cookie::Access.get(cookies))
+                    .ok_or(AuthError::MissingCredentials)?;
+                parse_jwt(jwt)
+            }
+        }
+        .map_err(|e| match e {
+            ErrorKind::ExpiredSignature => AuthError::ExpiredToken,
+            _ => AuthError::InvalidToken,
+        })?;
+        // Reject tokens whose session was revoked (e.g. by logout)
+        let active = is_session_active(&state.db, &token_data.claims.jti).await?;
+        if !active {
+            return Err(AuthError::RevokedToken.into());
+        }
+        check_account(state, &token_data.claims).await?;
+        touch_session(&state.db, &token_data.claims.jti).await?;
         Ok(token_data.claims)
     }
 }
+
+/// Disabled accounts are locked out right away, not only once their tokens expire.
+async fn check_account(state: &AppState, claims: &Claims) -> Result<(), AppError> {
+    let status = get_account_status(&state.db, &claims.id)
+        .await?
+        .ok_or(AuthError::InvalidToken)?;
+    if status.disabled {
+        return Err(AuthError::AccountDisabled.into());
+    }
+    Ok(())
+}
+
+/// An instance administrator. The flag is read from the database on every request,
+/// so revoking it takes effect immediately.
+pub struct Admin(pub Claims);
+
+impl axum::extract::FromRequestParts<()> for Admin {
+    type Rejection = AppError;
+    async fn from_request_parts(parts: &mut Parts, state: &()) -> Result<Self, Self::Rejection> {
+        let claims =
+            <Claims as axum::extract::FromRequestParts<()>>::from_request_parts(parts, state)
+                .await?;
+        let app_state = parts
+            .extensions
+            .get::<Arc<AppState>>()
+            .ok_or(AuthError::InvalidToken)?;
+        let status = get_account_status(&app_state.db, &claims.id).await?;
+        if !status.is_some_and(|status| status.is_admin) {
+            return Err(AuthError::AdminRequired.into());
+        }
+        Ok(Admin(claims))
+    }
+}
+
+/// Personal access tokens only reach the canvas API and `/api/auth/me`, so a leaked token
+/// can't manage the account. Read tokens are further limited to safe methods.
+fn check_token_scope(parts: &Parts, claims: &Claims) -> Result<(), AuthError> {
+    let path = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(parts.uri.path(), |uri| uri.path());
+    let allowed =
+        path == "/api/canvas" || path.starts_with("/api/canvas/") || path == "/api/auth/me";
+    let safe = matches!(parts.method, Method::GET | Method::HEAD);
+    if !allowed || (claims.scope == Some(TokenScope::Read) && !safe) {
+        return Err(AuthError::InsufficientScope);
+    }
+    Ok(())
+}
+
+/// Address of the client, taken from `X-Forwarded-For` when `TRUST_FORWARDED_FOR` is set
+/// (i.e. behind a reverse proxy), otherwise from the TCP connection.
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl axum::extract::FromRequestParts<()> for ClientIp {
+    type Rejection = std::convert::Infallible;
+    async fn from_request_parts(parts: &mut Parts, _state: &()) -> Result<Self, Self::Rejection> {
+        let trust_forwarded = std::env::var("TRUST_FORWARDED_FOR").is_ok_and(|v| v == "true");
+        let forwarded = parts
+            .headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+        let connected = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip());
+        Ok(ClientIp(if trust_forwarded {
+            forwarded.or(connected)
+        } else {
+            connected
+        }))
+    }
+}
+
+/// `User-Agent` header of the client, stored with new sessions
+pub struct UserAgent(pub Option<String>);
+
+impl axum::extract::FromRequestParts<()> for UserAgent {
+    type Rejection = std::convert::Infallible;
+    async fn from_request_parts(parts: &mut Parts, _state: &()) -> Result<Self, Self::Rejection> {
+        Ok(UserAgent(
+            parts
+                .headers
+                .get(axum::http::header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+        ))
+    }
+}
+
+/// Longer user agents are cut, they are only shown to the user
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+impl std::fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(ip) => write!(f, "{}", ip),
+            None => write!(f, "unknown"),
+        }
+    }
+}
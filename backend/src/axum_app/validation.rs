@@ -0,0 +1,122 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+/// Length of the `VARCHAR(50)` email and display_name columns
+pub const MAX_FIELD_LENGTH: usize = 50;
+
+pub const MAX_TITLE_LENGTH: usize = 100;
+pub const MAX_DESCRIPTION_LENGTH: usize = 1000;
+
+/// Password rules, configurable via `PASSWORD_MIN_LENGTH` and `PASSWORD_REQUIRE_MIXED`
+pub static PASSWORD_POLICY: LazyLock<PasswordPolicy> = LazyLock::new(|| PasswordPolicy {
+    min_length: std::env::var("PASSWORD_MIN_LENGTH")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(8),
+    max_length: 128,
+    require_mixed: std::env::var("PASSWORD_REQUIRE_MIXED").is_ok_and(|v| v == "true"),
+});
+
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Bounds the hashing work per request
+    pub max_length: usize,
+    /// Require at least one letter and one digit
+    pub require_mixed: bool,
+}
+
+/// Validation errors per field, rendered as a 422 response
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationErrors(BTreeMap<&'static str, Vec<String>>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.0.entry(field).or_default().push(message.into());
+    }
+
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.0.is_empty() { Ok(()) } else { Err(self) }
+    }
+}
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+pub fn check_email(errors: &mut ValidationErrors, field: &'static str, email: &str) {
+    if email.chars().count() > MAX_FIELD_LENGTH {
+        errors.add(
+            field,
+            format!("must be at most {} characters", MAX_FIELD_LENGTH),
+        );
+    }
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        }
+        None => false,
+    };
+    if !valid {
+        errors.add(field, "must be a valid email address");
+    }
+}
+
+pub fn check_display_name(errors: &mut ValidationErrors, field: &'static str, name: &str) {
+    if name.trim().is_empty() {
+        errors.add(field, "must not be empty");
+    }
+    if name.chars().count() > MAX_FIELD_LENGTH {
+        errors.add(
+            field,
+            format!("must be at most {} characters", MAX_FIELD_LENGTH),
+        );
+    }
+}
+
+pub fn check_max_length(
+    errors: &mut ValidationErrors,
+    field: &'static str,
+    value: &str,
+    max_length: usize,
+) {
+    if value.chars().count() > max_length {
+        errors.add(field, format!("must be at most {} characters", max_length));
+    }
+}
+
+pub fn check_password(errors: &mut ValidationErrors, field: &'static str, password: &str) {
+    let policy = &*PASSWORD_POLICY;
+    let length = password.chars().count();
+    if length < policy.min_length {
+        errors.add(
+            field,
+            format!("must be at least {} characters", policy.min_length),
+        );
+    }
+    if length > policy.max_length {
+        errors.add(
+            field,
+            format!("must be at most {} characters", policy.max_length),
+        );
+    }
+    if policy.require_mixed
+        && !(password.chars().any(char::is_alphabetic)
+            && password.chars().any(|c| c.is_ascii_digit()))
+    {
+        errors.add(field, "must contain letters and digits");
+    }
+}
+
+/// Checks that a field is present without applying the password policy,
+/// used where existing passwords are entered.
+pub fn check_not_empty(errors: &mut ValidationErrors, field: &'static str, value: &str) {
+    if value.is_empty() {
+        errors.add(field, "must not be empty");
+    }
+}
@@ -6,7 +6,6 @@ use tokio::task::JoinHandle;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use anyhow::Result;
-use dotenv;
 use std::env;
 
 #[tokio::main]
@@ -15,15 +14,9 @@ async fn main() -> Result<()> {
         tracing::warn!("Failed to load .env file: {}", e);
     }
 
-    let required_envs = ["JWT_SECRET"];
-    let missing_envs: Vec<&str> = required_envs
-        .iter()
-        .cloned()
-        .filter(|key| env::var(key).is_err())
-        .collect();
-
-    if !missing_envs.is_empty() {
-        tracing::error!("Missing required environment variables: {:?}", missing_envs);
+    // Either a shared secret or a keyring is needed to sign tokens
+    if env::var("JWT_SECRET").is_err() && env::var("JWT_KEYS").is_err() {
+        tracing::error!("Missing required environment variables: JWT_SECRET or JWT_KEYS");
         std::process::exit(1);
     }
 
@@ -35,6 +28,16 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    // Fail on startup instead of the first request if a key can't be loaded
+    if let Err(e) = shared::keyring::Keyring::from_env() {
+        tracing::error!("Invalid JWT key configuration: {:?}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = shared::cookies::CookieConfig::from_env() {
+        tracing::error!("Invalid cookie configuration: {:?}", e);
+        std::process::exit(1);
+    }
+
     // Create broadcast channel for ws communication
     let (ws_sender, mut dummy_receiver) =
         tokio::sync::broadcast::channel::<shared::CanvasDataEvent>(100);
@@ -49,9 +52,14 @@ async fn main() -> Result<()> {
         }
     });
 
-    let axum_handle: JoinHandle<()> = axum_app::create_axum(ws_sender.clone()).await;
+    // Lets the session list show which sessions have a canvas open
+    let open_sockets = shared::open_sockets::OpenSockets::default();
+
+    let axum_handle: JoinHandle<()> =
+        axum_app::create_axum(ws_sender.clone(), open_sockets.clone()).await;
 
-    let wtransport_handle: JoinHandle<()> = wsocket_app::create_websocket_server(ws_sender).await;
+    let wtransport_handle: JoinHandle<()> =
+        wsocket_app::create_websocket_server(ws_sender, open_sockets).await;
 
     // Wait for either server to finish (or error)
     let _ = tokio::try_join!(axum_handle, wtransport_handle)?;
@@ -0,0 +1,63 @@
+use sqlx::{Row, SqlitePool};
+use std::sync::LazyLock;
+
+/// Accounts that are made admins once their address is verified, from the comma-separated
+/// `ADMIN_EMAILS`
+#[cfg(not(test))]
+pub static ADMIN_EMAILS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("ADMIN_EMAILS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|email| email.trim().to_lowercase())
+        .filter(|email| !email.is_empty())
+        .collect()
+});
+#[cfg(test)]
+pub static ADMIN_EMAILS: LazyLock<Vec<String>> =
+    LazyLock::new(|| vec!["admin@example.com".to_string()]);
+
+pub fn is_bootstrap_admin(email: &str) -> bool {
+    ADMIN_EMAILS.contains(&email.to_lowercase())
+}
+
+/// Promotes the existing verified accounts listed in `ADMIN_EMAILS`, so the first admin can
+/// be set without database access. Anyone can register an unconfirmed address.
+pub async fn bootstrap_admins(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    for email in ADMIN_EMAILS.iter() {
+        let res = sqlx::query(
+            "UPDATE users SET is_admin = TRUE WHERE lower(email) = $1 AND email_verified AND NOT is_admin",
+        )
+        .bind(email)
+        .execute(pool)
+        .await?;
+        if res.rows_affected() > 0 {
+            tracing::info!("Granted admin rights to {}", email);
+        }
+    }
+    Ok(())
+}
+
+pub struct AccountStatus {
+    pub is_admin: bool,
+    pub disabled: bool,
+}
+
+/// Admin flag and disabled state of a user, `None` if the user no longer exists.
+pub async fn get_account_status(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Option<AccountStatus>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT is_admin, disabled_at IS NOT NULL AS disabled FROM users WHERE id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    row.map(|row| {
+        Ok(AccountStatus {
+            is_admin: row.try_get("is_admin")?,
+            disabled: row.try_get("disabled")?,
+        })
+    })
+    .transpose()
+}
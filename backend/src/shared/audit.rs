@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Row, SqlitePool};
+use std::net::IpAddr;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Logout,
+    Register,
+    ProfileUpdated,
+    PasswordChanged,
+    PasswordReset,
+    TotpEnabled,
+    TotpDisabled,
+    SessionRevoked,
+    TokenCreated,
+    TokenRevoked,
+    AccountDeleted,
+    UserDisabled,
+    UserEnabled,
+    RightChanged,
+    ModeratedChanged,
+    PublicChanged,
+    CanvasDeleted,
+    InviteCreated,
+    InviteRevoked,
+    InviteAccepted,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::Logout => "logout",
+            AuditAction::Register => "register",
+            AuditAction::ProfileUpdated => "profile_updated",
+            AuditAction::PasswordChanged => "password_changed",
+            AuditAction::PasswordReset => "password_reset",
+            AuditAction::TotpEnabled => "totp_enabled",
+            AuditAction::TotpDisabled => "totp_disabled",
+            AuditAction::SessionRevoked => "session_revoked",
+            AuditAction::TokenCreated => "token_created",
+            AuditAction::TokenRevoked => "token_revoked",
+            AuditAction::AccountDeleted => "account_deleted",
+            AuditAction::UserDisabled => "user_disabled",
+            AuditAction::UserEnabled => "user_enabled",
+            AuditAction::RightChanged => "right_changed",
+            AuditAction::ModeratedChanged => "moderated_changed",
+            AuditAction::PublicChanged => "public_changed",
+            AuditAction::CanvasDeleted => "canvas_deleted",
+            AuditAction::InviteCreated => "invite_created",
+            AuditAction::InviteRevoked => "invite_revoked",
+            AuditAction::InviteAccepted => "invite_accepted",
+        }
+    }
+}
+
+/// A security relevant action about to be written to the audit log.
+pub struct AuditEvent {
+    action: AuditAction,
+    actor_id: Option<String>,
+    target: Option<String>,
+    canvas_id: Option<String>,
+    ip: Option<String>,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction) -> Self {
+        AuditEvent {
+            action,
+            actor_id: None,
+            target: None,
+            canvas_id: None,
+            ip: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn actor(mut self, user_id: &str) -> Self {
+        self.actor_id = Some(user_id.to_string());
+        self
+    }
+
+    /// The affected user, or the submitted email if there is no such user
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn canvas(mut self, canvas_id: &str) -> Self {
+        self.canvas_id = Some(canvas_id.to_string());
+        self
+    }
+
+    pub fn ip(mut self, ip: Option<IpAddr>) -> Self {
+        self.ip = ip.map(|ip| ip.to_string());
+        self
+    }
+
+    pub fn change(mut self, before: Value, after: Value) -> Self {
+        self.before = Some(before);
+        self.after = Some(after);
+        self
+    }
+
+    pub fn before(mut self, before: Value) -> Self {
+        self.before = Some(before);
+        self
+    }
+
+    pub fn after(mut self, after: Value) -> Self {
+        self.after = Some(after);
+        self
+    }
+}
+
+/// Appends the event to the audit log. A failed write is logged but doesn't fail the action.
+pub async fn record(pool: &SqlitePool, event: AuditEvent) {
+    let res = sqlx::query(
+        "INSERT INTO audit_log (action, actor_id, target, canvas_id, ip, before, after) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(event.action.as_str())
+    .bind(&event.actor_id)
+    .bind(&event.target)
+    .bind(&event.canvas_id)
+    .bind(&event.ip)
+    .bind(event.before.as_ref().map(Value::to_string))
+    .bind(event.after.as_ref().map(Value::to_string))
+    .execute(pool)
+    .await;
+    if let Err(e) = res {
+        tracing::error!(
+            "Failed to record audit event {}: {:?}",
+            event.action.as_str(),
+            e
+        );
+    }
+}
+
+/// Conditions on listed entries, all optional. `since` and `until` take anything SQLite's
+/// `datetime()` understands, e.g. `2025-07-11` or `2025-07-11 12:00:00` (UTC).
+#[derive(Default)]
+pub struct AuditFilter {
+    pub action: Option<AuditAction>,
+    pub actor_id: Option<String>,
+    pub target: Option<String>,
+    pub canvas_id: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub action: String,
+    pub actor_id: Option<String>,
+    /// Current email of the actor, missing once the account is deleted
+    pub actor_email: Option<String>,
+    pub target: Option<String>,
+    pub canvas_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub created_at: String,
+}
+
+fn parse_value(raw: Option<String>) -> Option<Value> {
+    raw.and_then(|raw| serde_json::from_str(&raw).ok())
+}
+
+/// Matching entries, newest first.
+pub async fn list_entries(
+    pool: &SqlitePool,
+    filter: &AuditFilter,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT a.id, a.action, a.actor_id, u.email AS actor_email, a.target, a.canvas_id, a.ip, a.before, a.after, a.created_at FROM audit_log a LEFT JOIN users u ON u.id = a.actor_id WHERE ($1 IS NULL OR a.action = $1) AND ($2 IS NULL OR a.actor_id = $2) AND ($3 IS NULL OR a.target = $3) AND ($4 IS NULL OR a.canvas_id = $4) AND ($5 IS NULL OR a.created_at >= datetime($5)) AND ($6 IS NULL OR a.created_at < datetime($6)) ORDER BY a.id DESC LIMIT $7 OFFSET $8",
+    )
+    .bind(filter.action.map(AuditAction::as_str))
+    .bind(&filter.actor_id)
+    .bind(&filter.target)
+    .bind(&filter.canvas_id)
+    .bind(&filter.since)
+    .bind(&filter.until)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+    rows.iter()
+        .map(|row| {
+            Ok(AuditEntry {
+                id: row.try_get("id")?,
+                action: row.try_get("action")?,
+                actor_id: row.try_get("actor_id")?,
+                actor_email: row.try_get("actor_email")?,
+                target: row.try_get("target")?,
+                canvas_id: row.try_get("canvas_id")?,
+                ip: row.try_get("ip")?,
+                before: parse_value(row.try_get("before")?),
+                after: parse_value(row.try_get("after")?),
+                created_at: row.try_get("created_at")?,
+            })
+        })
+        .collect()
+}
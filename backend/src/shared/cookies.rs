@@ -0,0 +1,161 @@
+use anyhow::bail;
+use axum::http::HeaderValue;
+use std::sync::LazyLock;
+
+use crate::shared::jwt::TOKEN_TTL;
+
+/// Attributes of all issued cookies, configurable via `COOKIE_SECURE`, `COOKIE_HOST_PREFIX`,
+/// `COOKIE_SAMESITE` (`lax`, `strict` or `none`) and `COOKIE_DOMAIN`
+pub static COOKIE_CONFIG: LazyLock<CookieConfig> =
+    LazyLock::new(|| CookieConfig::from_env().expect("Invalid cookie configuration"));
+
+pub struct CookieConfig {
+    pub secure: bool,
+    /// Prefix cookies with `__Host-` (or `__Secure-` for cookies scoped to a path)
+    pub host_prefix: bool,
+    pub same_site: &'static str,
+    pub domain: Option<String>,
+}
+
+fn flag_from_env(key: &str) -> anyhow::Result<bool> {
+    match std::env::var(key).ok().as_deref().map(str::trim) {
+        None | Some("") | Some("0") | Some("false") => Ok(false),
+        Some("1") | Some("true") => Ok(true),
+        Some(other) => bail!("{key} has to be true or false, got {other:?}"),
+    }
+}
+
+impl CookieConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let secure = flag_from_env("COOKIE_SECURE")?;
+        let host_prefix = flag_from_env("COOKIE_HOST_PREFIX")?;
+        let same_site = match std::env::var("COOKIE_SAMESITE")
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "lax" => "Lax",
+            "strict" => "Strict",
+            "none" => "None",
+            other => bail!("COOKIE_SAMESITE has to be lax, strict or none, got {other:?}"),
+        };
+        let domain = std::env::var("COOKIE_DOMAIN")
+            .ok()
+            .map(|d| d.trim().to_string())
+            .filter(|d| !d.is_empty());
+
+        // Browsers silently drop cookies violating these rules
+        if host_prefix && !secure {
+            bail!("COOKIE_HOST_PREFIX requires COOKIE_SECURE");
+        }
+        if host_prefix && domain.is_some() {
+            bail!("COOKIE_HOST_PREFIX cannot be combined with COOKIE_DOMAIN");
+        }
+        if same_site == "None" && !secure {
+            bail!("COOKIE_SAMESITE=none requires COOKIE_SECURE");
+        }
+        Ok(CookieConfig {
+            secure,
+            host_prefix,
+            same_site,
+            domain,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Cookie {
+    Access,
+    Refresh,
+    /// Double-submit token, readable by the SPA
+    Csrf,
+    /// PKCE verifier and state of a pending OpenID login
+    OidcState,
+}
+
+impl Cookie {
+    fn base_name(self) -> &'static str {
+        match self {
+            Cookie::Access => "access_token",
+            Cookie::Refresh => "refresh_token",
+            Cookie::Csrf => "csrf_token",
+            Cookie::OidcState => "oidc_state",
+        }
+    }
+
+    fn path(self) -> &'static str {
+        match self {
+            Cookie::Access | Cookie::Csrf => "/",
+            Cookie::Refresh => "/api/auth",
+            Cookie::OidcState => "/api/auth/oidc",
+        }
+    }
+
+    /// Lifetime in seconds, session cookies have none
+    fn max_age(self) -> Option<u64> {
+        match self {
+            Cookie::Access => Some(TOKEN_TTL.access),
+            Cookie::Refresh => Some(TOKEN_TTL.refresh),
+            Cookie::Csrf => None,
+            Cookie::OidcState => Some(TOKEN_TTL.oidc_login),
+        }
+    }
+
+    fn same_site(self) -> &'static str {
+        match self {
+            // The provider redirects back cross-site, a strict cookie would not be sent along
+            Cookie::OidcState if COOKIE_CONFIG.same_site == "Strict" => "Lax",
+            _ => COOKIE_CONFIG.same_site,
+        }
+    }
+
+    pub fn name(self) -> String {
+        let prefix = match (COOKIE_CONFIG.host_prefix, self.path()) {
+            (false, _) => "",
+            (true, "/") => "__Host-",
+            (true, _) => "__Secure-",
+        };
+        format!("{}{}", prefix, self.base_name())
+    }
+
+    /// Value of this cookie in a `Cookie` request header
+    pub fn get(self, cookies: &str) -> Option<&str> {
+        let name = self.name();
+        cookies.split(';').find_map(|c| {
+            c.trim()
+                .strip_prefix(name.as_str())
+                .and_then(|rest| rest.strip_prefix('='))
+        })
+    }
+
+    fn header(self, value: &str, max_age: Option<u64>) -> HeaderValue {
+        let mut cookie = format!("{}={}; Path={}", self.name(), value, self.path());
+        if !matches!(self, Cookie::Csrf) {
+            cookie.push_str("; HttpOnly");
+        }
+        if COOKIE_CONFIG.secure {
+            cookie.push_str("; Secure");
+        }
+        cookie.push_str("; SameSite=");
+        cookie.push_str(self.same_site());
+        if let Some(domain) = &COOKIE_CONFIG.domain {
+            cookie.push_str("; Domain=");
+            cookie.push_str(domain);
+        }
+        if let Some(max_age) = max_age {
+            cookie.push_str(&format!("; Max-Age={}", max_age));
+        }
+        cookie.parse().expect("Invalid cookie value")
+    }
+
+    /// `Set-Cookie` value issuing this cookie
+    pub fn set(self, value: &str) -> HeaderValue {
+        self.header(value, self.max_age())
+    }
+
+    /// `Set-Cookie` value removing this cookie
+    pub fn clear(self) -> HeaderValue {
+        self.header("", Some(0))
+    }
+}
@@ -0,0 +1,383 @@
+use anyhow::{Context, anyhow, bail};
+use futures::future::BoxFuture;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::{Position, Url};
+
+/// Responses larger than this are rejected
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Outgoing HTTP requests, e.g. to an OpenID provider. Behind a trait so the transport
+/// can be swapped, e.g. for a TLS capable client or an in-process provider.
+pub trait HttpClient: Send + Sync {
+    fn get(&self, url: &str) -> BoxFuture<'_, anyhow::Result<HttpResponse>>;
+    fn post_form(
+        &self,
+        url: &str,
+        form: &[(&str, &str)],
+    ) -> BoxFuture<'_, anyhow::Result<HttpResponse>>;
+
+    /// Fails if no request can be made at all, e.g. because a needed binary is missing.
+    fn check(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The client for requests to the OpenID provider, selected by `OIDC_HTTP_CLIENT`: `curl`
+/// (the default, supports https) or `plain`.
+pub fn create_http_client() -> Arc<dyn HttpClient> {
+    match std::env::var("OIDC_HTTP_CLIENT").as_deref() {
+        Ok("plain") => Arc::new(PlainHttpClient),
+        _ => Arc::new(CurlHttpClient),
+    }
+}
+
+/// Runs the system `curl` binary, which brings TLS with the system's CA store.
+pub struct CurlHttpClient;
+
+impl HttpClient for CurlHttpClient {
+    fn get(&self, url: &str) -> BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        let url = url.to_string();
+        Box::pin(async move { curl(&url, None).await })
+    }
+
+    fn post_form(
+        &self,
+        url: &str,
+        form: &[(&str, &str)],
+    ) -> BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        let url = url.to_string();
+        let body = serde_urlencoded::to_string(form);
+        Box::pin(async move { curl(&url, Some(body?)).await })
+    }
+
+    fn check(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async {
+            let output = tokio::process::Command::new("curl")
+                .args(["-q", "--version"])
+                .output()
+                .await
+                .context("Failed to run curl, is it installed?")?;
+            if !output.status.success() {
+                bail!("curl --version exited with {}", output.status);
+            }
+            Ok(())
+        })
+    }
+}
+
+async fn curl(url: &str, form: Option<String>) -> anyhow::Result<HttpResponse> {
+    let mut command = tokio::process::Command::new("curl");
+    // `-q` has to come first, it skips a .curlrc that could change the request
+    command
+        .args(["-q", "--silent", "--show-error", "--proto", "=http,https"])
+        .args(["--max-time", &TIMEOUT.as_secs().to_string()])
+        .args(["--max-filesize", &MAX_RESPONSE_SIZE.to_string()])
+        .args(["--header", "Accept: application/json"])
+        // The status follows the body on a line of its own
+        .args(["--write-out", "\n%{http_code}"]);
+    if form.is_some() {
+        command
+            .args([
+                "--header",
+                "Content-Type: application/x-www-form-urlencoded",
+            ])
+            // Read from stdin, so secrets don't show up in the process list
+            .args(["--data-binary", "@-"]);
+    }
+    command
+        .args(["--url", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = command.spawn().context("Failed to run curl")?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(form.unwrap_or_default().as_bytes()).await?;
+    drop(stdin);
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "Request to {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let mut body = output.stdout;
+    let split = body
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .ok_or_else(|| anyhow!("Missing status in curl output"))?;
+    let status = std::str::from_utf8(&body[split + 1..])?
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid status from {}", url))?;
+    body.truncate(split);
+    Ok(HttpResponse { status, body })
+}
+
+/// Minimal HTTP/1.1 client without TLS, for providers reached through a local
+/// TLS-terminating proxy or running next to the backend.
+pub struct PlainHttpClient;
+
+impl HttpClient for PlainHttpClient {
+    fn get(&self, url: &str) -> BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        let url = url.to_string();
+        Box::pin(async move { request("GET", &url, None, &[]).await })
+    }
+
+    fn post_form(
+        &self,
+        url: &str,
+        form: &[(&str, &str)],
+    ) -> BoxFuture<'_, anyhow::Result<HttpResponse>> {
+        let url = url.to_string();
+        let body = serde_urlencoded::to_string(form);
+        Box::pin(async move {
+            let body = body?;
+            request(
+                "POST",
+                &url,
+                Some("application/x-www-form-urlencoded"),
+                body.as_bytes(),
+            )
+            .await
+        })
+    }
+}
+
+async fn request(
+    method: &str,
+    url: &str,
+    content_type: Option<&str>,
+    body: &[u8],
+) -> anyhow::Result<HttpResponse> {
+    let url = Url::parse(url)?;
+    if url.scheme() != "http" {
+        bail!("Unsupported URL {}, only http:// is supported", url);
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("URL {} has no host", url))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let host_header = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nAccept: application/json\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        &url[Position::BeforePath..Position::AfterQuery],
+        host_header,
+        body.len()
+    );
+    if let Some(content_type) = content_type {
+        request.push_str(&format!("Content-Type: {}\r\n", content_type));
+    }
+    request.push_str("\r\n");
+
+    let response = tokio::time::timeout(TIMEOUT, async {
+        let mut stream = TcpStream::connect((host, port)).await?;
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(body).await?;
+        let mut response = Vec::new();
+        // The server closes the connection after the response
+        (&mut stream)
+            .take(MAX_RESPONSE_SIZE + 1)
+            .read_to_end(&mut response)
+            .await?;
+        anyhow::Ok(response)
+    })
+    .await
+    .with_context(|| format!("Request to {} timed out", url))??;
+    if response.len() as u64 > MAX_RESPONSE_SIZE {
+        bail!("Response of {} is too large", url);
+    }
+    parse_response(&response).with_context(|| format!("Invalid response from {}", url))
+}
+
+fn parse_response(raw: &[u8]) -> anyhow::Result<HttpResponse> {
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut response = httparse::Response::new(&mut headers);
+    let httparse::Status::Complete(header_len) = response.parse(raw)? else {
+        bail!("Incomplete response headers");
+    };
+    let status = response.code.ok_or_else(|| anyhow!("Missing status"))?;
+    let header = |name: &str| {
+        response
+            .headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .and_then(|h| std::str::from_utf8(h.value).ok())
+    };
+    let chunked = header("transfer-encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked"));
+    let content_length: Option<usize> = header("content-length").and_then(|v| v.parse().ok());
+
+    let rest = &raw[header_len..];
+    let body = if chunked {
+        decode_chunked(rest)?
+    } else {
+        match content_length {
+            Some(len) => rest
+                .get(..len)
+                .ok_or_else(|| anyhow!("Truncated body"))?
+                .to_vec(),
+            None => rest.to_vec(),
+        }
+    };
+    Ok(HttpResponse { status, body })
+}
+
+fn decode_chunked(mut rest: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = rest
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow!("Truncated chunk"))?;
+        let size_line = std::str::from_utf8(&rest[..line_end])?;
+        // Chunk extensions follow a semicolon
+        let size_hex = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_hex, 16)?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        body.extend_from_slice(rest.get(..size).ok_or_else(|| anyhow!("Truncated chunk"))?);
+        rest = rest
+            .get(size + 2..)
+            .ok_or_else(|| anyhow!("Truncated chunk"))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::net::TcpListener;
+
+    /// Serves one canned response and returns the URL and the received request.
+    async fn serve_once(response: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token?x=1", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = tokio::io::BufReader::new(stream);
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                if let Some((_, value)) = line
+                    .split_once(':')
+                    .filter(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                {
+                    content_length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await.unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            reader
+                .get_mut()
+                .write_all(response.as_bytes())
+                .await
+                .unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    async fn check_client(client: &dyn HttpClient) {
+        let (url, server) =
+            serve_once("HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"a\":\"b\"}\n\n").await;
+        let response = client.get(&url).await.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"{\"a\":\"b\"}\n\n");
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("GET /token?x=1 HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+
+        let (url, server) = serve_once(
+            "HTTP/1.1 400 Bad Request\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nnope\r\n0\r\n\r\n",
+        )
+        .await;
+        let response = client
+            .post_form(
+                &url,
+                &[("code", "a b&c"), ("grant_type", "authorization_code")],
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status, 400);
+        assert_eq!(response.body, b"nope");
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /token?x=1 HTTP/1.1\r\n"));
+        assert!(request.contains("application/x-www-form-urlencoded"));
+        assert!(request.ends_with("\r\n\r\ncode=a+b%26c&grant_type=authorization_code"));
+    }
+
+    #[tokio::test]
+    async fn plain_client_speaks_http() {
+        check_client(&PlainHttpClient).await;
+    }
+
+    #[tokio::test]
+    async fn curl_client_speaks_http() {
+        check_client(&CurlHttpClient).await;
+    }
+
+    #[tokio::test]
+    async fn curl_client_finds_curl() {
+        CurlHttpClient.check().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn plain_client_rejects_https() {
+        let error = PlainHttpClient
+            .get("https://127.0.0.1:1/")
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("only http://"));
+    }
+
+    #[tokio::test]
+    async fn curl_client_reports_connection_errors() {
+        // Nothing listens on port 1
+        let error = CurlHttpClient
+            .get("https://127.0.0.1:1/")
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .to_string()
+                .contains("Request to https://127.0.0.1:1/ failed")
+        );
+    }
+
+    #[test]
+    fn chunked_bodies_are_joined() {
+        let body = decode_chunked(b"3;ext=1\r\nabc\r\n2\r\nde\r\n0\r\n\r\n").unwrap();
+        assert_eq!(body, b"abcde");
+        assert!(decode_chunked(b"5\r\nab").is_err());
+    }
+}
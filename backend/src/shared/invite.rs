@@ -0,0 +1,188 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use crate::shared::token::{generate_token, hash_token};
+
+/// Canvas rights from least to most privileged, co-owners rank between moderators and owners
+const RIGHTS: [&str; 6] = ["R", "W", "V", "M", "CO", "O"];
+
+/// Rights an invite can grant, co-owners are only appointed by owners directly
+pub const INVITE_RIGHTS: [&str; 5] = ["R", "W", "V", "M", "O"];
+
+/// Position of the right in `RIGHTS`, `None` for unknown rights.
+pub fn right_rank(right: &str) -> Option<usize> {
+    RIGHTS.iter().position(|r| *r == right)
+}
+
+/// Whether a member with `member_right` may hand out `right` by invite: owners any right,
+/// moderators any but O, like `change_canvas_right`.
+pub fn can_invite(member_right: &str, right: &str) -> bool {
+    match member_right {
+        "O" => true,
+        "M" => right != "O",
+        _ => false,
+    }
+}
+
+#[derive(Serialize)]
+pub struct Invite {
+    pub id: String,
+    pub right: String,
+    /// User id of the creator
+    pub created_by: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub max_uses: Option<i64>,
+    pub uses: i64,
+}
+
+pub enum AcceptOutcome {
+    /// The user was added to the canvas or got a higher right
+    Joined {
+        invite_id: String,
+        canvas_id: String,
+        previous_right: Option<String>,
+        right: String,
+    },
+    /// The user already had the invited right or a higher one, the invite wasn't used up
+    AlreadyMember { canvas_id: String, right: String },
+}
+
+/// Stores a new invite and returns its id and the plain token, which is only shown once.
+pub async fn create_invite(
+    pool: &SqlitePool,
+    canvas_id: &str,
+    created_by: &str,
+    right: &str,
+    expires_in_days: u32,
+    max_uses: Option<u32>,
+) -> Result<(String, String), sqlx::Error> {
+    let token = generate_token();
+    let row = sqlx::query(
+        "INSERT INTO canvas_invites (canvas_id, token_hash, right, created_by, expires_at, max_uses) VALUES ($1, $2, $3, $4, datetime('now', $5), $6) RETURNING id",
+    )
+    .bind(canvas_id)
+    .bind(hash_token(&token))
+    .bind(right)
+    .bind(created_by)
+    .bind(format!("+{} days", expires_in_days))
+    .bind(max_uses)
+    .fetch_one(pool)
+    .await?;
+    Ok((row.try_get("id")?, token))
+}
+
+/// Invites of the canvas that can still be accepted, without their tokens. Invites of deleted
+/// accounts are left out, they can't be accepted anymore.
+pub async fn list_invites(pool: &SqlitePool, canvas_id: &str) -> Result<Vec<Invite>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT id, right, created_by, created_at, expires_at, max_uses, uses FROM canvas_invites WHERE canvas_id = $1 AND created_by IS NOT NULL AND revoked_at IS NULL AND expires_at > datetime('now') AND (max_uses IS NULL OR uses < max_uses) ORDER BY created_at",
+    )
+    .bind(canvas_id)
+    .fetch_all(pool)
+    .await?;
+    rows.iter()
+        .map(|row| {
+            Ok(Invite {
+                id: row.try_get("id")?,
+                right: row.try_get("right")?,
+                created_by: row.try_get("created_by")?,
+                created_at: row.try_get("created_at")?,
+                expires_at: row.try_get("expires_at")?,
+                max_uses: row.try_get("max_uses")?,
+                uses: row.try_get("uses")?,
+            })
+        })
+        .collect()
+}
+
+/// Returns whether an unrevoked invite of the canvas was revoked.
+pub async fn revoke_invite(
+    pool: &SqlitePool,
+    canvas_id: &str,
+    invite_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE canvas_invites SET revoked_at = datetime('now') WHERE id = $1 AND canvas_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(invite_id)
+    .bind(canvas_id)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Grants the right of the invite to the user, `None` if the token is unknown, revoked,
+/// expired or used up, or its creator can no longer grant the right. Rights are only ever
+/// raised, never lowered.
+pub async fn accept_invite(
+    pool: &SqlitePool,
+    token: &str,
+    user_id: &str,
+) -> Result<Option<AcceptOutcome>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // Counting the use first keeps concurrent accepts from exceeding max_uses
+    let Some(invite) = sqlx::query(
+        "UPDATE canvas_invites SET uses = uses + 1 WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > datetime('now') AND (max_uses IS NULL OR uses < max_uses) RETURNING id, canvas_id, right, created_by",
+    )
+    .bind(hash_token(token))
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let invite_id: String = invite.try_get("id")?;
+    let canvas_id: String = invite.try_get("canvas_id")?;
+    let right: String = invite.try_get("right")?;
+    // Dropping the transaction rolls back the counted use
+    let Some(created_by) = invite.try_get::<Option<String>, _>("created_by")? else {
+        return Ok(None);
+    };
+    // A demoted creator's invites must not grant more than they could grant themselves now
+    let creator_right: Option<String> =
+        sqlx::query("SELECT right FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
+            .bind(&created_by)
+            .bind(&canvas_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|row| row.try_get("right"))
+            .transpose()?;
+    if !creator_right.is_some_and(|creator_right| can_invite(&creator_right, &right)) {
+        return Ok(None);
+    }
+
+    let previous_right: Option<String> =
+        sqlx::query("SELECT right FROM user_canvas WHERE user_id = $1 AND canvas_id = $2")
+            .bind(user_id)
+            .bind(&canvas_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|row| row.try_get("right"))
+            .transpose()?;
+    if let Some(previous) = previous_right.as_deref() {
+        // Unknown rights are kept as they are rather than replaced by a possibly lower one
+        let keep = match (right_rank(previous), right_rank(&right)) {
+            (Some(previous), Some(right)) => previous >= right,
+            _ => true,
+        };
+        if keep {
+            return Ok(Some(AcceptOutcome::AlreadyMember {
+                canvas_id,
+                right: previous.to_string(),
+            }));
+        }
+    }
+    sqlx::query("INSERT INTO user_canvas (user_id, canvas_id, right) VALUES ($1, $2, $3) ON CONFLICT (user_id, canvas_id) DO UPDATE SET right = $3")
+        .bind(user_id)
+        .bind(&canvas_id)
+        .bind(&right)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some(AcceptOutcome::Joined {
+        invite_id,
+        canvas_id,
+        previous_right,
+        right,
+    }))
+}
@@ -1,15 +1,31 @@
 use jsonwebtoken::errors::ErrorKind;
-use jsonwebtoken::{DecodingKey, EncodingKey};
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::sync::LazyLock;
 
+use crate::shared::keyring::Keyring;
+use crate::shared::personal_token::TokenScope;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub id: String,
     pub email: String,
     pub exp: usize,
     pub display_name: String,
+    /// Id of the server-side session, used to revoke the token before `exp`.
+    /// For personal access tokens this is the id of the token.
+    pub jti: String,
+    /// Set when authenticated with a personal access token instead of a session
+    #[serde(skip)]
+    pub scope: Option<TokenScope>,
+}
+
+/// Issued after the password step of a login for accounts with a second factor.
+/// It can only be exchanged for a session at `/api/auth/login/totp`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaPendingClaims {
+    pub mfa_user_id: String,
+    pub exp: usize,
 }
 
 impl Display for Claims {
@@ -18,39 +34,61 @@ impl Display for Claims {
     }
 }
 
-pub static KEYS: LazyLock<Keys> = LazyLock::new(|| {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    Keys::new(secret.as_bytes())
+#[cfg(not(test))]
+pub static KEYS: LazyLock<Keyring> =
+    LazyLock::new(|| Keyring::from_env().expect("Invalid JWT key configuration"));
+#[cfg(test)]
+pub static KEYS: LazyLock<Keyring> = LazyLock::new(|| Keyring::from_secret(b"test secret"));
+
+/// Lifetimes of issued tokens in seconds, configurable via `ACCESS_TOKEN_TTL`,
+/// `REFRESH_TOKEN_TTL`, `PASSWORD_RESET_TTL`, `EMAIL_VERIFICATION_TTL`, `MFA_PENDING_TTL`,
+/// `OIDC_LOGIN_TTL` and `ACCOUNT_DELETION_TTL`
+pub static TOKEN_TTL: LazyLock<TokenTtl> = LazyLock::new(|| TokenTtl {
+    access: ttl_from_env("ACCESS_TOKEN_TTL", 15 * 60),
+    refresh: ttl_from_env("REFRESH_TOKEN_TTL", 30 * 24 * 60 * 60),
+    password_reset: ttl_from_env("PASSWORD_RESET_TTL", 60 * 60),
+    email_verification: ttl_from_env("EMAIL_VERIFICATION_TTL", 24 * 60 * 60),
+    mfa_pending: ttl_from_env("MFA_PENDING_TTL", 5 * 60),
+    oidc_login: ttl_from_env("OIDC_LOGIN_TTL", 10 * 60),
+    account_deletion: ttl_from_env("ACCOUNT_DELETION_TTL", 60 * 60),
 });
 
-pub struct Keys {
-    pub encoding: EncodingKey,
-    pub decoding: DecodingKey,
+pub struct TokenTtl {
+    pub access: u64,
+    pub refresh: u64,
+    pub password_reset: u64,
+    pub email_verification: u64,
+    pub mfa_pending: u64,
+    /// Time to complete the login at the OpenID provider
+    pub oidc_login: u64,
+    /// Validity of the mailed confirmation to delete an account without a password
+    pub account_deletion: u64,
 }
 
-impl Keys {
-    pub fn new(secret: &[u8]) -> Self {
-        Self {
-            encoding: EncodingKey::from_secret(secret),
-            decoding: DecodingKey::from_secret(secret),
-        }
-    }
+fn ttl_from_env(key: &str, default: u64) -> u64 {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Expiry timestamp for an access token issued now.
+pub fn access_token_exp() -> usize {
+    (jsonwebtoken::get_current_timestamp() + TOKEN_TTL.access) as usize
+}
+
+/// Value of an `Authorization: Bearer <token>` header.
+pub fn get_bearer_token(authorization: &str) -> Option<&str> {
+    authorization
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
 }
 
-pub fn parse_jwt_from_cookies(cookies: &str) -> Result<jsonwebtoken::TokenData<Claims>, ErrorKind> {
-    let jwt = cookies
-        .split(';')
-        .find_map(|c| {
-            let c = c.trim();
-            if c.starts_with("access_token=") {
-                Some(&c[13..])
-            } else {
-                None
-            }
-        })
-        .ok_or(ErrorKind::InvalidToken)?;
-    let token_data =
-        jsonwebtoken::decode::<Claims>(jwt, &KEYS.decoding, &jsonwebtoken::Validation::default())
-            .map_err(|_| ErrorKind::InvalidToken)?;
+pub fn parse_jwt(jwt: &str) -> Result<jsonwebtoken::TokenData<Claims>, ErrorKind> {
+    let token_data = KEYS.decode::<Claims>(jwt).map_err(|e| match e.kind() {
+        ErrorKind::ExpiredSignature => ErrorKind::ExpiredSignature,
+        _ => ErrorKind::InvalidToken,
+    })?;
     Ok(token_data)
 }
@@ -0,0 +1,248 @@
+use anyhow::{Context, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use ring::rsa::PublicKeyComponents;
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use simple_asn1::ASN1Block;
+
+/// Key id of `JWT_SECRET`, also used for tokens issued without a `kid` header
+pub const DEFAULT_KID: &str = "default";
+
+/// SubjectPublicKeyInfo prefix of an Ed25519 public key, followed by the 32 key bytes
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+struct Key {
+    kid: String,
+    algorithm: Algorithm,
+    /// Only present for secrets and private keys
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    /// Public JWK, only for asymmetric keys
+    jwk: Option<serde_json::Value>,
+}
+
+/// Verification keys addressed by the `kid` header, one of which signs new tokens.
+pub struct Keyring {
+    keys: Vec<Key>,
+    signing: usize,
+}
+
+impl Keyring {
+    /// Loads the keys from `JWT_KEYS`, a comma separated list of `kid:ALG:path` entries with
+    /// `ALG` one of `HS256` (file contains the secret), `RS256` or `EdDSA` (PEM file, private
+    /// or public key). `JWT_SECRET` is added as HS256 key `default`. `JWT_SIGNING_KID` selects
+    /// the signing key, by default the first entry of `JWT_KEYS` that has a private key.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut keys = Vec::new();
+        if let Ok(entries) = std::env::var("JWT_KEYS") {
+            for entry in entries.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let mut parts = entry.splitn(3, ':');
+                let (Some(kid), Some(algorithm), Some(path)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    bail!("Invalid JWT_KEYS entry {:?}, expected kid:ALG:path", entry);
+                };
+                let key = load_key(kid, algorithm, path)
+                    .with_context(|| format!("Failed to load JWT key {}", kid))?;
+                keys.push(key);
+            }
+        }
+        if let Ok(secret) = std::env::var("JWT_SECRET") {
+            keys.push(hmac_key(DEFAULT_KID, secret.as_bytes()));
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].iter().any(|other| other.kid == key.kid) {
+                bail!("Duplicate JWT key id {}", key.kid);
+            }
+        }
+
+        let signing = match std::env::var("JWT_SIGNING_KID") {
+            Ok(kid) => keys
+                .iter()
+                .position(|key| key.kid == kid)
+                .ok_or_else(|| anyhow!("Unknown JWT_SIGNING_KID {}", kid))?,
+            Err(_) => keys
+                .iter()
+                .position(|key| key.encoding.is_some())
+                .ok_or_else(|| {
+                    anyhow!("Neither JWT_KEYS nor JWT_SECRET configure a signing key")
+                })?,
+        };
+        if keys[signing].encoding.is_none() {
+            bail!("JWT signing key {} is a public key", keys[signing].kid);
+        }
+        Ok(Self { keys, signing })
+    }
+
+    /// A single HS256 secret, independent of the environment
+    #[cfg(test)]
+    pub fn from_secret(secret: &[u8]) -> Self {
+        Self {
+            keys: vec![hmac_key(DEFAULT_KID, secret)],
+            signing: 0,
+        }
+    }
+
+    /// Signs the claims with the active key, naming it in the `kid` header.
+    pub fn encode<T: Serialize>(&self, claims: &T) -> jsonwebtoken::errors::Result<String> {
+        let key = &self.keys[self.signing];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        // Checked to be present when loading
+        jsonwebtoken::encode(&header, claims, key.encoding.as_ref().unwrap())
+    }
+
+    /// Verifies a token with the key named by its `kid` header. The algorithm is taken from
+    /// the key, never from the token.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> jsonwebtoken::errors::Result<TokenData<T>> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let kid = header.kid.as_deref().unwrap_or(DEFAULT_KID);
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.kid == kid)
+            .ok_or(jsonwebtoken::errors::ErrorKind::InvalidToken)?;
+        jsonwebtoken::decode(token, &key.decoding, &Validation::new(key.algorithm))
+    }
+
+    /// JSON Web Key Set of the asymmetric keys; shared secrets are never published.
+    pub fn jwks(&self) -> serde_json::Value {
+        let keys: Vec<&serde_json::Value> = self
+            .keys
+            .iter()
+            .filter_map(|key| key.jwk.as_ref())
+            .collect();
+        serde_json::json!({ "keys": keys })
+    }
+}
+
+fn hmac_key(kid: &str, secret: &[u8]) -> Key {
+    Key {
+        kid: kid.to_string(),
+        algorithm: Algorithm::HS256,
+        encoding: Some(EncodingKey::from_secret(secret)),
+        decoding: DecodingKey::from_secret(secret),
+        jwk: None,
+    }
+}
+
+fn load_key(kid: &str, algorithm: &str, path: &str) -> anyhow::Result<Key> {
+    let contents = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+    if algorithm == "HS256" {
+        return Ok(hmac_key(kid, contents.trim_ascii()));
+    }
+    let pem = pem::parse(&contents)?;
+    let private = pem.tag().ends_with("PRIVATE KEY");
+    match algorithm {
+        "RS256" => {
+            let (n, e) = match pem.tag() {
+                "PRIVATE KEY" | "RSA PRIVATE KEY" => {
+                    let pair = if pem.tag() == "PRIVATE KEY" {
+                        RsaKeyPair::from_pkcs8(pem.contents())
+                    } else {
+                        RsaKeyPair::from_der(pem.contents())
+                    }
+                    .map_err(|e| anyhow!("Invalid RSA private key: {}", e))?;
+                    let public = PublicKeyComponents::<Vec<u8>>::from(pair.public());
+                    (public.n, public.e)
+                }
+                "PUBLIC KEY" => match asn1(pem.contents())?.as_slice() {
+                    // SubjectPublicKeyInfo wrapping an RSAPublicKey
+                    [ASN1Block::Sequence(_, items)] => match items.as_slice() {
+                        [ASN1Block::Sequence(_, _), ASN1Block::BitString(_, _, key)] => {
+                            rsa_components(key)?
+                        }
+                        _ => bail!("Not an RSA public key"),
+                    },
+                    _ => bail!("Not an RSA public key"),
+                },
+                "RSA PUBLIC KEY" => rsa_components(pem.contents())?,
+                tag => bail!("Unsupported PEM block {}", tag),
+            };
+            let n = URL_SAFE_NO_PAD.encode(n);
+            let e = URL_SAFE_NO_PAD.encode(e);
+            Ok(Key {
+                kid: kid.to_string(),
+                algorithm: Algorithm::RS256,
+                encoding: if private {
+                    Some(EncodingKey::from_rsa_pem(&contents)?)
+                } else {
+                    None
+                },
+                decoding: DecodingKey::from_rsa_components(&n, &e)?,
+                jwk: Some(serde_json::json!({
+                    "kty": "RSA",
+                    "use": "sig",
+                    "alg": "RS256",
+                    "kid": kid,
+                    "n": n,
+                    "e": e,
+                })),
+            })
+        }
+        "EdDSA" => {
+            let public = match pem.tag() {
+                "PRIVATE KEY" => {
+                    let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pem.contents())
+                        .map_err(|e| anyhow!("Invalid Ed25519 private key: {}", e))?;
+                    pair.public_key().as_ref().to_vec()
+                }
+                "PUBLIC KEY" => pem
+                    .contents()
+                    .strip_prefix(&ED25519_SPKI_PREFIX[..])
+                    .filter(|key| key.len() == 32)
+                    .ok_or_else(|| anyhow!("Not an Ed25519 public key"))?
+                    .to_vec(),
+                tag => bail!("Unsupported PEM block {}", tag),
+            };
+            let x = URL_SAFE_NO_PAD.encode(public);
+            Ok(Key {
+                kid: kid.to_string(),
+                algorithm: Algorithm::EdDSA,
+                encoding: if private {
+                    Some(EncodingKey::from_ed_pem(&contents)?)
+                } else {
+                    None
+                },
+                decoding: DecodingKey::from_ed_components(&x)?,
+                jwk: Some(serde_json::json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "use": "sig",
+                    "alg": "EdDSA",
+                    "kid": kid,
+                    "x": x,
+                })),
+            })
+        }
+        _ => bail!(
+            "Unsupported algorithm {}, expected HS256, RS256 or EdDSA",
+            algorithm
+        ),
+    }
+}
+
+fn asn1(der: &[u8]) -> anyhow::Result<Vec<ASN1Block>> {
+    simple_asn1::from_der(der).map_err(|e| anyhow!("Invalid DER: {:?}", e))
+}
+
+/// Modulus and exponent of a DER encoded PKCS#1 `RSAPublicKey`
+fn rsa_components(der: &[u8]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    match asn1(der)?.as_slice() {
+        [ASN1Block::Sequence(_, items)] => match items.as_slice() {
+            [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => {
+                Ok((n.to_bytes_be().1, e.to_bytes_be().1))
+            }
+            _ => bail!("Not an RSA public key"),
+        },
+        _ => bail!("Not an RSA public key"),
+    }
+}
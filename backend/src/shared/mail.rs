@@ -0,0 +1,62 @@
+use futures::future::BoxFuture;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers outgoing mails. Implementations decide how (SMTP, API, file, ...).
+pub trait MailSender: Send + Sync {
+    fn send(&self, mail: Mail) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+/// Writes every mail as a file into a directory instead of delivering it.
+/// Used for development and to inspect mails without network access.
+pub struct FileOutbox {
+    dir: PathBuf,
+}
+
+impl FileOutbox {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl MailSender for FileOutbox {
+    fn send(&self, mail: Mail) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_nanos();
+            let recipient: String = mail
+                .to
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || "@._-".contains(c) {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            let path = self.dir.join(format!("{}-{}.eml", timestamp, recipient));
+            let content = format!(
+                "To: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+                mail.to, mail.subject, mail.body
+            );
+            tokio::fs::write(&path, content).await?;
+            tracing::info!("Wrote mail to {}", path.display());
+            Ok(())
+        })
+    }
+}
+
+/// Creates the mail sender configured via `MAIL_OUTBOX_DIR`.
+pub fn create_mail_sender() -> std::sync::Arc<dyn MailSender> {
+    let dir = std::env::var("MAIL_OUTBOX_DIR").unwrap_or("outbox".to_string());
+    std::sync::Arc::new(FileOutbox::new(dir))
+}
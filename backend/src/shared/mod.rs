@@ -1,5 +1,20 @@
 /// This module contains shared types and utilities used across the backend.
+pub mod admin;
+pub mod audit;
+pub mod cookies;
+pub mod http_client;
+pub mod invite;
 pub mod jwt;
+pub mod keyring;
+pub mod mail;
+pub mod open_sockets;
+pub mod personal_token;
+pub mod session;
+pub mod token;
+pub mod totp;
+pub mod verification;
+
+use serde::Serialize;
 
 #[derive(Clone, Debug)]
 pub enum CanvasDataEvent {
@@ -8,4 +23,16 @@ pub enum CanvasDataEvent {
         (/*user_id*/ String, /*right*/ Option<String>),
     ),
     ModeratedChanged(/*canvas_id*/ String, /*moderated*/ bool),
+    PublicChanged(/*canvas_id*/ String, /*public*/ bool),
+    SessionRevoked(/*session_id*/ String),
+    MetadataChanged(/*canvas_id*/ String, CanvasMetadata),
+    CanvasDeleted(/*canvas_id*/ String),
+}
+
+/// Editable details of a canvas, sent to its clients when they change
+#[derive(Clone, Debug, Serialize)]
+pub struct CanvasMetadata {
+    pub title: String,
+    pub description: String,
+    pub updated_at: Option<String>,
 }
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Canvas sockets currently open per session, shared between the WebSocket server
+/// and the session list of the HTTP API.
+#[derive(Clone, Default)]
+pub struct OpenSockets(Arc<Mutex<HashMap<String, usize>>>);
+
+impl OpenSockets {
+    /// Counts a socket of the session as open until the returned guard is dropped.
+    pub fn register(&self, session_id: &str) -> OpenSocketGuard {
+        *self
+            .0
+            .lock()
+            .unwrap()
+            .entry(session_id.to_string())
+            .or_default() += 1;
+        OpenSocketGuard {
+            sockets: self.clone(),
+            session_id: session_id.to_string(),
+        }
+    }
+
+    /// Like `register`, unless `max` sockets are open for the key already.
+    pub fn try_register(&self, key: &str, max: usize) -> Option<OpenSocketGuard> {
+        let mut sockets = self.0.lock().unwrap();
+        let count = sockets.entry(key.to_string()).or_default();
+        if *count >= max {
+            if *count == 0 {
+                sockets.remove(key);
+            }
+            return None;
+        }
+        *count += 1;
+        Some(OpenSocketGuard {
+            sockets: self.clone(),
+            session_id: key.to_string(),
+        })
+    }
+
+    pub fn is_open(&self, session_id: &str) -> bool {
+        self.0.lock().unwrap().contains_key(session_id)
+    }
+}
+
+pub struct OpenSocketGuard {
+    sockets: OpenSockets,
+    session_id: String,
+}
+
+impl Drop for OpenSocketGuard {
+    fn drop(&mut self) {
+        let mut sockets = self.sockets.0.lock().unwrap();
+        if let Some(count) = sockets.get_mut(&self.session_id) {
+            *count -= 1;
+            if *count == 0 {
+                sockets.remove(&self.session_id);
+            }
+        }
+    }
+}
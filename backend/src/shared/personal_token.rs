@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+
+use crate::shared::jwt::Claims;
+use crate::shared::token::{generate_token, hash_token};
+
+/// Distinguishes personal access tokens from JWTs in the `Authorization` header
+pub const TOKEN_PREFIX: &str = "pat_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Only safe requests and read-only canvas sockets
+    Read,
+    /// Additionally modify canvases
+    Write,
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Write => "write",
+        }
+    }
+
+    fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "read" => Some(TokenScope::Read),
+            "write" => Some(TokenScope::Write),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PersonalToken {
+    pub id: String,
+    pub name: String,
+    pub scope: TokenScope,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+}
+
+/// Stores a new token and returns its id and the plain token, which is only shown once.
+pub async fn create_personal_token(
+    pool: &SqlitePool,
+    user_id: &str,
+    name: &str,
+    scope: TokenScope,
+    expires_in_days: Option<u32>,
+) -> Result<(String, String), sqlx::Error> {
+    let token = format!("{}{}", TOKEN_PREFIX, generate_token());
+    let row = sqlx::query(
+        "INSERT INTO personal_access_tokens (user_id, name, token_hash, scope, expires_at) VALUES ($1, $2, $3, $4, datetime('now', $5)) RETURNING id",
+    )
+    .bind(user_id)
+    .bind(name)
+    .bind(hash_token(&token))
+    .bind(scope.as_str())
+    // datetime() with a NULL modifier yields NULL, i.e. no expiry
+    .bind(expires_in_days.map(|days| format!("+{} days", days)))
+    .fetch_one(pool)
+    .await?;
+    Ok((row.try_get("id")?, token))
+}
+
+/// Active tokens of the user, without their secrets.
+pub async fn list_personal_tokens(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<PersonalToken>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT id, name, scope, created_at, expires_at, last_used_at FROM personal_access_tokens WHERE user_id = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > datetime('now')) ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    rows.iter()
+        .map(|row| {
+            let scope: String = row.try_get("scope")?;
+            Ok(PersonalToken {
+                id: row.try_get("id")?,
+                name: row.try_get("name")?,
+                scope: TokenScope::parse(&scope).unwrap_or(TokenScope::Read),
+                created_at: row.try_get("created_at")?,
+                expires_at: row.try_get("expires_at")?,
+                last_used_at: row.try_get("last_used_at")?,
+            })
+        })
+        .collect()
+}
+
+/// Returns whether an active token of the user was revoked.
+pub async fn revoke_personal_token(
+    pool: &SqlitePool,
+    user_id: &str,
+    token_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE personal_access_tokens SET revoked_at = datetime('now') WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(token_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Resolves a plain token to the claims of its user and records its use.
+/// The token id takes the place of the session id in `jti`.
+pub async fn authenticate_personal_token(
+    pool: &SqlitePool,
+    token: &str,
+) -> Result<Option<Claims>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT t.id, t.scope, CAST(strftime('%s', t.expires_at) AS INTEGER) AS expires_at, u.id AS user_id, u.email, u.display_name FROM personal_access_tokens t JOIN users u ON u.id = t.user_id WHERE t.token_hash = $1 AND t.revoked_at IS NULL AND (t.expires_at IS NULL OR t.expires_at > datetime('now'))",
+    )
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let token_id: String = row.try_get("id")?;
+    let scope: String = row.try_get("scope")?;
+    let expires_at: Option<i64> = row.try_get("expires_at")?;
+    sqlx::query("UPDATE personal_access_tokens SET last_used_at = datetime('now') WHERE id = $1")
+        .bind(&token_id)
+        .execute(pool)
+        .await?;
+    Ok(Some(Claims {
+        id: row.try_get("user_id")?,
+        email: row.try_get("email")?,
+        // Tokens without expiry never run out
+        exp: expires_at.map_or(usize::MAX, |exp| exp as usize),
+        display_name: row.try_get("display_name")?,
+        jti: token_id,
+        // Unknown scopes get the least privileges
+        scope: Some(TokenScope::parse(&scope).unwrap_or(TokenScope::Read)),
+    }))
+}
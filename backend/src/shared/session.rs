@@ -0,0 +1,319 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use crate::shared::jwt::TOKEN_TTL;
+use crate::shared::token::{generate_token, hash_token};
+
+/// Creates a new session for the user and returns its id, which is used as the `jti` claim.
+pub async fn create_session(
+    pool: &SqlitePool,
+    user_id: &str,
+    user_agent: Option<&str>,
+    ip: Option<&str>,
+) -> Result<String, sqlx::Error> {
+    let row = sqlx::query(
+        "INSERT INTO sessions (user_id, user_agent, ip, last_seen_at) VALUES ($1, $2, $3, datetime('now')) RETURNING id",
+    )
+    .bind(user_id)
+    .bind(user_agent)
+    .bind(ip)
+    .fetch_one(pool)
+    .await?;
+    row.try_get("id")
+}
+
+/// Records activity of the session, at most once a minute to spare the database writes.
+pub async fn touch_session(pool: &SqlitePool, jti: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE sessions SET last_seen_at = datetime('now') WHERE id = $1 AND (last_seen_at IS NULL OR last_seen_at < datetime('now', '-60 seconds'))",
+    )
+    .bind(jti)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: Option<String>,
+    pub last_seen_at: Option<String>,
+}
+
+/// Sessions of the user that are neither revoked nor past their refresh token.
+pub async fn list_sessions(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<SessionInfo>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT s.id, s.user_agent, s.ip, s.created_at, s.last_seen_at FROM sessions s WHERE s.user_id = $1 AND s.revoked_at IS NULL AND EXISTS (SELECT 1 FROM refresh_tokens rt WHERE rt.session_id = s.id AND rt.used_at IS NULL AND rt.expires_at > datetime('now')) ORDER BY s.last_seen_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    rows.iter()
+        .map(|row| {
+            Ok(SessionInfo {
+                id: row.try_get("id")?,
+                user_agent: row.try_get("user_agent")?,
+                ip: row.try_get("ip")?,
+                created_at: row.try_get("created_at")?,
+                last_seen_at: row.try_get("last_seen_at")?,
+            })
+        })
+        .collect()
+}
+
+/// A session is active as long as it exists and has not been revoked.
+pub async fn is_session_active(pool: &SqlitePool, jti: &str) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query("SELECT 1 FROM sessions WHERE id = $1 AND revoked_at IS NULL")
+        .bind(jti)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+pub async fn revoke_session(pool: &SqlitePool, jti: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE sessions SET revoked_at = datetime('now') WHERE id = $1 AND revoked_at IS NULL",
+    )
+    .bind(jti)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Revokes a session of the user, returns false if the user has no such active session.
+pub async fn revoke_user_session(
+    pool: &SqlitePool,
+    user_id: &str,
+    jti: &str,
+) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE sessions SET revoked_at = datetime('now') WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(jti)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Stores a new refresh token for the session and returns the plain token for the cookie.
+pub async fn issue_refresh_token(
+    pool: &SqlitePool,
+    session_id: &str,
+) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+    sqlx::query(
+        "INSERT INTO refresh_tokens (token_hash, session_id, expires_at) VALUES ($1, $2, datetime('now', $3))",
+    )
+    .bind(hash_token(&token))
+    .bind(session_id)
+    .bind(format!("+{} seconds", TOKEN_TTL.refresh))
+    .execute(pool)
+    .await?;
+    Ok(token)
+}
+
+pub enum RefreshOutcome {
+    /// The token was valid and has been replaced by `token`
+    Rotated {
+        session_id: String,
+        user_id: String,
+        token: String,
+    },
+    /// An already used token was presented again, the session has been revoked
+    Reused {
+        session_id: String,
+    },
+    Invalid,
+}
+
+/// Consumes a refresh token and issues its successor within the same session.
+pub async fn rotate_refresh_token(
+    pool: &SqlitePool,
+    token: &str,
+) -> Result<RefreshOutcome, sqlx::Error> {
+    let token_hash = hash_token(token);
+    let mut tx = pool.begin().await?;
+    // Claiming the token is the first statement, so the transaction takes the write lock right
+    // away instead of upgrading a read lock, and only one of concurrent refreshes can win
+    let claimed = sqlx::query(
+        "UPDATE refresh_tokens SET used_at = datetime('now') WHERE token_hash = $1 AND used_at IS NULL AND expires_at > datetime('now')",
+    )
+    .bind(&token_hash)
+    .execute(&mut *tx)
+    .await?;
+    let row = sqlx::query(
+        "SELECT rt.session_id, rt.used_at IS NOT NULL AS used, s.user_id, s.revoked_at IS NULL AS active FROM refresh_tokens rt JOIN sessions s ON rt.session_id = s.id WHERE rt.token_hash = $1",
+    )
+    .bind(&token_hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(row) = row else {
+        return Ok(RefreshOutcome::Invalid);
+    };
+    let session_id: String = row.try_get("session_id")?;
+    let user_id: String = row.try_get("user_id")?;
+    // Dropping the transaction rolls back the claim
+    if !row.try_get::<bool, _>("active")? {
+        return Ok(RefreshOutcome::Invalid);
+    }
+    if claimed.rows_affected() == 0 {
+        if !row.try_get::<bool, _>("used")? {
+            // Expired
+            return Ok(RefreshOutcome::Invalid);
+        }
+        sqlx::query("UPDATE sessions SET revoked_at = datetime('now') WHERE id = $1")
+            .bind(&session_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(RefreshOutcome::Reused { session_id });
+    }
+    let new_token = generate_token();
+    sqlx::query(
+        "INSERT INTO refresh_tokens (token_hash, session_id, expires_at) VALUES ($1, $2, datetime('now', $3))",
+    )
+    .bind(hash_token(&new_token))
+    .bind(&session_id)
+    .bind(format!("+{} seconds", TOKEN_TTL.refresh))
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(RefreshOutcome::Rotated {
+        session_id,
+        user_id,
+        token: new_token,
+    })
+}
+
+/// Looks up the session a refresh token belongs to, regardless of whether it was used.
+pub async fn session_of_refresh_token(
+    pool: &SqlitePool,
+    token: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query("SELECT session_id FROM refresh_tokens WHERE token_hash = $1")
+        .bind(hash_token(token))
+        .fetch_optional(pool)
+        .await?;
+    row.map(|row| row.try_get("session_id")).transpose()
+}
+
+/// Revokes all sessions of a user except `keep` and returns the ids of the revoked ones.
+pub async fn revoke_user_sessions(
+    pool: &SqlitePool,
+    user_id: &str,
+    keep: Option<&str>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query(
+        "UPDATE sessions SET revoked_at = datetime('now') WHERE user_id = $1 AND revoked_at IS NULL AND id IS NOT $2 RETURNING id",
+    )
+    .bind(user_id)
+    .bind(keep)
+    .fetch_all(pool)
+    .await?;
+    rows.iter().map(|row| row.try_get("id")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axum_app::test_support::TestApp;
+
+    async fn session_with_token(app: &TestApp) -> (String, String) {
+        let user_id = app.create_user("alice@example.com").await;
+        let session_id = create_session(app.db(), &user_id, None, None)
+            .await
+            .unwrap();
+        let token = issue_refresh_token(app.db(), &session_id).await.unwrap();
+        (session_id, token)
+    }
+
+    #[tokio::test]
+    async fn rotation_replaces_the_token() {
+        let app = TestApp::new().await;
+        let (session_id, token) = session_with_token(&app).await;
+
+        let RefreshOutcome::Rotated {
+            session_id: rotated_session,
+            token: next,
+            ..
+        } = rotate_refresh_token(app.db(), &token).await.unwrap()
+        else {
+            panic!("token was not rotated");
+        };
+        assert_eq!(rotated_session, session_id);
+        assert!(matches!(
+            rotate_refresh_token(app.db(), &next).await.unwrap(),
+            RefreshOutcome::Rotated { .. }
+        ));
+        assert!(matches!(
+            rotate_refresh_token(app.db(), "unknown").await.unwrap(),
+            RefreshOutcome::Invalid
+        ));
+    }
+
+    #[tokio::test]
+    async fn reuse_revokes_the_session() {
+        let app = TestApp::new().await;
+        let (session_id, token) = session_with_token(&app).await;
+
+        let RefreshOutcome::Rotated { token: next, .. } =
+            rotate_refresh_token(app.db(), &token).await.unwrap()
+        else {
+            panic!("token was not rotated");
+        };
+        assert!(matches!(
+            rotate_refresh_token(app.db(), &token).await.unwrap(),
+            RefreshOutcome::Reused { session_id: reused } if reused == session_id
+        ));
+        assert!(!is_session_active(app.db(), &session_id).await.unwrap());
+        // The successor dies with the session
+        assert!(matches!(
+            rotate_refresh_token(app.db(), &next).await.unwrap(),
+            RefreshOutcome::Invalid
+        ));
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_invalid_without_revoking() {
+        let app = TestApp::new().await;
+        let (session_id, token) = session_with_token(&app).await;
+        sqlx::query("UPDATE refresh_tokens SET expires_at = datetime('now', '-1 seconds')")
+            .execute(app.db())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            rotate_refresh_token(app.db(), &token).await.unwrap(),
+            RefreshOutcome::Invalid
+        ));
+        assert!(is_session_active(app.db(), &session_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn concurrent_refreshes_rotate_once() {
+        let app = TestApp::new().await;
+        let (_, token) = session_with_token(&app).await;
+
+        let outcomes =
+            futures::future::join_all((0..8).map(|_| rotate_refresh_token(app.db(), &token))).await;
+        // None of them may fail with SQLITE_BUSY
+        let outcomes: Vec<RefreshOutcome> = outcomes.into_iter().map(Result::unwrap).collect();
+        let rotated = outcomes
+            .iter()
+            .filter(|outcome| matches!(outcome, RefreshOutcome::Rotated { .. }))
+            .count();
+        assert_eq!(rotated, 1);
+        // The first loser revokes the session, the others find it revoked
+        assert!(
+            outcomes
+                .iter()
+                .any(|outcome| matches!(outcome, RefreshOutcome::Reused { .. }))
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use sqlx::{Row, SqlitePool};
+use std::sync::LazyLock;
+
+use crate::shared::admin::is_bootstrap_admin;
+use crate::shared::jwt::TOKEN_TTL;
+use crate::shared::mail::{Mail, MailSender};
+use crate::shared::token::{generate_token, hash_token};
+
+/// What unverified users may do, configurable via `REQUIRE_VERIFIED_LOGIN`
+/// and `REQUIRE_VERIFIED_RIGHTS`
+pub static VERIFICATION_POLICY: LazyLock<VerificationPolicy> =
+    LazyLock::new(|| VerificationPolicy {
+        login: flag_from_env("REQUIRE_VERIFIED_LOGIN", false),
+        canvas_rights: flag_from_env("REQUIRE_VERIFIED_RIGHTS", false),
+    });
+
+pub struct VerificationPolicy {
+    /// Unverified users can't log in
+    pub login: bool,
+    /// Unverified users can't be granted canvas rights
+    pub canvas_rights: bool,
+}
+
+fn flag_from_env(key: &str, default: bool) -> bool {
+    std::env::var(key)
+        .map(|v| matches!(v.as_str(), "1" | "true"))
+        .unwrap_or(default)
+}
+
+/// Stores a verification token for `email` and mails the link to that address.
+pub async fn send_verification_mail(
+    pool: &SqlitePool,
+    mailer: &dyn MailSender,
+    user_id: &str,
+    email: &str,
+) -> anyhow::Result<()> {
+    let token = generate_token();
+    sqlx::query(
+        "INSERT INTO email_verifications (token_hash, user_id, email, expires_at) VALUES ($1, $2, $3, datetime('now', $4))",
+    )
+    .bind(hash_token(&token))
+    .bind(user_id)
+    .bind(email)
+    .bind(format!("+{} seconds", TOKEN_TTL.email_verification))
+    .execute(pool)
+    .await?;
+    let public_url = std::env::var("PUBLIC_URL").unwrap_or("http://localhost:8000".to_string());
+    mailer
+        .send(Mail {
+            to: email.to_string(),
+            subject: "Confirm your email address".to_string(),
+            body: format!(
+                "Open the following link to confirm your email address.\r\n\r\n{}/api/auth/verify/{}",
+                public_url, token
+            ),
+        })
+        .await
+}
+
+pub enum VerifyOutcome {
+    Verified {
+        email: String,
+    },
+    /// The address was taken by another account in the meantime
+    EmailTaken,
+    Invalid,
+}
+
+/// Consumes a verification token and applies its address to the user. Addresses listed in
+/// `ADMIN_EMAILS` get admin rights here, once their owner has proven access.
+pub async fn verify_email(pool: &SqlitePool, token: &str) -> Result<VerifyOutcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let Some(row) = sqlx::query(
+        "UPDATE email_verifications SET used_at = datetime('now') WHERE token_hash = $1 AND used_at IS NULL AND expires_at > datetime('now') RETURNING user_id, email",
+    )
+    .bind(hash_token(token))
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(VerifyOutcome::Invalid);
+    };
+    let user_id: String = row.try_get("user_id")?;
+    let email: String = row.try_get("email")?;
+    let res = sqlx::query(
+        "UPDATE users SET email = $1, email_verified = TRUE, is_admin = is_admin OR $3, updated_at = datetime('now') WHERE id = $2",
+    )
+    .bind(&email)
+    .bind(&user_id)
+    .bind(is_bootstrap_admin(&email))
+    .execute(&mut *tx)
+    .await;
+    match res {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Ok(VerifyOutcome::EmailTaken);
+        }
+        Err(e) => return Err(e),
+    }
+    tx.commit().await?;
+    Ok(VerifyOutcome::Verified { email })
+}
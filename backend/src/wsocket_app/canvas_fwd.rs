@@ -19,11 +19,39 @@ pub struct CanvasEvent {
     pub timestamp: u64,
     pub payload: serde_json::Value,
 }
+/// Event types the server sends on its own, clients may not use them
+pub const RESERVED_EVENT_TYPES: [&str; 6] = [
+    "CANVAS_DELETED",
+    "SESSION_REVOKED",
+    "TOKEN_EXPIRED",
+    "RIGHTS_CHANGED",
+    "METADATA_CHANGED",
+    "PING",
+];
+
+/// What a connection hands to the forwarder. Only `Broadcast` carries events of the client,
+/// the others are created by the server.
+#[derive(Debug)]
+pub enum FwdMessage {
+    /// Forwarded to the other connections of the canvas
+    Broadcast(CanvasEvent),
+    /// Sent to this connection only
+    Notify(CanvasEvent),
+    /// Sent to this connection, which is closed afterwards
+    Close(CanvasEvent),
+    /// Sent to every connection of the canvas, which are all closed afterwards
+    CloseCanvas(CanvasEvent),
+}
+
+fn to_message(event: &CanvasEvent) -> Message {
+    Message::Text(serde_json::to_string(event).unwrap().into())
+}
+
 pub type CanvasFwd = Arc<
     Mutex<(
         JoinHandle<()>,
         UnboundedSender<(
-            mpsc::UnboundedReceiver<CanvasEvent>,
+            mpsc::UnboundedReceiver<FwdMessage>,
             SplitSink<WebSocketStream<TcpStream>, Message>,
             String, // canvas_id
         )>,
@@ -33,7 +61,7 @@ pub type CanvasFwd = Arc<
 pub fn create_client() -> CanvasFwd {
     // this should have been multiple forwarders per canvas but thread handling was too complicated
     let (send, mut recv) = mpsc::unbounded_channel::<(
-        mpsc::UnboundedReceiver<CanvasEvent>,
+        mpsc::UnboundedReceiver<FwdMessage>,
         SplitSink<WebSocketStream<TcpStream>, Message>,
         String, // canvas_id
     )>();
@@ -60,74 +88,66 @@ pub fn create_client() -> CanvasFwd {
                     select_all.push(stream);
                 }
 
-                Some((event, from_id)) = select_all.next() => {
-                    if event.event_type == "PING" {
-                        // Just send the event, do not close
-                        if let Some(sender_map) = canvas_sender_map.get_mut(event.canvas_id.as_str()) {
-                            if let Some(ws_sender) = sender_map.get_mut(&from_id) {
-                                ws_sender
-                                    .send(Message::Text(serde_json::to_string(&event).unwrap().into()))
-                                    .await
-                                    .unwrap();
+                Some((message, from_id)) = select_all.next() => {
+                    // The canvas of a connection is the one it registered for, never the one
+                    // its events name
+                    let Some(canvas_id) = id_canvas_map.get(&from_id).cloned() else {
+                        continue;
+                    };
+                    match message {
+                        FwdMessage::Notify(event) => {
+                            if let Some(ws_sender) = canvas_sender_map
+                                .get_mut(&canvas_id)
+                                .and_then(|sender_map| sender_map.get_mut(&from_id))
+                            {
+                                // A broken socket is noticed by its own connection loop
+                                let _ = ws_sender.send(to_message(&event)).await;
                             }
                         }
-                        continue;
-                    }
-                    if event.event_type=="RIGHTS_CHANGED" {
-                        // Only close connection if user lost all rights (right is null or missing)
-                        if event.payload.get("right").is_none() || event.payload.get("right").unwrap().is_null() {
+                        FwdMessage::Close(event) => {
                             id_canvas_map.remove(&from_id);
-                            let mut ws_sender = canvas_sender_map
-                                .get_mut(event.canvas_id.as_str())
-                                .unwrap()
-                                .remove(&from_id)
-                                .unwrap();
-                            ws_sender
-                                .send(Message::Text(serde_json::to_string(&event).unwrap().into()))
-                                .await
-                                .unwrap();
-                            ws_sender.close().await.unwrap();
-                            continue;
+                            if let Some(sender_map) = canvas_sender_map.get_mut(&canvas_id) {
+                                if let Some(mut ws_sender) = sender_map.remove(&from_id) {
+                                    let _ = ws_sender.send(to_message(&event)).await;
+                                    let _ = ws_sender.close().await;
+                                }
+                                if sender_map.is_empty() {
+                                    canvas_sender_map.remove(&canvas_id);
+                                }
+                            }
                         }
-                        // Just send the event, do not close
-                        if let Some(sender_map) = canvas_sender_map.get_mut(event.canvas_id.as_str()) {
-                            if let Some(ws_sender) = sender_map.get_mut(&from_id) {
-                                ws_sender
-                                    .send(Message::Text(serde_json::to_string(&event).unwrap().into()))
-                                    .await
-                                    .unwrap();
+                        FwdMessage::CloseCanvas(event) => {
+                            // Every connection of the canvas reports the deletion, the first one
+                            // closes them all and the rest are no longer registered
+                            if let Some(sender_map) = canvas_sender_map.remove(&canvas_id) {
+                                for (id, mut ws_sender) in sender_map {
+                                    id_canvas_map.remove(&id);
+                                    let _ = ws_sender.send(to_message(&event)).await;
+                                    let _ = ws_sender.close().await;
+                                }
                             }
                         }
-                        continue;
-                    }
-                    let mut to_remove = Vec::new();
-                    // Only forward to clients on the same canvas, except the sender
-                    if let Some(canvas_id) = id_canvas_map.get(&from_id) {
-                        if let Some(sender_map) = canvas_sender_map.get_mut(canvas_id) {
-                            for (&id, sender) in sender_map.iter_mut() {
-                                if id != from_id {
-                                    match sender
-                                        .send(Message::Text(serde_json::to_string(&event).unwrap().into()))
-                                        .await
-                                    {
-                                        Ok(_) => {}
-                                        Err(_) => {
-                                            info!("Id {id} had an error");
-                                            to_remove.push((canvas_id.clone(), id));
-                                        }
+                        FwdMessage::Broadcast(event) => {
+                            let mut to_remove = Vec::new();
+                            // Only forward to clients on the same canvas, except the sender
+                            if let Some(sender_map) = canvas_sender_map.get_mut(&canvas_id) {
+                                for (&id, sender) in sender_map.iter_mut() {
+                                    if id != from_id && sender.send(to_message(&event)).await.is_err() {
+                                        info!("Id {id} had an error");
+                                        to_remove.push(id);
                                     }
                                 }
                             }
-                        }
-                    }
-                    for (canvas_id, id) in to_remove {
-                        if let Some(sender_map) = canvas_sender_map.get_mut(&canvas_id) {
-                            sender_map.remove(&id);
-                            if sender_map.is_empty() {
-                                canvas_sender_map.remove(&canvas_id);
+                            for id in to_remove {
+                                if let Some(sender_map) = canvas_sender_map.get_mut(&canvas_id) {
+                                    sender_map.remove(&id);
+                                    if sender_map.is_empty() {
+                                        canvas_sender_map.remove(&canvas_id);
+                                    }
+                                }
+                                id_canvas_map.remove(&id);
                             }
                         }
-                        id_canvas_map.remove(&id);
                     }
                 }
 
@@ -136,5 +156,5 @@ pub fn create_client() -> CanvasFwd {
         }
     });
 
-    return Arc::new(Mutex::new((fwd_register_task, send)));
+    Arc::new(Mutex::new((fwd_register_task, send)))
 }
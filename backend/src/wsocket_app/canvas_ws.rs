@@ -11,12 +11,25 @@ use tokio::{
 use tokio_tungstenite::{WebSocketStream, tungstenite::Message};
 
 use crate::shared::jwt::Claims;
+use crate::shared::personal_token::TokenScope;
 use crate::wsocket_app::canvas_fwd::CanvasEvent;
 use crate::wsocket_app::canvas_fwd::CanvasFwd;
+use crate::wsocket_app::canvas_fwd::{FwdMessage, RESERVED_EVENT_TYPES};
 
+/// Events of the lines of a message. Anyone, anonymous viewers included, can send messages,
+/// so malformed lines are skipped instead of ending the connection.
+fn parse_events<'a>(lines: impl Iterator<Item = &'a str>) -> impl Iterator<Item = CanvasEvent> {
+    lines.filter(|line| !line.is_empty()).filter_map(|line| {
+        serde_json::from_str(line)
+            .inspect_err(|e| warn!("Skipped malformed canvas event: {}", e))
+            .ok()
+    })
+}
+
+/// `jwt` is missing for anonymous viewers of public canvases.
 pub async fn handle_canvas_connection(
     ws_stream: WebSocketStream<TcpStream>,
-    jwt: Claims,
+    jwt: Option<Claims>,
     client: CanvasFwd,
     pool: SqlitePool,
     rights_rx: broadcast::Receiver<crate::shared::CanvasDataEvent>,
@@ -29,7 +42,7 @@ pub async fn handle_canvas_connection(
 
 async fn handle_connection_impl(
     ws_stream: WebSocketStream<TcpStream>,
-    jwt: Claims,
+    jwt: Option<Claims>,
     client: CanvasFwd,
     pool: SqlitePool,
     rights_rx: broadcast::Receiver<crate::shared::CanvasDataEvent>,
@@ -44,34 +57,47 @@ async fn handle_connection_impl(
     let first_cmd = first_msg_split.next().unwrap();
     let first_cmd: CanvasEvent = serde_json::from_str(first_cmd)?;
     let canvas_id = first_cmd.canvas_id.clone();
+    let user_id = jwt.as_ref().map(|jwt| jwt.id.clone());
+    let viewer = jwt.as_ref().map_or("anonymous", |jwt| jwt.email.as_str());
     let canvas_data = sqlx::query(
-        "SELECT right, moderated FROM user_canvas uc JOIN canvas c ON uc.canvas_id = c.id WHERE uc.canvas_id = ? AND user_id = ?",
+        "SELECT uc.right, c.moderated, c.public FROM canvas c LEFT JOIN user_canvas uc ON uc.canvas_id = c.id AND uc.user_id = ? WHERE c.id = ?",
     )
+    .bind(&user_id)
     .bind(&canvas_id)
-    .bind(&jwt.id)
-    .fetch_one(&pool)
+    .fetch_optional(&pool)
     .await?;
 
-    if canvas_data.is_empty() {
+    // Users without a right, signed in or not, may only watch public canvases
+    let canvas_data = match canvas_data {
+        Some(canvas)
+            if canvas.try_get::<Option<String>, _>("right")?.is_some()
+                || canvas.try_get::<bool, _>("public")? =>
+        {
+            Some(canvas)
+        }
+        _ => None,
+    };
+    let Some(canvas_data) = canvas_data else {
         ws_sender
             .send(Message::Text(
                 "{\"error\": \"You do not have access to this canvas.\"}".into(),
             ))
             .await?;
         return Ok(());
-    }
+    };
 
-    let right: String = canvas_data.try_get("right")?;
+    let right: Option<String> = canvas_data.try_get("right")?;
     let initial_moderated: bool = canvas_data.try_get("moderated")?;
 
     if first_cmd.event_type == "register" && first_cmd.payload.as_bool() == Some(true) {
-        info!("User {} connected to canvas {}", jwt.email, canvas_id);
+        info!("User {} connected to canvas {}", viewer, canvas_id);
         // Send event history
         let mut events_coll = vec![];
-        let rows = sqlx::query("SELECT events FROM canvas_events WHERE canvas_id = $1")
-            .bind(&canvas_id)
-            .fetch_all(&pool)
-            .await?;
+        let rows =
+            sqlx::query("SELECT events FROM canvas_events WHERE canvas_id = $1 ORDER BY seq")
+                .bind(&canvas_id)
+                .fetch_all(&pool)
+                .await?;
         for row in rows {
             let events: String = row.try_get("events")?;
             events_coll.push(events);
@@ -83,7 +109,7 @@ async fn handle_connection_impl(
             .await?;
     }
 
-    let (data_send, data_recv) = mpsc::unbounded_channel::<CanvasEvent>();
+    let (data_send, data_recv) = mpsc::unbounded_channel::<FwdMessage>();
 
     // Register sender for broadcast
     client
@@ -95,41 +121,76 @@ async fn handle_connection_impl(
 
     let mut right = right;
     let mut moderated = initial_moderated;
+    // Read-only personal access tokens may watch but not draw, neither may viewers without a right
+    let token_read_only = jwt
+        .as_ref()
+        .is_some_and(|jwt| jwt.scope == Some(TokenScope::Read));
+    let read_only = |right: &Option<String>| token_read_only || right.is_none();
 
     // Avoid moving canvas_id and pool by cloning inside the closure
     let handle_cmd = {
         let data_send = data_send.clone();
         let canvas_id = canvas_id.clone();
         let pool = pool.clone();
-        move |event: CanvasEvent| {
+        let author_id = user_id.clone();
+        move |mut event: CanvasEvent| {
             let data_send = data_send.clone();
             let canvas_id = canvas_id.clone();
             let pool = pool.clone();
+            let author_id = author_id.clone();
             async move {
+                // Anonymous viewers never persist or forward anything
+                let Some(author_id) = author_id else {
+                    warn!("Dropped event of anonymous viewer on canvas {}", canvas_id);
+                    return;
+                };
+                // Clients could otherwise close or mislead the other sockets of the canvas
+                if RESERVED_EVENT_TYPES.contains(&event.event_type.as_str()) {
+                    warn!(
+                        "Dropped reserved event {} of user {} on canvas {}",
+                        event.event_type, author_id, canvas_id
+                    );
+                    return;
+                }
+                event.canvas_id = canvas_id.clone();
                 if event.event_type != "SELECTION_EVENT" {
                     let res = sqlx::query(
-                        "INSERT INTO canvas_events (canvas_id, events) VALUES ($1, $2)",
+                        "INSERT INTO canvas_events (canvas_id, events, event_type, author_id) VALUES ($1, $2, $3, $4)",
                     )
                     .bind(&canvas_id)
                     .bind(serde_json::to_string(&event).unwrap())
+                    .bind(&event.event_type)
+                    .bind(&author_id)
                     .execute(&pool)
                     .await;
 
                     match res {
-                        Ok(_) => {}
+                        Ok(_) => {
+                            let res = sqlx::query(
+                                "UPDATE canvas SET updated_at = datetime('now') WHERE id = $1",
+                            )
+                            .bind(&canvas_id)
+                            .execute(&pool)
+                            .await;
+                            if let Err(e) = res {
+                                error!("Failed to bump updated_at of canvas {}: {}", canvas_id, e);
+                            }
+                        }
                         Err(e) => {
                             println!("Error {:?}", e);
                         }
                     }
                 }
 
-                data_send.send(event).unwrap();
+                data_send.send(FwdMessage::Broadcast(event)).unwrap();
             }
         }
     };
 
-    for msg in first_msg_split {
-        handle_cmd(serde_json::from_str(msg).unwrap()).await;
+    if !read_only(&right) {
+        for event in parse_events(first_msg_split) {
+            handle_cmd(event).await;
+        }
     }
 
     let mut rights_rx = rights_rx.resubscribe();
@@ -137,6 +198,14 @@ async fn handle_connection_impl(
     let mut last_pong = Instant::now();
     let mut ping_interval = time::interval(Duration::from_secs(20));
 
+    // Access tokens are short-lived, the client has to reconnect with a refreshed one.
+    // Anonymous viewers have no token that could expire.
+    let expires_in = jwt.as_ref().map_or(Duration::MAX, |jwt| {
+        Duration::from_secs((jwt.exp as u64).saturating_sub(jsonwebtoken::get_current_timestamp()))
+    });
+    let token_expired = time::sleep(expires_in);
+    tokio::pin!(token_expired);
+
     loop {
         tokio::select! {
             _ = ping_interval.tick() => {
@@ -147,7 +216,7 @@ async fn handle_connection_impl(
                     timestamp: 0,
                     payload: serde_json::json!({}),
                 };
-                if let Err(e) = data_send.send(ping_event) {
+                if let Err(e) = data_send.send(FwdMessage::Notify(ping_event)) {
                     error!("Failed to send ping event: {}", e);
                     break;
                 }
@@ -156,32 +225,45 @@ async fn handle_connection_impl(
                     break;
                 }
             }
+            _ = &mut token_expired => {
+                info!("Token of user {} expired, asking for re-authentication", viewer);
+                let res = data_send.send(FwdMessage::Close(CanvasEvent {
+                    event_type: "TOKEN_EXPIRED".into(),
+                    canvas_id: canvas_id.clone(),
+                    timestamp: 0,
+                    payload: serde_json::json!({}),
+                }));
+                if let Err(e) = res {
+                    error!("Error sending token_expired event: {}", e);
+                }
+                break;
+            }
             changed = rights_rx.recv() => {
                 if let Ok(event) = changed {
                     match event {
-                        crate::shared::CanvasDataEvent::RightChanged(ref cid, (ref uid, ref new_right)) if *cid == canvas_id && *uid == jwt.id => {
+                        crate::shared::CanvasDataEvent::RightChanged(ref cid, (ref uid, ref new_right)) if *cid == canvas_id && user_id.as_ref() == Some(uid) => {
                             if let Some(new_right) = new_right {
-                                right = new_right.clone();
+                                right = Some(new_right.clone());
                                 // Send rights_changed event to client
                                 let res = data_send
-                                    .send(CanvasEvent {
+                                    .send(FwdMessage::Notify(CanvasEvent {
                                         event_type: "RIGHTS_CHANGED".into(),
                                         canvas_id: canvas_id.clone(),
                                         timestamp: 0,
                                         payload: serde_json::json!({ "right": new_right }),
-                                    });
+                                    }));
                                 if let Err(e) = res {
                                     error!("Error sending rights_changed event: {}", e);
                                 }
                             } else {
                                 // Send rights_changed event with null right
                                 let res = data_send
-                                    .send(CanvasEvent {
+                                    .send(FwdMessage::Close(CanvasEvent {
                                         event_type: "RIGHTS_CHANGED".into(),
                                         canvas_id: canvas_id.clone(),
                                         timestamp: 0,
                                         payload: serde_json::json!({ "right": null }),
-                                    });
+                                    }));
                                 if let Err(e) = res {
                                     error!("Error sending rights_changed event: {}", e);
                                 }
@@ -192,20 +274,70 @@ async fn handle_connection_impl(
                             moderated = new_moderated;
                             // Send moderated_changed event to client
                             data_send
-                                .send(CanvasEvent {
+                                .send(FwdMessage::Notify(CanvasEvent {
                                     event_type: "RIGHTS_CHANGED".into(),
                                     canvas_id: canvas_id.clone(),
                                     timestamp: 0,
                                     payload: serde_json::json!({ "moderated": new_moderated }),
-                                })
+                                }))
                                 .unwrap();
                         },
+                        crate::shared::CanvasDataEvent::MetadataChanged(ref cid, ref metadata) if *cid == canvas_id => {
+                            let res = data_send.send(FwdMessage::Notify(CanvasEvent {
+                                event_type: "METADATA_CHANGED".into(),
+                                canvas_id: canvas_id.clone(),
+                                timestamp: 0,
+                                payload: serde_json::json!(metadata),
+                            }));
+                            if let Err(e) = res {
+                                error!("Error sending metadata_changed event: {}", e);
+                            }
+                        },
+                        crate::shared::CanvasDataEvent::CanvasDeleted(ref cid) if *cid == canvas_id => {
+                            // The forwarder notifies and closes every socket of the canvas
+                            let res = data_send.send(FwdMessage::CloseCanvas(CanvasEvent {
+                                event_type: "CANVAS_DELETED".into(),
+                                canvas_id: canvas_id.clone(),
+                                timestamp: 0,
+                                payload: serde_json::json!({}),
+                            }));
+                            if let Err(e) = res {
+                                error!("Error sending canvas_deleted event: {}", e);
+                            }
+                            break;
+                        },
+                        crate::shared::CanvasDataEvent::PublicChanged(ref cid, false) if *cid == canvas_id && right.is_none() => {
+                            // Viewers without a right lose access, the forwarder closes the socket
+                            let res = data_send.send(FwdMessage::Close(CanvasEvent {
+                                event_type: "RIGHTS_CHANGED".into(),
+                                canvas_id: canvas_id.clone(),
+                                timestamp: 0,
+                                payload: serde_json::json!({ "right": null }),
+                            }));
+                            if let Err(e) = res {
+                                error!("Error sending rights_changed event: {}", e);
+                            }
+                            break;
+                        },
+                        crate::shared::CanvasDataEvent::SessionRevoked(ref jti) if jwt.as_ref().is_some_and(|jwt| jwt.jti == *jti) => {
+                            // Tell the forwarder to notify the client and close the socket
+                            let res = data_send.send(FwdMessage::Close(CanvasEvent {
+                                event_type: "SESSION_REVOKED".into(),
+                                canvas_id: canvas_id.clone(),
+                                timestamp: 0,
+                                payload: serde_json::json!({}),
+                            }));
+                            if let Err(e) = res {
+                                error!("Error sending session_revoked event: {}", e);
+                            }
+                            break;
+                        },
                         _ => {}
                     }
                 }
             }
             msg = ws_receiver.next() => {
-                if right == "W" && moderated {
+                if right.as_deref() == Some("W") && moderated {
                     // Writer, but canvas is moderated: block
                     continue;
                 }
@@ -218,16 +350,15 @@ async fn handle_connection_impl(
                     }
                     None => break,
                 };
-                let datas = msg.split('\n').filter(|s| !s.is_empty()).map(|s| {
-                    let event: CanvasEvent = serde_json::from_str(s).unwrap();
-                    event
-                });
-                for data in datas {
+                for data in parse_events(msg.split('\n')) {
                     if data.event_type == "PONG" {
                         // Handle pong
                         last_pong = Instant::now();
                         continue;
                     }
+                    if read_only(&right) {
+                        continue;
+                    }
                     // Enforce moderation logic
                     // V, M, O can always write
                     handle_cmd(data).await;
@@ -235,5 +366,249 @@ async fn handle_connection_impl(
             }
         }
     }
-    return Ok(());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::handle_canvas_connection;
+    use crate::axum_app::test_support::TestApp;
+    use crate::shared::CanvasDataEvent;
+    use crate::shared::jwt::{Claims, access_token_exp};
+    use crate::wsocket_app::canvas_fwd::{CanvasFwd, create_client};
+    use futures::{SinkExt, StreamExt};
+    use serde_json::{Value, json};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::{Duration, timeout};
+    use tokio_tungstenite::{WebSocketStream, tungstenite::Message};
+
+    type Client = WebSocketStream<TcpStream>;
+
+    async fn socket_pair() -> (WebSocketStream<TcpStream>, Client) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (server, client) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        let (server, client) = tokio::join!(
+            tokio_tungstenite::accept_async(server.unwrap().0),
+            tokio_tungstenite::client_async(format!("ws://{}/", addr), client.unwrap()),
+        );
+        (server.unwrap(), client.unwrap().0)
+    }
+
+    /// Next text message as JSON, `None` once the socket is closed
+    async fn next_message(client: &mut Client) -> Option<Value> {
+        loop {
+            match timeout(Duration::from_secs(5), client.next())
+                .await
+                .expect("no message within 5 seconds")
+            {
+                Some(Ok(Message::Text(text))) => return Some(serde_json::from_str(&text).unwrap()),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return None,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+
+    /// Next message that isn't a ping
+    async fn next_event(client: &mut Client) -> Option<Value> {
+        loop {
+            let message = next_message(client).await?;
+            if message["type"] != "PING" {
+                return Some(message);
+            }
+        }
+    }
+
+    /// Opens a socket on the canvas, as the user or anonymously, and returns it once the
+    /// history arrived and the forwarder registered it.
+    async fn join(
+        app: &TestApp,
+        fwd: &CanvasFwd,
+        user_id: Option<&str>,
+        canvas_id: &str,
+    ) -> Client {
+        let (server, mut client) = socket_pair().await;
+        let claims = user_id.map(|user_id| Claims {
+            email: format!("{}@example.com", user_id),
+            exp: access_token_exp(),
+            display_name: String::new(),
+            id: user_id.to_string(),
+            jti: format!("session-{}", user_id),
+            scope: None,
+        });
+        tokio::spawn(handle_canvas_connection(
+            server,
+            claims,
+            fwd.clone(),
+            app.db().clone(),
+            app.state.ws_sender.subscribe(),
+        ));
+        send(&mut client, "register", canvas_id, json!(true)).await;
+        assert!(next_message(&mut client).await.unwrap().is_array());
+        // Pings only arrive through the forwarder, so the socket is registered by now
+        let ping = next_message(&mut client).await.unwrap();
+        assert_eq!(ping["type"], "PING");
+        client
+    }
+
+    async fn send(client: &mut Client, event_type: &str, canvas_id: &str, payload: Value) {
+        let event = json!({
+            "type": event_type,
+            "canvas_id": canvas_id,
+            "timestamp": 1,
+            "payload": payload,
+        });
+        client
+            .send(Message::Text(event.to_string().into()))
+            .await
+            .unwrap();
+    }
+
+    async fn stored_types(app: &TestApp, canvas_id: &str) -> Vec<String> {
+        sqlx::query_scalar("SELECT event_type FROM canvas_events WHERE canvas_id = $1 ORDER BY seq")
+            .bind(canvas_id)
+            .fetch_all(app.db())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn clients_can_not_send_control_events() {
+        let app = TestApp::new().await;
+        let fwd = create_client();
+        let owner = app.create_user("owner@example.com").await;
+        let writer = app.create_user("writer@example.com").await;
+        let other = app.create_user("other@example.com").await;
+        let canvas = app.create_canvas(&[(&owner, "O"), (&writer, "W")]).await;
+        let other_canvas = app.create_canvas(&[(&other, "O")]).await;
+        let mut writer_ws = join(&app, &fwd, Some(&writer), &canvas).await;
+        let mut owner_ws = join(&app, &fwd, Some(&owner), &canvas).await;
+        let mut other_ws = join(&app, &fwd, Some(&other), &other_canvas).await;
+
+        send(&mut writer_ws, "CANVAS_DELETED", &other_canvas, json!({})).await;
+        send(&mut writer_ws, "CANVAS_DELETED", &canvas, json!({})).await;
+        send(
+            &mut writer_ws,
+            "RIGHTS_CHANGED",
+            &canvas,
+            json!({ "right": null }),
+        )
+        .await;
+        send(
+            &mut writer_ws,
+            "METADATA_CHANGED",
+            &canvas,
+            json!({ "title": "x" }),
+        )
+        .await;
+        send(&mut writer_ws, "DRAW", &other_canvas, json!({})).await;
+
+        // Only the drawing event reaches the other socket, on the writer's own canvas
+        let event = next_event(&mut owner_ws).await.unwrap();
+        assert_eq!(event["type"], "DRAW");
+        assert_eq!(event["canvas_id"], json!(canvas));
+        assert_eq!(stored_types(&app, &canvas).await, ["DRAW"]);
+        assert!(stored_types(&app, &other_canvas).await.is_empty());
+
+        // The socket of the other canvas is still open
+        app.state
+            .ws_sender
+            .send(CanvasDataEvent::ModeratedChanged(
+                other_canvas.clone(),
+                true,
+            ))
+            .unwrap();
+        let event = next_event(&mut other_ws).await.unwrap();
+        assert_eq!(event["payload"], json!({ "moderated": true }));
+    }
+
+    #[tokio::test]
+    async fn canvas_deletion_closes_only_the_sockets_of_the_canvas() {
+        let app = TestApp::new().await;
+        let fwd = create_client();
+        let owner = app.create_user("owner@example.com").await;
+        let writer = app.create_user("writer@example.com").await;
+        let other = app.create_user("other@example.com").await;
+        let canvas = app.create_canvas(&[(&owner, "O"), (&writer, "W")]).await;
+        let other_canvas = app.create_canvas(&[(&other, "O")]).await;
+        let mut writer_ws = join(&app, &fwd, Some(&writer), &canvas).await;
+        let mut owner_ws = join(&app, &fwd, Some(&owner), &canvas).await;
+        let mut other_ws = join(&app, &fwd, Some(&other), &other_canvas).await;
+
+        app.state
+            .ws_sender
+            .send(CanvasDataEvent::CanvasDeleted(canvas.clone()))
+            .unwrap();
+        for ws in [&mut writer_ws, &mut owner_ws] {
+            assert_eq!(next_event(ws).await.unwrap()["type"], "CANVAS_DELETED");
+            assert_eq!(next_event(ws).await, None);
+        }
+        app.state
+            .ws_sender
+            .send(CanvasDataEvent::ModeratedChanged(
+                other_canvas.clone(),
+                true,
+            ))
+            .unwrap();
+        assert_eq!(
+            next_event(&mut other_ws).await.unwrap()["type"],
+            "RIGHTS_CHANGED"
+        );
+    }
+
+    #[tokio::test]
+    async fn anonymous_viewers_only_watch_public_canvases() {
+        let app = TestApp::new().await;
+        let fwd = create_client();
+        let owner = app.create_user("owner@example.com").await;
+        let writer = app.create_user("writer@example.com").await;
+        let canvas = app.create_canvas(&[(&owner, "O"), (&writer, "W")]).await;
+
+        let (server, mut client) = socket_pair().await;
+        tokio::spawn(handle_canvas_connection(
+            server,
+            None,
+            fwd.clone(),
+            app.db().clone(),
+            app.state.ws_sender.subscribe(),
+        ));
+        send(&mut client, "register", &canvas, json!(true)).await;
+        let message = next_message(&mut client).await.unwrap();
+        assert!(message["error"].is_string());
+
+        sqlx::query("UPDATE canvas SET public = TRUE WHERE id = $1")
+            .bind(&canvas)
+            .execute(app.db())
+            .await
+            .unwrap();
+        let mut writer_ws = join(&app, &fwd, Some(&writer), &canvas).await;
+        let mut owner_ws = join(&app, &fwd, Some(&owner), &canvas).await;
+        let mut anonymous_ws = join(&app, &fwd, None, &canvas).await;
+
+        send(
+            &mut anonymous_ws,
+            "DRAW",
+            &canvas,
+            json!({ "by": "anonymous" }),
+        )
+        .await;
+        // Malformed lines are skipped without closing the connection
+        let draw = json!({
+            "type": "DRAW",
+            "canvas_id": canvas,
+            "timestamp": 1,
+            "payload": { "by": "writer" },
+        });
+        writer_ws
+            .send(Message::Text(format!("{{not json\n{}", draw).into()))
+            .await
+            .unwrap();
+
+        // Only the writer's event is forwarded and stored
+        for ws in [&mut owner_ws, &mut anonymous_ws] {
+            let event = next_event(ws).await.unwrap();
+            assert_eq!(event["payload"], json!({ "by": "writer" }));
+        }
+        assert_eq!(stored_types(&app, &canvas).await, ["DRAW"]);
+    }
 }
@@ -1,23 +1,44 @@
 use log::*;
 use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
 use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
 use tokio::{
     net::{TcpListener, TcpStream},
     task::JoinHandle,
 };
 use tokio_tungstenite::{
     accept_hdr_async,
-    tungstenite::handshake::server::{Request, Response},
+    tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response},
 };
 
 use crate::{
     shared::jwt::Claims,
     wsocket_app::{canvas_fwd::create_client, canvas_ws::handle_canvas_connection},
 };
-use crate::{shared::jwt::parse_jwt_from_cookies, wsocket_app::canvas_fwd::CanvasFwd};
+use crate::{
+    shared::{
+        admin::get_account_status,
+        cookies::Cookie,
+        jwt::{get_bearer_token, parse_jwt},
+        open_sockets::{OpenSocketGuard, OpenSockets},
+        personal_token::{TOKEN_PREFIX, authenticate_personal_token},
+        session::{is_session_active, touch_session},
+    },
+    wsocket_app::canvas_fwd::CanvasFwd,
+};
+
+/// Sockets one address may keep open without an account, from `MAX_ANONYMOUS_SOCKETS_PER_IP`
+static MAX_ANONYMOUS_SOCKETS_PER_IP: LazyLock<usize> = LazyLock::new(|| {
+    env::var("MAX_ANONYMOUS_SOCKETS_PER_IP")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10)
+});
 
 pub async fn create_websocket_server(
     ws_sender: tokio::sync::broadcast::Sender<crate::shared::CanvasDataEvent>,
+    open_sockets: OpenSockets,
 ) -> JoinHandle<()> {
     // Spawn a thread to log every broadcast for testing
     let pool = SqlitePoolOptions::new()
@@ -32,41 +53,257 @@ pub async fn create_websocket_server(
     let bind_to = env::var("BIND_TO_WS").unwrap_or("0.0.0.0:8001".to_string());
     let listener = TcpListener::bind(&bind_to).await.expect("Can't listen");
     info!("WebSocket listening on: {}", bind_to);
+    let anonymous_sockets = OpenSockets::default();
     tokio::spawn(async move {
-        while let Ok((stream, _)) = listener.accept().await {
+        while let Ok((stream, peer)) = listener.accept().await {
             let pool = pool.clone();
             let clients = clients.clone();
             let receiver = ws_sender.subscribe();
+            let open_sockets = open_sockets.clone();
+            let anonymous_sockets = anonymous_sockets.clone();
             tokio::spawn(async move {
-                accept_connection(stream, clients, pool, receiver).await;
+                accept_connection(
+                    stream,
+                    peer,
+                    clients,
+                    pool,
+                    receiver,
+                    open_sockets,
+                    anonymous_sockets,
+                )
+                .await;
             });
         }
     })
 }
 
+/// Browsers authenticate with the access token cookie, scripts with `Authorization: Bearer`.
+/// Requests without either may only watch public canvases.
+enum Credentials {
+    Jwt(Claims),
+    PersonalToken(String),
+    /// Holds one of the sockets the address may open without an account
+    Anonymous(OpenSocketGuard),
+}
+
+/// Address of the client, from `X-Forwarded-For` when `TRUST_FORWARDED_FOR` is set, like
+/// `ClientIp` of the HTTP API.
+fn client_ip(req: &Request, peer: SocketAddr) -> IpAddr {
+    let trust_forwarded = env::var("TRUST_FORWARDED_FOR").is_ok_and(|v| v == "true");
+    req.headers()
+        .get("x-forwarded-for")
+        .filter(|_| trust_forwarded)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(peer.ip())
+}
+
+/// Reads the credentials of the upgrade request, rejecting invalid ones with 401 and
+/// anonymous ones beyond `MAX_ANONYMOUS_SOCKETS_PER_IP` with 429.
+struct Handshake<'a> {
+    peer: SocketAddr,
+    anonymous_sockets: &'a OpenSockets,
+    credentials: &'a mut Option<Credentials>,
+}
+
+impl Callback for Handshake<'_> {
+    fn on_request(self, req: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        let bearer = req
+            .headers()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(get_bearer_token);
+        let cookie = req
+            .headers()
+            .get("cookie")
+            .and_then(|c| c.to_str().ok())
+            .and_then(|cookies| Cookie::Access.get(cookies));
+        // Invalid credentials are still rejected, so clients know to refresh them
+        *self.credentials = match (bearer, cookie) {
+            (Some(token), _) if token.starts_with(TOKEN_PREFIX) => {
+                Some(Credentials::PersonalToken(token.to_string()))
+            }
+            (Some(jwt), _) | (None, Some(jwt)) => {
+                parse_jwt(jwt).ok().map(|jwt| Credentials::Jwt(jwt.claims))
+            }
+            (None, None) => {
+                let ip = client_ip(req, self.peer);
+                let Some(guard) = self
+                    .anonymous_sockets
+                    .try_register(&ip.to_string(), *MAX_ANONYMOUS_SOCKETS_PER_IP)
+                else {
+                    info!("Rejected anonymous websocket of {}: too many open", ip);
+                    return Err(Response::builder().status(429).body(None).unwrap());
+                };
+                Some(Credentials::Anonymous(guard))
+            }
+        };
+        if self.credentials.is_none() {
+            return Err(Response::builder().status(401).body(None).unwrap());
+        }
+        Ok(response)
+    }
+}
+
 async fn accept_connection(
     stream: TcpStream,
+    peer: SocketAddr,
     client: CanvasFwd,
     pool: SqlitePool,
     ws_receiver: tokio::sync::broadcast::Receiver<crate::shared::CanvasDataEvent>,
+    open_sockets: OpenSockets,
+    anonymous_sockets: OpenSockets,
 ) {
-    let mut jwt_outer: Option<Claims> = None;
-    let callback = |req: &Request, response: Response| {
-        let cookies = match req.headers().get("cookie").and_then(|c| c.to_str().ok()) {
-            Some(cookies) => cookies,
-            None => return Err(Response::builder().status(401).body(None).unwrap()),
-        };
-        if let Ok(jwt) = parse_jwt_from_cookies(cookies) {
-            jwt_outer = Some(jwt.claims);
-        } else {
-            return Err(Response::builder().status(401).body(None).unwrap());
+    let mut credentials: Option<Credentials> = None;
+    let handshake = Handshake {
+        peer,
+        anonymous_sockets: &anonymous_sockets,
+        credentials: &mut credentials,
+    };
+    let mut ws_stream = match accept_hdr_async(stream, handshake).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            // Includes requests rejected for invalid credentials
+            info!("WebSocket handshake failed: {}", e);
+            return;
         }
+    };
 
-        Ok(response)
+    // The handshake callback is synchronous, so sessions and tokens are checked right after the upgrade
+    let jwt = match credentials.unwrap() {
+        Credentials::Anonymous(_open_socket) => {
+            handle_canvas_connection(ws_stream, None, client, pool, ws_receiver).await;
+            return;
+        }
+        Credentials::Jwt(jwt) => match is_session_active(&pool, &jwt.jti).await {
+            Ok(true) => Some(jwt),
+            Ok(false) => {
+                info!("Rejected websocket of revoked session {}", jwt.jti);
+                None
+            }
+            Err(e) => {
+                error!("Failed to check session {}: {:?}", jwt.jti, e);
+                None
+            }
+        },
+        Credentials::PersonalToken(token) => {
+            match authenticate_personal_token(&pool, &token).await {
+                Ok(Some(jwt)) => Some(jwt),
+                Ok(None) => {
+                    info!("Rejected websocket with invalid personal access token");
+                    None
+                }
+                Err(e) => {
+                    error!("Failed to check personal access token: {:?}", e);
+                    None
+                }
+            }
+        }
     };
-    let ws_stream = accept_hdr_async(stream, callback)
-        .await
-        .expect("Error during the websocket handshake occurred");
+    let jwt = match jwt {
+        Some(jwt) => match get_account_status(&pool, &jwt.id).await {
+            Ok(Some(status)) if !status.disabled => Some(jwt),
+            Ok(_) => {
+                info!("Rejected websocket of disabled or deleted user {}", jwt.id);
+                None
+            }
+            Err(e) => {
+                error!("Failed to check account of {}: {:?}", jwt.id, e);
+                None
+            }
+        },
+        None => None,
+    };
+    let Some(jwt) = jwt else {
+        let _ = ws_stream.close(None).await;
+        return;
+    };
+    if let Err(e) = touch_session(&pool, &jwt.jti).await {
+        error!("Failed to update session {}: {:?}", jwt.jti, e);
+    }
+    // Dropped when the connection ends
+    let _open_socket = open_sockets.register(&jwt.jti);
+
+    handle_canvas_connection(ws_stream, Some(jwt), client, pool, ws_receiver).await;
+}
 
-    handle_canvas_connection(ws_stream, jwt_outer.unwrap(), client, pool, ws_receiver).await;
+#[cfg(test)]
+mod tests {
+    use super::{MAX_ANONYMOUS_SOCKETS_PER_IP, accept_connection};
+    use crate::axum_app::test_support::TestApp;
+    use crate::shared::open_sockets::OpenSockets;
+    use crate::wsocket_app::canvas_fwd::create_client;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::{Duration, sleep};
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::{Error, http::StatusCode};
+    use tokio_tungstenite::{WebSocketStream, client_async};
+
+    async fn connect(
+        addr: std::net::SocketAddr,
+        cookie: Option<&str>,
+    ) -> Result<WebSocketStream<TcpStream>, Error> {
+        let mut request = format!("ws://{}/", addr).into_client_request().unwrap();
+        if let Some(cookie) = cookie {
+            request
+                .headers_mut()
+                .insert("cookie", cookie.parse().unwrap());
+        }
+        let stream = TcpStream::connect(addr).await.unwrap();
+        client_async(request, stream).await.map(|(ws, _)| ws)
+    }
+
+    fn is_rejected(result: &Result<WebSocketStream<TcpStream>, Error>) -> bool {
+        matches!(result, Err(Error::Http(res)) if res.status() == StatusCode::TOO_MANY_REQUESTS)
+    }
+
+    #[tokio::test]
+    async fn anonymous_sockets_are_capped_per_address() {
+        let app = TestApp::new().await;
+        let user_id = app.create_user("alice@example.com").await;
+        let cookie = app.login(&user_id).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let fwd = create_client();
+        let anonymous_sockets = OpenSockets::default();
+        {
+            let pool = app.db().clone();
+            let ws_sender = app.state.ws_sender.clone();
+            let open_sockets = app.state.open_sockets.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, peer)) = listener.accept().await {
+                    tokio::spawn(accept_connection(
+                        stream,
+                        peer,
+                        fwd.clone(),
+                        pool.clone(),
+                        ws_sender.subscribe(),
+                        open_sockets.clone(),
+                        anonymous_sockets.clone(),
+                    ));
+                }
+            });
+        }
+
+        let mut sockets = Vec::new();
+        for _ in 0..*MAX_ANONYMOUS_SOCKETS_PER_IP {
+            sockets.push(connect(addr, None).await.unwrap());
+        }
+        assert!(is_rejected(&connect(addr, None).await));
+        // Signed in users aren't counted
+        connect(addr, Some(&cookie)).await.unwrap();
+
+        // A closed socket frees its place once the server noticed
+        sockets.pop().unwrap().close(None).await.unwrap();
+        for _ in 0..50 {
+            let result = connect(addr, None).await;
+            if !is_rejected(&result) {
+                result.unwrap();
+                return;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        panic!("the closed socket was still counted");
+    }
 }
@@ -3,19 +3,80 @@ let user: {
   display_name: string;
   canvases: {
     canvas_id: string;
+    title: string;
+    description: string;
+    created_at: string | null;
+    updated_at: string | null;
+    created_by: string | null;
+    forked_from: string | null;
+    forked_at_seq: number | null;
     moderated: boolean;
+    public: boolean;
     right: string;
     rights: { email: string; right: string }[] | null;
   }[];
   id: string;
 } | null = null;
 
+let csrfToken: string | null = null;
+
+// The backend hands out a csrf_token cookie that has to be echoed in a header.
+// With COOKIE_HOST_PREFIX it is called __Host-csrf_token.
+async function getCsrfToken(): Promise<string> {
+  const cookie = document.cookie
+    .split("; ")
+    .find((c) => /^(__Host-)?csrf_token=/.test(c));
+  if (cookie) {
+    return cookie.substring(cookie.indexOf("=") + 1);
+  }
+  if (!csrfToken) {
+    // The cookie isn't readable when the backend runs on another domain
+    const res = await fetch(`${__BACKEND_URL__}/api/auth/csrf`, {
+      credentials: "include",
+    });
+    csrfToken = (await res.json()).csrf_token;
+  }
+  return csrfToken;
+}
+
+// fetch with cookies and, for mutating requests, the CSRF header
+export async function csrfFetch(
+  input: string,
+  init: RequestInit = {}
+): Promise<Response> {
+  const method = (init.method ?? "GET").toUpperCase();
+  const headers = new Headers(init.headers);
+  if (!["GET", "HEAD", "OPTIONS"].includes(method)) {
+    headers.set("X-CSRF-Token", await getCsrfToken());
+  }
+  return fetch(input, { ...init, headers, credentials: "include" });
+}
+
+// Access tokens are short-lived, so a 401 triggers one refresh and a retry
+export async function apiFetch(
+  input: string,
+  init: RequestInit = {}
+): Promise<Response> {
+  const res = await csrfFetch(input, init);
+  if (res.status !== 401 || !(await refreshSession())) {
+    return res;
+  }
+  return csrfFetch(input, init);
+}
+
+export async function refreshSession(): Promise<boolean> {
+  const res = await csrfFetch(`${__BACKEND_URL__}/api/auth/refresh`, {
+    method: "POST",
+  });
+  return res.ok;
+}
+
 export async function fetchUser() {
   try {
-    const userRes = await fetch(`${__BACKEND_URL__}/api/auth/me`, {
+    const userRes = await apiFetch(`${__BACKEND_URL__}/api/auth/me`, {
       credentials: "include",
     });
-    const canvasRes = await fetch(`${__BACKEND_URL__}/api/canvas/datas`, {
+    const canvasRes = await apiFetch(`${__BACKEND_URL__}/api/canvas/datas`, {
       credentials: "include",
     });
     if (userRes.ok) {
@@ -38,9 +99,8 @@ export function getUser() {
 }
 
 export async function logout() {
-  await fetch(`${__BACKEND_URL__}/api/auth/logout`, {
+  await csrfFetch(`${__BACKEND_URL__}/api/auth/logout`, {
     method: "POST",
-    credentials: "include",
   });
   user = null;
 }
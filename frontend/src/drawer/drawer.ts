@@ -840,10 +840,14 @@ export function canvasPage(pageContent: HTMLElement) {
   document.title = "Zeichenfläche";
   const canvas_id = window.location.href.split("/").at(-1);
   const user = getUser();
-  const userCanvas = user.canvases.find((c) => c.canvas_id === canvas_id);
-  const readonly = userCanvas && userCanvas.right === "R";
+  const userCanvas = user?.canvases.find((c) => c.canvas_id === canvas_id);
+  // Visitors without a right can only watch, which the server allows for public canvases
+  const readonly = !userCanvas || userCanvas.right === "R";
   const moderated = userCanvas && userCanvas.moderated === true;
 
+  // Title and description, kept up to date via METADATA_CHANGED
+  const canvasTitleHtml = `<h2 id="canvasTitle" style="margin-bottom: 4px;"></h2><div id="canvasDescription" style="color: #555; margin-bottom: 8px;"></div>`;
+
   // Add moderated status and instruction elements
   const moderatedStatusId = "moderatedStatus";
   const instructionTextId = "instructionText";
@@ -853,6 +857,7 @@ export function canvasPage(pageContent: HTMLElement) {
   const wsErrorDivId = "ws-error-div";
 
   const content =
+    canvasTitleHtml +
     moderatedStatusHtml +
     instructionTextHtml +
     `
@@ -862,12 +867,31 @@ export function canvasPage(pageContent: HTMLElement) {
       <div class="event-stream-container">
         <textarea id="eventStream" rows="10" cols="130"></textarea>
         <button id="loadEventsButton"${
-          userCanvas.right === "O" ? ' style="display:none"' : ""
+          userCanvas?.right === "O" ? ' style="display:none"' : ""
         }>Load Events</button>
       </div>
     `;
   pageContent.innerHTML = content;
 
+  const canvasTitleElem = document.getElementById("canvasTitle") as HTMLElement;
+  const canvasDescriptionElem = document.getElementById(
+    "canvasDescription"
+  ) as HTMLElement;
+  const showMetadata = (title: string, description: string) => {
+    canvasTitleElem.textContent = title || "Unbenannte Zeichenfläche";
+    canvasDescriptionElem.textContent = description;
+    document.title = title || "Zeichenfläche";
+  };
+  showMetadata(userCanvas?.title ?? "", userCanvas?.description ?? "");
+  if (!userCanvas) {
+    fetch(`${__BACKEND_URL__}/api/canvas/${canvas_id}/public`).then(async (resp) => {
+      if (resp.ok) {
+        const canvas = await resp.json();
+        showMetadata(canvas.title, canvas.description);
+      }
+    });
+  }
+
   // Set initial instruction text and moderated status
   const instructionTextElem = document.getElementById(
     instructionTextId
@@ -963,6 +987,15 @@ export function canvasPage(pageContent: HTMLElement) {
   );
   initEventLog(loadEventsButton, eventStreamTextArea, eventBus, canvas, sm);
 
+  eventBus.subscribe(EventTypes.METADATA_CHANGED, (event) => {
+    showMetadata(event.payload.title, event.payload.description);
+    if (userCanvas) {
+      userCanvas.title = event.payload.title;
+      userCanvas.description = event.payload.description;
+      userCanvas.updated_at = event.payload.updated_at;
+    }
+  });
+
   sm.redraw();
   void wtransEvent.connect();
   return () => {
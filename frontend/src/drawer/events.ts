@@ -8,6 +8,7 @@ export enum EventTypes {
   CLEAR_CANVAS_EVENT = "CLEAR_CANVAS_EVENT",
   REDRAW_EVENT = "REDRAW_EVENT",
   RIGHTS_CHANGED = "RIGHTS_CHANGED",
+  METADATA_CHANGED = "METADATA_CHANGED",
   SELECTION_EVENT = "SELECTION_EVENT",
 }
 
@@ -45,6 +46,14 @@ export type DomainEvent = {
         right?: string | null;
       };
     }
+  | {
+      type: EventTypes.METADATA_CHANGED;
+      payload: {
+        title: string;
+        description: string;
+        updated_at: string | null;
+      };
+    }
   | {
       type: EventTypes.ADD_SHAPE_EVENT;
       payload: AddShapePayload;
@@ -1,4 +1,5 @@
-import { navigateTo } from "../router";
+import { fetchUser, refreshSession } from "../auth";
+import { navigateTo, renderPage } from "../router";
 import { DomainEvent, EventBus, EventHandler } from "./events";
 
 export class WSocketEvent {
@@ -96,6 +97,19 @@ export class WSocketEvent {
       );
       return;
     }
+    if (parsed.type === "CANVAS_DELETED") {
+      // Server closes the socket, the canvas is gone for good
+      alert("Diese Zeichenfläche wurde gelöscht.");
+      fetchUser().then(() => navigateTo("/"));
+      return;
+    }
+    if (parsed.type === "TOKEN_EXPIRED") {
+      // Server closes the socket, reconnect with a refreshed token
+      refreshSession().then((ok) =>
+        ok ? renderPage(window.location.pathname) : navigateTo("/login")
+      );
+      return;
+    }
     const events: WSDomainEvent[] = Array.isArray(parsed)
       ? parsed
           .map((e) => {
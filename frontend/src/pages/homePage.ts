@@ -1,4 +1,4 @@
-import { fetchUser, getUser } from "../auth";
+import { apiFetch, fetchUser, getUser } from "../auth";
 import { navigateTo } from "../router";
 
 export function homePage(pageContent: HTMLElement) {
@@ -11,6 +11,16 @@ export function homePage(pageContent: HTMLElement) {
   return () => {};
 }
 
+// Titles and descriptions are user input and must not be rendered as markup
+function escapeHtml(text: string): string {
+  return text
+    .replace(/&/g, "&amp;")
+    .replace(/</g, "&lt;")
+    .replace(/>/g, "&gt;")
+    .replace(/"/g, "&quot;")
+    .replace(/'/g, "&#39;");
+}
+
 export async function home(pageContent: HTMLElement) {
   const user = getUser();
   document.title = "Canvas";
@@ -23,18 +33,34 @@ export async function home(pageContent: HTMLElement) {
     <h2>Select a Canvas</h2>
     <div id="canvas-list" style="display: grid; grid-template-columns: repeat(auto-fit, minmax(260px, 1fr)); gap: 1.2em; padding: 0.5em 0;">
       ${canvasesData
-        .sort((a, b) => a.canvas_id.localeCompare(b.canvas_id))
+        .sort((a, b) =>
+          (a.title || a.canvas_id).localeCompare(b.title || b.canvas_id)
+        )
         .map((canvas) => {
           // Find current user's right for this canvas
           return `<div class="canvas-card" style="background: #fff; border-radius: 10px; box-shadow: 0 2px 10px rgba(79,140,255,0.08); padding: 1.2em 1.5em; width: 100%; min-height: 150px; display: flex; flex-direction: column; align-items: flex-start; gap: 0.7em; border: 1.5px solid #e3edff; box-sizing: border-box;">
               <div style="font-size: 1.13em; font-weight: 600; color: #23272f; display: flex; align-items: center; gap: 0.7em;">
-                ${canvas.canvas_id}
+                ${escapeHtml(canvas.title || "Untitled")}
                 ${
                   canvas.moderated
                     ? '<span style="background:#f3f4f6; color:#4f8cff; border:1px solid #4f8cff; border-radius:5px; padding:0.1em 0.7em; font-size:0.92em; margin-left:0.5em;">Moderated</span>'
                     : ""
                 }
               </div>
+              ${
+                canvas.description
+                  ? `<div style="font-size: 0.95em; color: #4b5563;">${escapeHtml(
+                      canvas.description
+                    )}</div>`
+                  : ""
+              }
+              <div style="font-size: 0.8em; color: #9ca3af;">${canvas.canvas_id}${
+                canvas.updated_at ? ` · Updated ${canvas.updated_at} UTC` : ""
+              }${
+                canvas.forked_from
+                  ? ` · Forked from ${canvas.forked_from} at event ${canvas.forked_at_seq}`
+                  : ""
+              }</div>
               <div style="font-size: 0.98em; color: #4f8cff; font-weight: 500;">Right: ${
                 canvas.right
               }</div>
@@ -42,11 +68,19 @@ export async function home(pageContent: HTMLElement) {
                 <button data-id="${
                   canvas.canvas_id
                 }" class="open-canvas-btn" style="background: #4f8cff; color: #fff; border: none; border-radius: 5px; padding: 0.45em 1.2em; font-size: 1em; font-weight: 500; cursor: pointer; transition: background 0.18s; box-shadow: 0 2px 8px rgba(79,140,255,0.09);">Open</button>
+                <button data-id="${
+                  canvas.canvas_id
+                }" class="fork-canvas-btn" style="background: #fff; color: #4f8cff; border: 1.5px solid #4f8cff; border-radius: 5px; padding: 0.45em 1.2em; font-size: 1em; font-weight: 500; cursor: pointer;">Fork</button>
                 ${
                   canvas.right === "M" || canvas.right === "O"
                     ? `<button class="manage-rights" data-id="${canvas.canvas_id}" style="background: #fff; color: #4f8cff; border: 1.5px solid #4f8cff; border-radius: 5px; padding: 0.45em 1.2em; font-size: 1em; font-weight: 500; cursor: pointer; transition: background 0.18s, color 0.18s;">Manage Rights</button>`
                     : ""
                 }
+                ${
+                  canvas.right === "O"
+                    ? `<button class="delete-canvas-btn" data-id="${canvas.canvas_id}" style="background: #fff; color: #ef4444; border: 1.5px solid #ef4444; border-radius: 5px; padding: 0.45em 1.2em; font-size: 1em; font-weight: 500; cursor: pointer;">Delete</button>`
+                    : ""
+                }
               </div>
             </div>`;
         })
@@ -54,6 +88,7 @@ export async function home(pageContent: HTMLElement) {
     </div>
     <h3 style="margin-top:2em;">Or Create a New Canvas</h3>
     <form id="create-canvas-form" style="margin-bottom:1.2em;">
+      <input type="text" name="title" placeholder="Title" maxlength="100" style="min-width:220px; margin-right:0.5em;" />
       <button type="submit" style="background: #22c55e; color: #fff; border: none; border-radius: 5px; padding: 0.5em 1.5em; font-size: 1em; font-weight: 500; cursor: pointer; transition: background 0.18s; box-shadow: 0 2px 8px rgba(34,197,94,0.09);">Create</button>
     </form>
     <div id="canvas-error" style="color:red;"></div>
@@ -70,6 +105,46 @@ export async function home(pageContent: HTMLElement) {
     });
   });
 
+  // Handle canvas deletion
+  pageContent.querySelectorAll(".delete-canvas-btn").forEach((btn) => {
+    btn.addEventListener("click", async (e) => {
+      const id = (e.target as HTMLButtonElement).dataset.id;
+      if (!confirm("Delete this canvas for all members? This can't be undone.")) {
+        return;
+      }
+      const resp = await apiFetch(`${__BACKEND_URL__}/api/canvas/${id}`, {
+        method: "DELETE",
+        credentials: "include",
+      });
+      if (resp.ok) {
+        await fetchUser();
+        home(pageContent);
+      } else {
+        (pageContent.querySelector("#canvas-error") as HTMLElement).textContent =
+          await resp.text();
+      }
+    });
+  });
+
+  // Handle canvas forking, the fork is opened right away
+  pageContent.querySelectorAll(".fork-canvas-btn").forEach((btn) => {
+    btn.addEventListener("click", async (e) => {
+      const id = (e.target as HTMLButtonElement).dataset.id;
+      const resp = await apiFetch(`${__BACKEND_URL__}/api/canvas/${id}/fork`, {
+        method: "POST",
+        credentials: "include",
+      });
+      if (resp.ok) {
+        const fork = await resp.json();
+        await fetchUser();
+        navigateTo(`/canvas/${fork.id}`);
+      } else {
+        (pageContent.querySelector("#canvas-error") as HTMLElement).textContent =
+          await resp.text();
+      }
+    });
+  });
+
   // Handle canvas creation
   const form = pageContent.querySelector(
     "#create-canvas-form"
@@ -77,10 +152,10 @@ export async function home(pageContent: HTMLElement) {
   form.addEventListener("submit", async (e) => {
     e.preventDefault();
     try {
-      const resp = await fetch(`${__BACKEND_URL__}/api/canvas`, {
+      const resp = await apiFetch(`${__BACKEND_URL__}/api/canvas`, {
         method: "POST",
         headers: { "Content-Type": "application/json" },
-        body: JSON.stringify({}),
+        body: JSON.stringify({ title: (form.elements.namedItem("title") as HTMLInputElement).value }),
         credentials: "include",
       });
       if (resp.ok) {
@@ -118,12 +193,30 @@ export async function home(pageContent: HTMLElement) {
         return;
       }
       modal.innerHTML = `
-          <h4>Manage Rights for Canvas ${id}</h4>
+          <h4>Manage Rights for Canvas ${escapeHtml(canvasData.title || id)}</h4>
+          <form id="metadata-form" style="display:flex; flex-direction:column; gap:0.5em; margin-bottom:1.5em;">
+            <input type="text" name="title" placeholder="Title" maxlength="100" value="${escapeHtml(
+              canvasData.title
+            )}" />
+            <textarea name="description" placeholder="Description" maxlength="1000" rows="3">${escapeHtml(
+              canvasData.description
+            )}</textarea>
+            <button type="submit" style="align-self:flex-start;">Save</button>
+          </form>
           <div style=\"margin-bottom:2em;\">Moderated: <b>${
             canvasData.moderated ? "Yes" : "No"
           }</b> <button id="toggle-moderated" style="margin-left:1em; background:#f3f4f6; border:1px solid #4f8cff; color:#4f8cff; border-radius:5px; padding:0.2em 0.8em; cursor:pointer; font-size:0.95em;">${
         canvasData.moderated ? "Disable" : "Enable"
       }</button></div>
+          ${
+            canvasData.right === "O"
+              ? `<div style="margin-bottom:2em;">Public: <b>${
+                  canvasData.public ? "Yes" : "No"
+                }</b> <button id="toggle-public" style="margin-left:1em; background:#f3f4f6; border:1px solid #4f8cff; color:#4f8cff; border-radius:5px; padding:0.2em 0.8em; cursor:pointer; font-size:0.95em;">${
+                  canvasData.public ? "Unpublish" : "Publish"
+                }</button></div>`
+              : ""
+          }
           <table style="width:100%; border-collapse:collapse;">
             <tr><th>User Email</th><th>Right</th><th>Change</th><th>Remove</th></tr>
             ${canvasData.rights
@@ -176,6 +269,21 @@ export async function home(pageContent: HTMLElement) {
             </select>
             <button type="submit">Add</button>
           </form>
+          <h5>Invite Link</h5>
+          <form id="create-invite-form">
+            <select name="right" style="min-width:80px">
+              <option value="R">R</option>
+              <option value="W">W</option>
+              <option value="V">V</option>
+              <option value="M">M</option>
+              ${canvasData.right === "O" ? '<option value="O">O</option>' : ""}
+            </select>
+            <input type="number" name="max_uses" min="1" placeholder="Max uses" style="width:90px" />
+            <input type="number" name="expires_in_days" min="1" max="30" value="7" style="width:60px" /> days
+            <button type="submit">Create</button>
+          </form>
+          <div id="invite-link" style="word-break:break-all; margin:0.5em 0;"></div>
+          ${canvasData.right === "O" ? '<div id="invite-list" style="margin-bottom:1em;"></div>' : ""}
           <button type="button" id="close-rights-modal">Close</button>
           <div id="rights-error" style="color:red;"></div>
         `;
@@ -184,11 +292,39 @@ export async function home(pageContent: HTMLElement) {
         () => {
           modal.style.display = "none";
         };
+      // Title and description
+      (
+        modal.querySelector("#metadata-form") as HTMLFormElement
+      ).addEventListener("submit", async (ev) => {
+        ev.preventDefault();
+        const form = ev.target as HTMLFormElement;
+        try {
+          const resp = await apiFetch(`${__BACKEND_URL__}/api/canvas/${id}`, {
+            method: "PATCH",
+            headers: { "Content-Type": "application/json" },
+            body: JSON.stringify({
+              title: (form.elements.namedItem("title") as HTMLInputElement).value,
+              description: form.description.value,
+            }),
+            credentials: "include",
+          });
+          if (!resp.ok) {
+            (modal.querySelector("#rights-error") as HTMLElement).textContent =
+              await resp.text();
+          } else {
+            await import("../auth").then((m) => m.fetchUser());
+            home(pageContent);
+          }
+        } catch (err) {
+          (modal.querySelector("#rights-error") as HTMLElement).textContent =
+            "Failed to update canvas.";
+        }
+      });
       // Moderated toggle
       (modal.querySelector("#toggle-moderated") as HTMLElement).onclick =
         async () => {
           try {
-            const resp = await fetch(
+            const resp = await apiFetch(
               `${__BACKEND_URL__}/api/canvas/${id}/moderated`,
               {
                 method: "POST",
@@ -210,6 +346,25 @@ export async function home(pageContent: HTMLElement) {
               "Failed to update moderated status";
           }
         };
+      // Public visibility, owners only
+      const togglePublic = modal.querySelector("#toggle-public") as HTMLElement | null;
+      if (togglePublic) {
+        togglePublic.onclick = async () => {
+          const resp = await apiFetch(`${__BACKEND_URL__}/api/canvas/${id}/public`, {
+            method: "POST",
+            headers: { "Content-Type": "application/json" },
+            body: JSON.stringify({ public: !canvasData.public }),
+            credentials: "include",
+          });
+          if (!resp.ok) {
+            (modal.querySelector("#rights-error") as HTMLElement).textContent =
+              await resp.text();
+          } else {
+            await fetchUser();
+            home(pageContent);
+          }
+        };
+      }
       modal.querySelectorAll(".change-right-form").forEach((form) => {
         form.addEventListener("submit", async (ev) => {
           ev.preventDefault();
@@ -237,6 +392,68 @@ export async function home(pageContent: HTMLElement) {
           });
         });
       });
+      // Invite links, listed and revocable by owners only
+      const inviteList = modal.querySelector("#invite-list") as HTMLElement | null;
+      async function loadInvites() {
+        const resp = await apiFetch(`${__BACKEND_URL__}/api/canvas/${id}/invites`, {
+          credentials: "include",
+        });
+        if (!resp.ok) return;
+        const invites: {
+          id: string;
+          right: string;
+          expires_at: string;
+          max_uses: number | null;
+          uses: number;
+        }[] = await resp.json();
+        inviteList.innerHTML = invites
+          .map(
+            (invite) => `<div>${invite.right} · ${invite.uses}/${
+              invite.max_uses ?? "∞"
+            } uses · expires ${invite.expires_at} UTC
+              <button class="revoke-invite-btn" data-id="${invite.id}">Revoke</button></div>`
+          )
+          .join("");
+        inviteList.querySelectorAll(".revoke-invite-btn").forEach((btn) => {
+          btn.addEventListener("click", async () => {
+            await apiFetch(
+              `${__BACKEND_URL__}/api/canvas/${id}/invites/${
+                (btn as HTMLButtonElement).dataset.id
+              }`,
+              { method: "DELETE", credentials: "include" }
+            );
+            loadInvites();
+          });
+        });
+      }
+      if (inviteList) loadInvites();
+      (
+        modal.querySelector("#create-invite-form") as HTMLFormElement
+      ).addEventListener("submit", async (ev) => {
+        ev.preventDefault();
+        const form = ev.target as HTMLFormElement;
+        const maxUses = form.max_uses.value;
+        const resp = await apiFetch(`${__BACKEND_URL__}/api/canvas/${id}/invites`, {
+          method: "POST",
+          headers: { "Content-Type": "application/json" },
+          body: JSON.stringify({
+            right: form.right.value,
+            max_uses: maxUses ? Number(maxUses) : null,
+            expires_in_days: Number(form.expires_in_days.value),
+          }),
+          credentials: "include",
+        });
+        if (!resp.ok) {
+          (modal.querySelector("#rights-error") as HTMLElement).textContent =
+            await resp.text();
+          return;
+        }
+        const { token } = await resp.json();
+        // The token is only shown once
+        (modal.querySelector("#invite-link") as HTMLElement).textContent =
+          `${window.location.origin}/invite/${token}`;
+        if (inviteList) loadInvites();
+      });
       // Add right form
       (
         modal.querySelector("#add-right-form") as HTMLFormElement
@@ -246,7 +463,7 @@ export async function home(pageContent: HTMLElement) {
         const email = form.email.value;
         const right = form.right.value;
         try {
-          const resp = await fetch(
+          const resp = await apiFetch(
             `${__BACKEND_URL__}/api/canvas/${id}/right`,
             {
               method: "POST",
@@ -291,7 +508,7 @@ export async function home(pageContent: HTMLElement) {
       ? "Failed to remove right."
       : "Failed to change rights.";
     try {
-      const resp = await fetch(`${__BACKEND_URL__}/api/canvas/${id}/right`, {
+      const resp = await apiFetch(`${__BACKEND_URL__}/api/canvas/${id}/right`, {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({ email, right }),
@@ -0,0 +1,42 @@
+import { apiFetch, fetchUser, getUser } from "../auth";
+import { navigateTo } from "../router";
+
+// Accepts the invite in the link and opens its canvas
+export function invitePage(pageContent: HTMLElement) {
+  document.title = "Invite";
+  const token = window.location.pathname.split("/").at(-1);
+  if (!getUser()) {
+    pageContent.innerHTML = `
+      <h2>Canvas Invite</h2>
+      <p>Please <a href="/login" class="nav-link" data-route="login">log in</a> and open this link again to join the canvas.</p>
+    `;
+    return () => {};
+  }
+  pageContent.innerHTML = "<h2>Canvas Invite</h2><p>Joining canvas...</p>";
+  acceptInvite(pageContent, token);
+  return () => {};
+}
+
+async function acceptInvite(pageContent: HTMLElement, token: string) {
+  const resp = await apiFetch(
+    `${__BACKEND_URL__}/api/canvas/invite/${encodeURIComponent(token)}/accept`,
+    {
+      method: "POST",
+      credentials: "include",
+    }
+  );
+  if (!resp.ok) {
+    pageContent.innerHTML = `
+      <h2>Canvas Invite</h2>
+      <p style="color:red;">${
+        resp.status === 404
+          ? "This invite link is invalid, expired or used up."
+          : "You can't accept this invite. Please confirm your email address first."
+      }</p>
+    `;
+    return;
+  }
+  const { canvas_id } = await resp.json();
+  await fetchUser();
+  navigateTo(`/canvas/${canvas_id}`);
+}
@@ -1,4 +1,4 @@
-import { fetchUser, getUser } from "../auth";
+import { csrfFetch, fetchUser, getUser } from "../auth";
 import { navigateTo } from "../router";
 
 export function loginPage(pageContent: HTMLElement) {
@@ -20,10 +20,22 @@ export function loginPage(pageContent: HTMLElement) {
       </div>
       <button type="submit">Login</button>
     </form>
+    <p><a href="${__BACKEND_URL__}/api/auth/oidc/login">Mit Firmenkonto anmelden</a></p>
+    <p><a href="reset-password" class="nav-link" data-route="reset-password">Passwort vergessen?</a></p>
     <p>Noch keinen Account? <a href="register" class="nav-link" data-route="register">Registrieren</a></p>
     <div id="loginError" style="color:red;"></div>
   `;
   pageContent.innerHTML = content;
+  // An OpenID login of an account with TOTP comes back with a pending token
+  const mfaToken = new URLSearchParams(window.location.hash.slice(1)).get("mfa_token");
+  if (mfaToken) {
+    history.replaceState(null, "", window.location.pathname);
+    completeMfa(mfaToken).catch((err) => {
+      document.getElementById("loginError").textContent =
+        "Netzwerkfehler beim Login.";
+      console.error("Login Fehler:", err);
+    });
+  }
   const form = document.getElementById("loginForm") as HTMLFormElement | null;
   form?.addEventListener("submit", async (e) => {
     e.preventDefault();
@@ -32,12 +44,17 @@ export function loginPage(pageContent: HTMLElement) {
     const email = data.get("email");
     const password = data.get("password");
     try {
-      const res = await fetch(`${__BACKEND_URL__}/api/auth/login`, {
+      const res = await csrfFetch(`${__BACKEND_URL__}/api/auth/login`, {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({ email, password }),
         credentials: "include",
       });
+      const body = res.ok ? await res.json() : null;
+      if (body?.mfa_required) {
+        await completeMfa(body.mfa_token);
+        return;
+      }
       if (res.ok) {
         await fetchUser();
         navigateTo("");
@@ -54,3 +71,25 @@ export function loginPage(pageContent: HTMLElement) {
   });
   return () => {};
 }
+
+// Second step: exchanges the pending token and a TOTP or recovery code for a session
+async function completeMfa(mfaToken: string) {
+  const code = window.prompt("Code aus der Authenticator-App oder Wiederherstellungscode:");
+  const totpRes = await csrfFetch(`${__BACKEND_URL__}/api/auth/login/totp`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify(
+      /^\d{6}$/.test(code?.trim() ?? "")
+        ? { mfa_token: mfaToken, code: code.trim() }
+        : { mfa_token: mfaToken, recovery_code: code }
+    ),
+    credentials: "include",
+  });
+  if (!totpRes.ok) {
+    document.getElementById("loginError").textContent =
+      "Login fehlgeschlagen. " + (await totpRes.text());
+    return;
+  }
+  await fetchUser();
+  navigateTo("");
+}
@@ -1,3 +1,4 @@
+import { csrfFetch } from "../auth";
 import { navigateTo } from "../router";
 
 export function registerPage(pageContent: HTMLElement) {
@@ -34,7 +35,7 @@ export function registerPage(pageContent: HTMLElement) {
     const password = data.get("password");
     const display_name = data.get("display_name");
     try {
-      const res = await fetch(`${__BACKEND_URL__}/api/auth/register`, {
+      const res = await csrfFetch(`${__BACKEND_URL__}/api/auth/register`, {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({ email, password, display_name }),
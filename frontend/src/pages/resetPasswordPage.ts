@@ -0,0 +1,110 @@
+import { csrfFetch } from "../auth";
+import { navigateTo } from "../router";
+
+// Target of the link in the password reset mail, without a token it requests a new mail
+export function resetPasswordPage(pageContent: HTMLElement) {
+  document.title = "Passwort zurücksetzen";
+  const token = new URLSearchParams(window.location.search).get("token");
+  if (!token) {
+    renderForgotForm(pageContent);
+  } else {
+    renderResetForm(pageContent, token);
+  }
+  return () => {};
+}
+
+function renderForgotForm(pageContent: HTMLElement) {
+  pageContent.innerHTML = `
+    <h1>Passwort vergessen</h1>
+    <form id="forgotForm">
+      <div class="form-row">
+        <label class="form-label">Email:</label>
+        <input class="form-input" type="email" name="email" required>
+      </div>
+      <button type="submit">Link anfordern</button>
+    </form>
+    <div id="resetMessage"></div>
+  `;
+  const form = document.getElementById("forgotForm") as HTMLFormElement;
+  const message = document.getElementById("resetMessage");
+  form.addEventListener("submit", async (e) => {
+    e.preventDefault();
+    const email = new FormData(form).get("email");
+    try {
+      const res = await csrfFetch(`${__BACKEND_URL__}/api/auth/password/forgot`, {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ email }),
+        credentials: "include",
+      });
+      if (res.status === 429) {
+        message.style.color = "red";
+        message.textContent = "Zu viele Anfragen. Bitte versuche es später erneut.";
+        return;
+      }
+      // The backend answers the same way for unknown addresses
+      message.style.color = "";
+      message.textContent =
+        "Falls ein Account mit dieser Adresse existiert, wurde ein Link verschickt.";
+    } catch (err) {
+      message.style.color = "red";
+      message.textContent = "Netzwerkfehler.";
+      console.error("Passwort vergessen Fehler:", err);
+    }
+  });
+}
+
+function renderResetForm(pageContent: HTMLElement, token: string) {
+  pageContent.innerHTML = `
+    <h1>Neues Passwort</h1>
+    <form id="resetForm">
+      <div class="form-row">
+        <label class="form-label">Neues Passwort:</label>
+        <input class="form-input" type="password" name="new_password" required>
+      </div>
+      <div class="form-row">
+        <label class="form-label">Wiederholen:</label>
+        <input class="form-input" type="password" name="confirm" required>
+      </div>
+      <button type="submit">Passwort setzen</button>
+    </form>
+    <div id="resetMessage" style="color:red;"></div>
+  `;
+  const form = document.getElementById("resetForm") as HTMLFormElement;
+  const message = document.getElementById("resetMessage");
+  form.addEventListener("submit", async (e) => {
+    e.preventDefault();
+    const data = new FormData(form);
+    const new_password = data.get("new_password");
+    if (new_password !== data.get("confirm")) {
+      message.textContent = "Die Passwörter stimmen nicht überein.";
+      return;
+    }
+    try {
+      const res = await csrfFetch(`${__BACKEND_URL__}/api/auth/password/reset`, {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ token, new_password }),
+        credentials: "include",
+      });
+      if (res.ok) {
+        // All sessions were revoked, the new password has to be used right away
+        navigateTo("/login");
+        return;
+      }
+      const body = await res.json().catch(() => null);
+      if (body?.code === "validation_failed") {
+        message.textContent =
+          "Passwort: " + (body.fields?.new_password ?? []).join(", ");
+      } else if (body?.code === "invalid_token") {
+        message.textContent =
+          "Der Link ist ungültig oder abgelaufen. Bitte fordere einen neuen an.";
+      } else {
+        message.textContent = "Zurücksetzen fehlgeschlagen.";
+      }
+    } catch (err) {
+      message.textContent = "Netzwerkfehler.";
+      console.error("Passwort zurücksetzen Fehler:", err);
+    }
+  });
+}
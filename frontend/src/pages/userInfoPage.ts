@@ -1,5 +1,5 @@
 // User info page for viewing and editing user details
-import { getUser } from "../auth";
+import { apiFetch, getUser, logout } from "../auth";
 import { navigateTo } from "../router";
 
 export async function userInfoPage(pageContent: HTMLElement) {
@@ -18,6 +18,17 @@ export async function userInfoPage(pageContent: HTMLElement) {
       <button type="submit">Aktualisieren</button>
       <span id="user-info-msg"></span>
     </form>
+    <p><a href="${__BACKEND_URL__}/api/user/${user.id}/export" download>Meine Daten exportieren</a></p>
+    <h3>Aktive Sitzungen</h3>
+    <ul id="session-list"></ul>
+    <button id="revoke-other-sessions">Überall sonst abmelden</button>
+    <h3>Account löschen</h3>
+    <form id="delete-account-form">
+      <label>Passwort: <input type="password" name="password" /></label><br />
+      <button type="submit">Account löschen</button>
+      <button type="button" id="delete-confirmation">Ohne Passwort per Mail bestätigen</button>
+      <span id="delete-account-msg"></span>
+    </form>
   `;
   const form = document.getElementById("user-info-form") as HTMLFormElement;
   const msg = document.getElementById("user-info-msg");
@@ -27,21 +38,110 @@ export async function userInfoPage(pageContent: HTMLElement) {
     const email = formData.get("email");
     const display_name = formData.get("display_name");
     try {
-      const resp = await fetch(`${__BACKEND_URL__}/api/user/${user.id}`, {
+      const resp = await apiFetch(`${__BACKEND_URL__}/api/user/${user.id}`, {
         method: "PATCH",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({ email, display_name }),
         credentials: "include",
       });
-      user.email = email as string;
-      user.display_name = display_name as string;
       if (!resp.ok) {
         throw new Error("Update fehlgeschlagen");
       }
+      const updated = await resp.json();
+      user.email = updated.email;
+      user.display_name = updated.display_name;
+      if (updated.pending_email) {
+        // The new address only applies after confirming the mailed link
+        msg.textContent = `Bestätigungslink an ${updated.pending_email} gesendet.`;
+        return;
+      }
       navigateTo("/");
     } catch (err) {
       msg.textContent = "Fehler beim Aktualisieren.";
     }
   };
+  const sessionList = document.getElementById("session-list");
+  const loadSessions = async () => {
+    const resp = await apiFetch(`${__BACKEND_URL__}/api/auth/sessions`);
+    if (!resp.ok) return;
+    const sessions = await resp.json();
+    sessionList.innerHTML = "";
+    for (const session of sessions) {
+      const item = document.createElement("li");
+      item.textContent =
+        `${session.user_agent ?? "Unbekanntes Gerät"} (${session.ip ?? "?"}), ` +
+        `zuletzt aktiv ${session.last_seen_at}` +
+        (session.websocket ? ", Canvas geöffnet" : "") +
+        (session.current ? " – diese Sitzung" : "");
+      if (!session.current) {
+        const button = document.createElement("button");
+        button.textContent = "Abmelden";
+        button.onclick = async () => {
+          await apiFetch(`${__BACKEND_URL__}/api/auth/sessions/${session.id}`, {
+            method: "DELETE",
+          });
+          loadSessions();
+        };
+        item.append(" ", button);
+      }
+      sessionList.append(item);
+    }
+  };
+  document.getElementById("revoke-other-sessions").onclick = async () => {
+    await apiFetch(`${__BACKEND_URL__}/api/auth/sessions`, { method: "DELETE" });
+    loadSessions();
+  };
+  loadSessions();
+  const deleteForm = document.getElementById(
+    "delete-account-form"
+  ) as HTMLFormElement;
+  const deleteMsg = document.getElementById("delete-account-msg");
+  // Set when the page was opened from the link in the confirmation mail
+  const confirmation = new URLSearchParams(window.location.search).get(
+    "confirm_delete"
+  );
+  if (confirmation) {
+    deleteMsg.textContent =
+      "Bestätigung erhalten, \"Account löschen\" löscht den Account.";
+  }
+  document.getElementById("delete-confirmation").onclick = async () => {
+    const resp = await apiFetch(
+      `${__BACKEND_URL__}/api/user/${user.id}/delete-confirmation`,
+      { method: "POST" }
+    );
+    deleteMsg.textContent = resp.ok
+      ? "Ein Bestätigungslink wurde an deine Adresse geschickt."
+      : "Anfordern fehlgeschlagen.";
+  };
+  deleteForm.onsubmit = async (e) => {
+    e.preventDefault();
+    const password = new FormData(deleteForm).get("password") || undefined;
+    const deleteAccount = (owned_canvases?: string) =>
+      apiFetch(`${__BACKEND_URL__}/api/user/${user.id}`, {
+        method: "DELETE",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({
+          password,
+          confirmation: password ? undefined : confirmation,
+          owned_canvases,
+        }),
+      });
+    let resp = await deleteAccount();
+    if (resp.status === 409) {
+      // Canvases only this user owns still have other members
+      const { canvases } = await resp.json();
+      const transfer = window.confirm(
+        `Du bist alleiniger Besitzer von ${canvases.length} geteilten Canvas. ` +
+          "OK überträgt sie an ein anderes Mitglied, Abbrechen löscht sie."
+      );
+      resp = await deleteAccount(transfer ? "transfer" : "delete");
+    }
+    if (!resp.ok) {
+      deleteMsg.textContent = "Löschen fehlgeschlagen.";
+      return;
+    }
+    await logout();
+    navigateTo("/");
+  };
   return () => {};
 }
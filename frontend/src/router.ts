@@ -1,9 +1,11 @@
 // Routing logic
 import { renderNavBar } from "./navbar";
 import { homePage } from "./pages/homePage";
+import { invitePage } from "./pages/invitePage";
 import { canvasPage as canvasPage } from "./drawer/drawer";
 import { loginPage } from "./pages/loginPage";
 import { registerPage } from "./pages/registerPage";
+import { resetPasswordPage } from "./pages/resetPasswordPage";
 import { userInfoPage } from "./pages/userInfoPage";
 
 let afterLeave = () => {};
@@ -19,8 +21,10 @@ export const routes: Record<
 > = {
   "": homePage,
   canvas: canvasPage,
+  invite: invitePage,
   login: loginPage,
   register: registerPage,
+  "reset-password": resetPasswordPage,
   user: userInfoPage,
   404: notFound,
 };